@@ -3,8 +3,10 @@ use bytes::{Buf, BufMut, BytesMut};
 
 use miltr_common::decoding::ServerCommand;
 use miltr_common::encoding::{ClientMessage, Writable};
+#[cfg(feature = "metrics")]
+use miltr_common::metrics::{self, Side};
 use miltr_common::ProtocolError;
-use miltr_utils::trace;
+use miltr_utils::{metric, trace};
 
 // The `MilterCodec` is responsible for decoding from and encoding to bits on
 /// the wire from structs provided by this crate.
@@ -19,13 +21,14 @@ impl MilterCodec {
     pub(crate) fn new(max_buffer_size: usize) -> Self {
         Self { max_buffer_size }
     }
-}
 
-impl Decoder for MilterCodec {
-    type Item = ServerCommand;
-    type Error = ProtocolError;
+    /// The maximum size of a single item sent
+    pub(crate) fn max_buffer_size(&self) -> usize {
+        self.max_buffer_size
+    }
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+    /// Decode a single frame from `src`, if it arrived completely
+    fn decode_frame(&self, src: &mut BytesMut) -> Result<Option<ServerCommand>, ProtocolError> {
         if src.len() < 4 {
             // Not enough data to read length marker.
 
@@ -56,16 +59,13 @@ impl Decoder for MilterCodec {
         parse_buf.advance(4);
 
         trace!(length = parse_buf.len(), "Read bytes from the network");
+        metric!(metrics::bytes_received(Side::Client, 4 + length));
 
         Ok(Some(ServerCommand::parse(parse_buf)?))
     }
-}
 
-impl Encoder for MilterCodec {
-    type Item<'i> = &'i ClientMessage;
-    type Error = ProtocolError;
-
-    fn encode(&mut self, item: &ClientMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+    /// Encode `item` as a single frame into `dst`
+    fn encode_frame(&self, item: &ClientMessage, dst: &mut BytesMut) -> Result<(), ProtocolError> {
         // Don't send a string if it is longer than the other end will
         // accept or  larger than we will be able to compute.
         let item_len = item.len();
@@ -90,11 +90,41 @@ impl Encoder for MilterCodec {
         item.write(dst);
 
         trace!(length = dst.len(), "Wrote bytes to the network");
+        metric!(metrics::bytes_sent(Side::Client, 4 + packet_len));
 
         Ok(())
     }
 }
 
+impl Decoder for MilterCodec {
+    type Item = ServerCommand;
+    type Error = ProtocolError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let result = self.decode_frame(src);
+        metric!(match &result {
+            Ok(Some(command)) => metrics::server_command(Side::Client, command),
+            Ok(None) => {}
+            Err(error) => metrics::codec_error(Side::Client, error),
+        });
+        result
+    }
+}
+
+impl Encoder for MilterCodec {
+    type Item<'i> = &'i ClientMessage;
+    type Error = ProtocolError;
+
+    fn encode(&mut self, item: &ClientMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let result = self.encode_frame(item, dst);
+        metric!(match &result {
+            Ok(()) => metrics::client_message(Side::Client, item),
+            Err(error) => metrics::codec_error(Side::Client, error),
+        });
+        result
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
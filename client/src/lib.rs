@@ -1,14 +1,22 @@
 #![doc = include_str!("../Readme.md")]
 
 mod codec;
+mod message;
+mod mutation;
+#[cfg(feature = "connect")]
+mod socket;
+mod timeout;
+mod verdict;
 
 #[cfg(feature = "_fuzzing")]
 pub mod fuzzing;
 
-use std::{ops::Deref, sync::Arc};
+use std::{collections::HashMap, ops::Deref, sync::Arc, time::Duration};
 
 use asynchronous_codec::Framed;
 use futures::{AsyncRead, AsyncWrite, SinkExt, StreamExt};
+#[cfg(feature = "metrics")]
+use miltr_common::metrics::{self, Side};
 use miltr_utils::debug;
 use paste::paste;
 use thiserror::Error;
@@ -16,23 +24,31 @@ use thiserror::Error;
 use tracing::{instrument, Level};
 
 use miltr_common::{
-    actions::{Abort, Action, Quit},
+    actions::{Abort, Action, Quit, QuitNc},
     commands::{
-        Body, Command, Connect, Data, EndOfBody, EndOfHeader, Header, Helo, Mail, Recipient,
+        Body, Command, Connect, Data, EndOfBody, EndOfHeader, Header, Helo, Macro, Mail, Recipient,
         Unknown,
     },
     decoding::ServerCommand,
+    encoding::Writable,
     modifications::{ModificationAction, ModificationResponse},
-    optneg::{CompatibilityError, OptNeg},
+    optneg::{CompatibilityError, MacroStage, OptNeg},
     ProtocolError,
 };
 
 use self::codec::MilterCodec;
+pub use self::message::{Envelope, MessageOutcome, Stage};
+pub use self::mutation::Message;
+#[cfg(feature = "connect")]
+pub use self::socket::Stream;
+use self::timeout::{timeout, Timeouts};
+pub use self::verdict::Verdict;
 
 /// A milter client using some options and a codec to talk to a milter server
 pub struct Client {
     options: Arc<OptNeg>,
     codec: MilterCodec,
+    timeouts: Timeouts,
 }
 
 /// A single milter connection
@@ -52,9 +68,20 @@ pub struct Client {
 /// - [`Connection::body`] (multiple)
 /// - [`Connection::end_of_body`]
 ///
+/// To process more mails on the same connection, either call
+/// [`Connection::reset`] to start the next mail of the same smtp session or
+/// [`Connection::quit_nc`] to start a new smtp session.
+///
 /// Be careful about the ordering of these commands, milter implementations
 /// are designed to expect them in order they appear in the SMTP protocol.
 ///
+/// # Verdicts
+///
+/// Each command returns the [`Verdict`] of the server, which the MTA should
+/// act on, e.g. stop processing a mail on [`Verdict::Reject`]. After a
+/// [`Verdict::Skip`] answering a body chunk, remaining body chunks are not
+/// sent anymore and [`Connection::body`] returns [`Verdict::Skip`] directly.
+///
 /// # Protocol from `OptNeg`
 ///
 /// Depending on what was set by client and server during option negotiation
@@ -63,19 +90,33 @@ pub struct Client {
 ///
 /// Assuming [`Protocol::NO_HELO`](miltr_common::optneg::Protocol::NO_HELO) is
 /// set during option negotiation, calling [`Connection::helo`] short-circuits
-/// to `return Ok(())`.
+/// to `return Ok(Verdict::Continue)`.
 ///
 /// If [`Protocol::NR_HELO`](miltr_common::optneg::Protocol::NR_HELO) is set,
 /// calling [`Connection::helo`] does not wait for an answer from the milter
-/// server, it immediately `return Ok(())` after sending the command.
+/// server, it immediately `return Ok(Verdict::Continue)` after sending the
+/// command.
 ///
 /// Commands behave differently here, see the implementations for
 /// [`Protocol::skip_send`](miltr_common::optneg::Protocol::should_skip_send) and
 /// [`Protocol::skip_response`](miltr_common::optneg::Protocol::should_skip_response)
 /// for details.
+///
+/// # Macros
+///
+/// Macros can be sent explicitly using [`Connection::macro_`]. Alternatively,
+/// values set by [`Connection::set_macro`] are sent automatically before each
+/// command, if the server requested them for that stage during option
+/// negotiation.
 pub struct Connection<RW: AsyncRead + AsyncWrite + Unpin> {
     framed: Framed<RW, MilterCodec>,
     options: OptNeg,
+    macros: HashMap<String, String>,
+    body_skipped: bool,
+    timeouts: Timeouts,
+    /// Counts the session as closed once the connection is dropped
+    #[cfg(feature = "metrics")]
+    _session: metrics::Session,
 }
 
 impl Client {
@@ -88,9 +129,46 @@ impl Client {
         Self {
             options: Arc::new(options),
             codec,
+            timeouts: Timeouts::default(),
         }
     }
 
+    /// Limit option negotiation in [`Client::connect_via`] to `timeout`.
+    ///
+    /// [`Client::connect`] limits opening the socket to it as well.
+    ///
+    /// Like postfix' `milter_connect_timeout`, which defaults to 30s.
+    /// No timeout is applied by default.
+    #[must_use]
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.connect = Some(timeout);
+        self
+    }
+
+    /// Limit sending a smtp command to the server and receiving it's
+    /// response to `timeout`.
+    ///
+    /// Like postfix' `milter_command_timeout`, which defaults to 30s.
+    /// No timeout is applied by default.
+    #[must_use]
+    pub fn with_command_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.command = Some(timeout);
+        self
+    }
+
+    /// Limit sending message content (headers, body and end of body) to the
+    /// server and receiving it's response to `timeout`.
+    ///
+    /// Every progress packet of the server restarts this timeout while
+    /// waiting for the end of body response. Like postfix'
+    /// `milter_content_timeout`, which defaults to 300s. No timeout is
+    /// applied by default.
+    #[must_use]
+    pub fn with_content_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.content = Some(timeout);
+        self
+    }
+
     /// Option negotiate with the server
     ///
     /// The steps are:
@@ -129,12 +207,42 @@ impl Client {
     ) -> Result<Connection<RW>, ResponseError> {
         let codec = self.codec.clone();
         let mut framed = Framed::new(connection, codec);
-        let options = self.recv_option_negotiation(&mut framed).await?;
-
-        let connection = Connection { framed, options };
+        let options = timeout(
+            self.timeouts.connect,
+            self.recv_option_negotiation(&mut framed),
+        )
+        .await??;
+
+        let connection = Connection {
+            framed,
+            options,
+            macros: HashMap::new(),
+            body_skipped: false,
+            timeouts: self.timeouts,
+            #[cfg(feature = "metrics")]
+            _session: metrics::Session::open(Side::Client),
+        };
 
         Ok(connection)
     }
+
+    /// Connect to the milter listening on `spec`, e.g. `inet:10025@127.0.0.1`,
+    /// and handle the milter connection via it.
+    ///
+    /// # Errors
+    /// This fails if connecting to the socket fails or see
+    /// [`Client::connect_via`].
+    #[cfg(feature = "connect")]
+    pub async fn connect(
+        &self,
+        spec: &miltr_common::socket::SocketSpec,
+    ) -> Result<Connection<Stream>, ResponseError> {
+        let stream = timeout(self.timeouts.connect, Stream::connect(spec))
+            .await?
+            .map_err(ResponseError::Connect)?;
+
+        self.connect_via(stream).await
+    }
 }
 
 macro_rules! command {
@@ -144,7 +252,7 @@ macro_rules! command {
     ) => {
         paste! {
             $(#[$outer])*
-            pub async fn [<$variant:snake>]<C: Into<[<$variant:camel>]>>(&mut self, command: C) -> Result<(), ResponseError> {
+            pub async fn [<$variant:snake>]<C: Into<[<$variant:camel>]>>(&mut self, command: C) -> Result<Verdict, ResponseError> {
                 let command_intoed: [<$variant:camel>] = command.into();
                 let command: Command = command_intoed.into();
 
@@ -158,7 +266,7 @@ macro_rules! command {
     ) => {
         paste! {
             $(#[$outer])*
-            pub async fn [<$variant:snake>](&mut self) -> Result<(), ResponseError> {
+            pub async fn [<$variant:snake>](&mut self) -> Result<Verdict, ResponseError> {
                 let command: Command = [<$variant:camel>].into();
 
                 self.send_command(command).await
@@ -168,12 +276,48 @@ macro_rules! command {
 }
 
 impl<RW: AsyncRead + AsyncWrite + Unpin> Connection<RW> {
+    /// The options negotiated with the milter server.
+    ///
+    /// This includes the [`MacroStages`](miltr_common::optneg::MacroStages)
+    /// the server requested.
+    #[must_use]
+    pub fn options(&self) -> &OptNeg {
+        &self.options
+    }
+
+    /// Send macros for the command identified by `Macro.code`.
+    ///
+    /// The server does not answer macros.
+    ///
+    /// # Errors
+    /// Errors on io or codec Errors
+    #[doc(alias = "SMFIC_MACRO")]
+    pub async fn macro_(&mut self, macro_: Macro) -> Result<(), ResponseError> {
+        self.send_command(macro_.into()).await?;
+
+        Ok(())
+    }
+
+    /// Set the `value` of macro `name`, e.g. `{auth_authen}` or `i`.
+    ///
+    /// Before sending a command, the values of all macros the server
+    /// requested for this stage are sent along automatically. `name` has to
+    /// match the name requested by the server.
+    pub fn set_macro<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) {
+        self.macros.insert(name.into(), value.into());
+    }
+
+    /// Forget the value of macro `name`
+    pub fn remove_macro(&mut self, name: &str) {
+        self.macros.remove(name);
+    }
+
     command!(
         /// Send connect information.
         ///
         ///
         /// # Errors
-        /// Errors on any response from the milter server that is not Continue
+        /// Errors on io or codec errors or a response that is not a verdict
         (into) Connect
     );
 
@@ -182,7 +326,7 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> Connection<RW> {
         ///
         ///
         /// # Errors
-        /// Errors on any response from the milter server that is not Continue
+        /// Errors on io or codec errors or a response that is not a verdict
         (into) Helo
     );
 
@@ -191,7 +335,7 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> Connection<RW> {
         ///
         ///
         /// # Errors
-        /// Errors on any response from the milter server that is not Continue
+        /// Errors on io or codec errors or a response that is not a verdict
         (into) Mail
     );
 
@@ -200,7 +344,7 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> Connection<RW> {
         ///
         ///
         /// # Errors
-        /// Errors on any response from the milter server that is not Continue
+        /// Errors on io or codec errors or a response that is not a verdict
         (into) Recipient
     );
 
@@ -209,7 +353,7 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> Connection<RW> {
         ///
         ///
         /// # Errors
-        /// Errors on any response from the milter server that is not Continue
+        /// Errors on io or codec errors or a response that is not a verdict
         (new) Data
     );
 
@@ -218,7 +362,7 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> Connection<RW> {
         ///
         ///
         /// # Errors
-        /// Errors on any response from the milter server that is not Continue
+        /// Errors on io or codec errors or a response that is not a verdict
         (into) Header
     );
 
@@ -227,7 +371,7 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> Connection<RW> {
         ///
         ///
         /// # Errors
-        /// Errors on any response from the milter server that is not Continue
+        /// Errors on io or codec errors or a response that is not a verdict
         (new) EndOfHeader
     );
 
@@ -236,7 +380,7 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> Connection<RW> {
         ///
         ///
         /// # Errors
-        /// Errors on any response from the milter server that is not Continue
+        /// Errors on io or codec errors or a response that is not a verdict
         (into) Body
     );
 
@@ -250,17 +394,33 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> Connection<RW> {
 
     /// Indicate all body parts have been sent
     ///
+    /// Progress packets sent by the server while it is still processing
+    /// are accepted and skipped until the final answer arrives.
+    ///
     /// # Errors
     /// Errors on any response from the milter server that is not Continue
     pub async fn end_of_body(&mut self) -> Result<ModificationResponse, ResponseError> {
         // First, send the eob command
+        self.body_skipped = false;
+        let limit = self.timeouts.content;
         let command: Command = EndOfBody.into();
-        self.framed.send(&command.into()).await?;
+        timeout(limit, async {
+            self.send_requested_macros(&command).await?;
+            self.framed.send(&command.into()).await?;
+            Ok::<_, ResponseError>(())
+        })
+        .await??;
 
         let mut modification_response_builder = ModificationResponse::builder();
         loop {
-            // Receive a response from the server
-            let answer = self.receive_answer().await?;
+            // Receive a response from the server, each restarting the timeout
+            let answer = timeout(limit, self.receive_answer()).await??;
+
+            // The server is still busy, keep on waiting
+            if let ServerCommand::Progress(_) = answer {
+                debug!("Received progress");
+                continue;
+            }
 
             // Convert it to a command type
             let command: CommandType = answer.try_into()?;
@@ -296,12 +456,20 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> Connection<RW> {
         Ok(())
     }
 
-    /// Ask to re-use this connection for a new mail
+    /// Finish the current smtp session, but keep this connection open
+    /// for a new one.
+    ///
+    /// The negotiated options stay valid, the next session starts with
+    /// [`Connection::connect`].
     ///
     /// # Errors
-    /// Errors on any response from the milter server that is not Continue
-    pub fn quit_nc(self) -> Result<(), ProtocolError> {
-        todo!("Quit_NC Not yet implemented")
+    /// Errors on io or codec Errors
+    #[doc(alias = "SMFIC_QUIT_NC")]
+    pub async fn quit_nc(&mut self) -> Result<(), ProtocolError> {
+        self.body_skipped = false;
+        self.framed.send(&Action::from(QuitNc).into()).await?;
+
+        Ok(())
     }
 
     /// Abort processing for the current mail
@@ -309,6 +477,19 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> Connection<RW> {
     /// # Errors
     /// Errors on io or codec Errors
     pub async fn abort(mut self) -> Result<(), ProtocolError> {
+        self.reset().await
+    }
+
+    /// Abort processing for the current mail, but keep this connection
+    /// to process the next mail of the same smtp session.
+    ///
+    /// The next mail starts with [`Connection::mail`].
+    ///
+    /// # Errors
+    /// Errors on io or codec Errors
+    #[doc(alias = "SMFIC_ABORT")]
+    pub async fn reset(&mut self) -> Result<(), ProtocolError> {
+        self.body_skipped = false;
         self.framed.send(&Action::from(Abort).into()).await?;
 
         Ok(())
@@ -325,13 +506,28 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> Connection<RW> {
 
     /// Send a command to the server respecting protocol settings
     #[cfg_attr(feature = "tracing", instrument(level = Level::DEBUG, skip(self), fields(%command), err))]
-    async fn send_command(&mut self, command: Command) -> Result<(), ResponseError> {
+    async fn send_command(&mut self, command: Command) -> Result<Verdict, ResponseError> {
         // Eval skips
         if self.options.protocol.should_skip_send(&command) {
             debug!("Skip sending");
-            return Ok(());
+            return Ok(Verdict::Continue);
+        }
+        if self.body_skipped && matches!(command, Command::Body(_)) {
+            debug!("Skip sending, server skipped the remaining body");
+            return Ok(Verdict::Skip);
         }
+
+        let limit = self.timeouts.for_command(&command);
+        timeout(limit, self.exchange_command(command)).await?
+    }
+
+    /// Send a command to the server and receive it's verdict, if expected
+    async fn exchange_command(&mut self, command: Command) -> Result<Verdict, ResponseError> {
         let skip_response = self.options.protocol.should_skip_response(&command);
+        let is_body = matches!(command, Command::Body(_));
+
+        // Send macros requested for this stage
+        self.send_requested_macros(&command).await?;
 
         // Send it
         debug!("Sending command");
@@ -340,9 +536,43 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> Connection<RW> {
         // Check response
         if skip_response {
             debug!("Skip receiving response");
+            return Ok(Verdict::Continue);
+        }
+        let verdict = self.receive_verdict().await?;
+        if is_body && matches!(verdict, Verdict::Skip) {
+            self.body_skipped = true;
+        }
+
+        Ok(verdict)
+    }
+
+    /// Send the values of the macros requested by the server for the stage
+    /// of `command`, if any are known
+    async fn send_requested_macros(&mut self, command: &Command) -> Result<(), ResponseError> {
+        let Some(stage) = MacroStage::from_code(command.code()) else {
+            return Ok(());
+        };
+
+        let values: Vec<(&[u8], &[u8])> = self
+            .options
+            .macro_stages
+            .symbols(stage)
+            .iter()
+            .filter_map(|name| {
+                self.macros
+                    .get_key_value(name)
+                    .map(|(name, value)| (name.as_bytes(), value.as_bytes()))
+            })
+            .collect();
+        if values.is_empty() {
             return Ok(());
         }
-        self.expect_continue().await
+
+        debug!("Sending requested macros");
+        let macro_: Command = Macro::new(command.code(), &values).into();
+        self.framed.send(&macro_.into()).await?;
+
+        Ok(())
     }
 
     /// Shortcut to fetch an answer from the server
@@ -355,16 +585,11 @@ impl<RW: AsyncRead + AsyncWrite + Unpin> Connection<RW> {
 
         Ok(resp)
     }
-    /// Shortcut expect a Continue answer from the server
-    async fn expect_continue(&mut self) -> Result<(), ResponseError> {
-        // Receive back answer
+    /// Shortcut to receive the verdict of the server on the last command
+    async fn receive_verdict(&mut self) -> Result<Verdict, ResponseError> {
         let resp = self.receive_answer().await?;
 
-        // If continue, just continue. Otherwise return an error
-        match resp {
-            ServerCommand::Continue(_c) => Ok(()),
-            command => Err(ResponseError::Unexpected(command)),
-        }
+        Verdict::try_from(resp)
     }
 }
 
@@ -383,6 +608,18 @@ pub enum ResponseError {
     /// If we have a protocol compatibility issue
     #[error(transparent)]
     CompatibilityError(#[from] CompatibilityError),
+    /// Connecting to the milter failed
+    #[error("Failed connecting to the milter")]
+    Connect(#[source] std::io::Error),
+    /// Reading the message to process failed
+    #[error("Failed reading the message to process")]
+    ReadMessage(#[source] std::io::Error),
+    /// The server did not respond in time.
+    ///
+    /// The state of the connection is unknown afterwards, it should not be
+    /// used anymore.
+    #[error("Server did not respond within {0:?}")]
+    Timeout(Duration),
 }
 
 /// The types of commands the server may respond with
@@ -399,6 +636,7 @@ impl TryFrom<ServerCommand> for CommandType {
     fn try_from(value: ServerCommand) -> Result<Self, Self::Error> {
         match value {
             ServerCommand::OptNeg(value) => Err(ResponseError::Unexpected(value.into())),
+            ServerCommand::Progress(value) => Err(ResponseError::Unexpected(value.into())),
             ServerCommand::Abort(value) => Ok(Self::Action(value.into())),
             ServerCommand::Continue(value) => Ok(Self::Action(value.into())),
             ServerCommand::Discard(value) => Ok(Self::Action(value.into())),
@@ -407,12 +645,16 @@ impl TryFrom<ServerCommand> for CommandType {
             ServerCommand::Skip(value) => Ok(Self::Action(value.into())),
             ServerCommand::Replycode(value) => Ok(Self::Action(value.into())),
             ServerCommand::AddRecipient(value) => Ok(Self::ModificationAction(value.into())),
+            ServerCommand::AddRecipientWithArgs(value) => {
+                Ok(Self::ModificationAction(value.into()))
+            }
             ServerCommand::DeleteRecipient(value) => Ok(Self::ModificationAction(value.into())),
             ServerCommand::ReplaceBody(value) => Ok(Self::ModificationAction(value.into())),
             ServerCommand::AddHeader(value) => Ok(Self::ModificationAction(value.into())),
             ServerCommand::InsertHeader(value) => Ok(Self::ModificationAction(value.into())),
             ServerCommand::ChangeHeader(value) => Ok(Self::ModificationAction(value.into())),
             ServerCommand::Quarantine(value) => Ok(Self::ModificationAction(value.into())),
+            ServerCommand::ChangeFrom(value) => Ok(Self::ModificationAction(value.into())),
         }
     }
 }
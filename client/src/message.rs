@@ -0,0 +1,377 @@
+//! Drive a milter with a whole message at once
+
+use futures::{io::BufReader, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite};
+use miltr_common::{
+    actions::Action,
+    commands::{Connect, Header, Helo, Mail, Recipient},
+    modifications::ModificationResponse,
+    optneg::Protocol,
+};
+use miltr_utils::debug;
+
+use crate::{Connection, ResponseError, Verdict};
+
+/// The maximum size of a single body chunk sent to the milter
+/// (`MILTER_CHUNK_SIZE`)
+const MAX_BODY_CHUNK_SIZE: usize = 65535;
+
+/// The smtp envelope of a message to process
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    connect: Option<Connect>,
+    helo: Option<Helo>,
+    sender: Mail,
+    recipients: Vec<Recipient>,
+}
+
+impl Envelope {
+    /// A message sent by `sender`
+    #[must_use]
+    pub fn new(sender: &[u8]) -> Self {
+        Self {
+            connect: None,
+            helo: None,
+            sender: sender.into(),
+            recipients: Vec::new(),
+        }
+    }
+
+    /// Send `connect` information before the message
+    #[must_use]
+    pub fn with_connect(mut self, connect: Connect) -> Self {
+        self.connect = Some(connect);
+        self
+    }
+
+    /// Send the `helo` name of the smtp client before the message
+    #[must_use]
+    pub fn with_helo(mut self, helo: &[u8]) -> Self {
+        self.helo = Some(helo.into());
+        self
+    }
+
+    /// Add a `recipient` of the message
+    #[must_use]
+    pub fn with_recipient(mut self, recipient: &[u8]) -> Self {
+        self.recipients.push(recipient.into());
+        self
+    }
+}
+
+/// The stages of processing a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// [`Connection::connect`]
+    Connect,
+    /// [`Connection::helo`]
+    Helo,
+    /// [`Connection::mail`]
+    Mail,
+    /// [`Connection::recipient`]
+    Recipient,
+    /// [`Connection::data`]
+    Data,
+    /// [`Connection::header`]
+    Header,
+    /// [`Connection::end_of_header`]
+    EndOfHeader,
+    /// [`Connection::body`]
+    Body,
+    /// [`Connection::end_of_body`]
+    EndOfBody,
+}
+
+/// The aggregated result of [`Connection::process_message`]
+#[derive(Debug)]
+pub struct MessageOutcome {
+    response: ModificationResponse,
+    decided_at: Option<Stage>,
+    rejected_recipients: Vec<(Recipient, Verdict)>,
+}
+
+impl MessageOutcome {
+    /// The final action to take on the message
+    #[must_use]
+    pub fn final_action(&self) -> &Action {
+        self.response.final_action()
+    }
+
+    /// The response to the end of body, including all modifications.
+    ///
+    /// If processing stopped early, this contains no modifications and the
+    /// verdict of that stage as final action.
+    #[must_use]
+    pub fn response(&self) -> &ModificationResponse {
+        &self.response
+    }
+
+    /// The stage whose verdict was not to continue, if any
+    #[must_use]
+    pub fn decided_at(&self) -> Option<Stage> {
+        self.decided_at
+    }
+
+    /// The recipients the milter did not accept, along with it's verdict.
+    ///
+    /// The message is still processed for the remaining recipients.
+    #[must_use]
+    pub fn rejected_recipients(&self) -> &[(Recipient, Verdict)] {
+        &self.rejected_recipients
+    }
+
+    /// Consume this outcome, returning the end of body response
+    #[must_use]
+    pub fn into_response(self) -> ModificationResponse {
+        self.response
+    }
+}
+
+impl<RW: AsyncRead + AsyncWrite + Unpin> Connection<RW> {
+    /// Process a whole message, running all stages in order.
+    ///
+    /// `message` is a raw RFC 5322 message, headers followed by an empty line
+    /// and the body. As `&[u8]` implements [`AsyncRead`], a message in
+    /// memory can be passed directly. Headers are sent one by one, the body
+    /// is sent in chunks fitting the codec and the milter protocol.
+    ///
+    /// Processing stops early if the milter does not want to continue at any
+    /// stage. In that case, the message is aborted using
+    /// [`Connection::reset`]. Rejected recipients do not stop processing,
+    /// unless all recipients were rejected.
+    ///
+    /// After processing, either call [`Connection::quit`] or continue with
+    /// the next smtp session using [`Connection::quit_nc`].
+    ///
+    /// # Errors
+    /// Errors on io or codec errors, a response that is not a verdict or if
+    /// reading `message` fails.
+    pub async fn process_message<R: AsyncRead + Unpin>(
+        &mut self,
+        envelope: &Envelope,
+        message: R,
+    ) -> Result<MessageOutcome, ResponseError> {
+        if let Some(connect) = &envelope.connect {
+            let verdict = self.connect(connect.clone()).await?;
+            if !verdict.is_continue() {
+                return self.stop_early(Stage::Connect, verdict).await;
+            }
+        }
+        if let Some(helo) = &envelope.helo {
+            let verdict = self.helo(helo.clone()).await?;
+            if !verdict.is_continue() {
+                return self.stop_early(Stage::Helo, verdict).await;
+            }
+        }
+
+        let verdict = self.mail(envelope.sender.clone()).await?;
+        if !verdict.is_continue() {
+            return self.stop_early(Stage::Mail, verdict).await;
+        }
+
+        let mut rejected_recipients = Vec::new();
+        for recipient in &envelope.recipients {
+            match self.recipient(recipient.clone()).await? {
+                Verdict::Continue => {}
+                Verdict::Discard => {
+                    return self.stop_early(Stage::Recipient, Verdict::Discard).await
+                }
+                verdict => rejected_recipients.push((recipient.clone(), verdict)),
+            }
+        }
+
+        let mut outcome = match rejected_recipients.last() {
+            Some((_recipient, verdict))
+                if rejected_recipients.len() == envelope.recipients.len() =>
+            {
+                self.stop_early(Stage::Recipient, verdict.clone()).await?
+            }
+            _ => self.process_content(message).await?,
+        };
+        outcome.rejected_recipients = rejected_recipients;
+
+        Ok(outcome)
+    }
+
+    /// Send data, headers and body of `message`, and finish it with the end
+    /// of body
+    async fn process_content<R: AsyncRead + Unpin>(
+        &mut self,
+        message: R,
+    ) -> Result<MessageOutcome, ResponseError> {
+        let verdict = self.data().await?;
+        if !verdict.is_continue() {
+            return self.stop_early(Stage::Data, verdict).await;
+        }
+
+        let mut message = BufReader::new(message);
+        let leading_space = self.options.protocol.contains(Protocol::SMFIP_HDR_LEADSPC);
+
+        // Headers, as long as no empty line or line without a colon is found
+        let mut line = Vec::new();
+        let mut header: Option<Vec<u8>> = None;
+        loop {
+            line.clear();
+            message
+                .read_until(b'\n', &mut line)
+                .await
+                .map_err(ResponseError::ReadMessage)?;
+
+            // A folded header continues on this line
+            if let (Some(header), Some(b' ' | b'\t')) = (&mut header, line.first()) {
+                header.push(b'\n');
+                header.extend_from_slice(trim_line_ending(&line));
+                continue;
+            }
+
+            if let Some(header) = header.take() {
+                let verdict = self.header(parse_header(&header, leading_space)).await?;
+                if !verdict.is_continue() {
+                    return self.stop_early(Stage::Header, verdict).await;
+                }
+            }
+
+            let is_header = line.contains(&b':') && !matches!(line.first(), Some(b' ' | b'\t'));
+            if !is_header {
+                break;
+            }
+            header = Some(trim_line_ending(&line).to_vec());
+        }
+
+        let verdict = self.end_of_header().await?;
+        if !verdict.is_continue() {
+            return self.stop_early(Stage::EndOfHeader, verdict).await;
+        }
+
+        // The body, starting with the line ending the headers if it is not empty
+        let chunk_size = self
+            .framed
+            .codec()
+            .max_buffer_size()
+            .min(MAX_BODY_CHUNK_SIZE);
+        let mut chunk = if trim_line_ending(&line).is_empty() {
+            Vec::new()
+        } else {
+            line
+        };
+        'body: loop {
+            // Fill a chunk up to the chunk size or the end of the message
+            let mut end_of_message = false;
+            while chunk.len() < chunk_size {
+                let filled = chunk.len();
+                chunk.resize(chunk_size, 0);
+                let read = message
+                    .read(&mut chunk[filled..])
+                    .await
+                    .map_err(ResponseError::ReadMessage)?;
+                chunk.truncate(filled + read);
+
+                if read == 0 {
+                    end_of_message = true;
+                    break;
+                }
+            }
+
+            for part in chunk.chunks(chunk_size) {
+                match self.body(part).await? {
+                    Verdict::Continue => {}
+                    Verdict::Skip => {
+                        debug!("Skipping the remaining body");
+                        break 'body;
+                    }
+                    verdict => return self.stop_early(Stage::Body, verdict).await,
+                }
+            }
+            chunk.clear();
+
+            if end_of_message {
+                break;
+            }
+        }
+
+        let response = self.end_of_body().await?;
+        let decided_at = match response.final_action() {
+            Action::Continue(_) => None,
+            _ => Some(Stage::EndOfBody),
+        };
+
+        Ok(MessageOutcome {
+            response,
+            decided_at,
+            rejected_recipients: Vec::new(),
+        })
+    }
+
+    /// Abort the current message after `verdict` at `stage`
+    async fn stop_early(
+        &mut self,
+        stage: Stage,
+        verdict: Verdict,
+    ) -> Result<MessageOutcome, ResponseError> {
+        debug!("Milter decided on the message early");
+        self.reset().await?;
+
+        Ok(MessageOutcome {
+            response: ModificationResponse::builder().build(verdict),
+            decided_at: Some(stage),
+            rejected_recipients: Vec::new(),
+        })
+    }
+}
+
+/// Strip a trailing `\n` or `\r\n`
+fn trim_line_ending(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// Split a raw header into name and value
+fn parse_header(header: &[u8], leading_space: bool) -> Header {
+    let (name, value) = match header.iter().position(|&b| b == b':') {
+        Some(colon) => (&header[..colon], &header[colon + 1..]),
+        None => (header, &b""[..]),
+    };
+
+    let value = if leading_space {
+        value
+    } else {
+        let start = value
+            .iter()
+            .position(|&b| b != b' ' && b != b'\t')
+            .unwrap_or(value.len());
+        &value[start..]
+    };
+
+    Header::new(name, value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(b"Subject: Hi", false, "Subject", "Hi")]
+    #[case(b"Subject: Hi", true, "Subject", " Hi")]
+    #[case(b"Subject:\tHi\n\tthere", false, "Subject", "Hi\n\tthere")]
+    #[case(b"X-Empty:", false, "X-Empty", "")]
+    fn test_parse_header(
+        #[case] header: &[u8],
+        #[case] leading_space: bool,
+        #[case] name: &str,
+        #[case] value: &str,
+    ) {
+        let header = parse_header(header, leading_space);
+
+        assert_eq!(header.name(), name);
+        assert_eq!(header.value(), value);
+    }
+
+    #[rstest]
+    #[case(b"line\r\n", b"line")]
+    #[case(b"line\n", b"line")]
+    #[case(b"line", b"line")]
+    #[case(b"\r\n", b"")]
+    fn test_trim_line_ending(#[case] line: &[u8], #[case] expected: &[u8]) {
+        assert_eq!(trim_line_ending(line), expected);
+    }
+}
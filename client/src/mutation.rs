@@ -0,0 +1,369 @@
+//! Apply the modifications requested by a milter to a message
+
+use miltr_common::modifications::{
+    body::ReplaceBody,
+    headers::{AddHeader, ChangeHeader, InsertHeader},
+    ModificationAction, ModificationResponse,
+};
+
+/// A message in memory, as an MTA would keep it while running milters.
+///
+/// Apply the [`ModificationResponse`] returned by
+/// [`Connection::end_of_body`](crate::Connection::end_of_body) to it using
+/// [`Message::apply`]. The semantics follow those of the Postfix cleanup
+/// daemon. Headers the MTA adds itself, e.g. `Received`, are not part of
+/// this message and do not count for header indices.
+///
+/// ```
+/// use miltr_client::Message;
+/// use miltr_common::modifications::{headers::ChangeHeader, ModificationResponse};
+///
+/// let mut message = Message::new("<alice@example.com>")
+///     .with_recipient("<bob@example.com>")
+///     .with_header("Subject", "Hi")
+///     .with_body(b"Hello Bob\r\n");
+///
+/// let mut builder = ModificationResponse::builder();
+/// builder.push(ChangeHeader::new(1, b"Subject", b"[SPAM] Hi"));
+/// message.apply(&builder.contin());
+///
+/// assert_eq!(message.header("subject"), Some("[SPAM] Hi"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Message {
+    sender: String,
+    esmtp_args: Vec<String>,
+    recipients: Vec<String>,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    quarantine: Option<String>,
+}
+
+impl Message {
+    /// An empty message sent by `sender`
+    #[must_use]
+    pub fn new<S: Into<String>>(sender: S) -> Self {
+        Self {
+            sender: sender.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Add a `recipient` to the envelope
+    #[must_use]
+    pub fn with_recipient<R: Into<String>>(mut self, recipient: R) -> Self {
+        self.recipients.push(recipient.into());
+        self
+    }
+
+    /// Append a header.
+    ///
+    /// Folded values use `\n` followed by whitespace to separate lines, the
+    /// same way they are sent to the milter.
+    #[must_use]
+    pub fn with_header<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Set the `body` of the message
+    #[must_use]
+    pub fn with_body(mut self, body: &[u8]) -> Self {
+        self.body = body.to_vec();
+        self
+    }
+
+    /// The envelope sender
+    #[must_use]
+    pub fn sender(&self) -> &str {
+        &self.sender
+    }
+
+    /// The esmtp args of the envelope sender, if changed by the milter
+    #[must_use]
+    pub fn esmtp_args(&self) -> &[String] {
+        &self.esmtp_args
+    }
+
+    /// The envelope recipients
+    #[must_use]
+    pub fn recipients(&self) -> &[String] {
+        &self.recipients
+    }
+
+    /// All headers in order, as name and value
+    #[must_use]
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// The value of the first header called `name`, ignoring case
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _value)| n.eq_ignore_ascii_case(name))
+            .map(|(_name, value)| value.as_str())
+    }
+
+    /// The message body
+    #[must_use]
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// The reason the milter quarantined this message for, if it did
+    #[must_use]
+    pub fn quarantine(&self) -> Option<&str> {
+        self.quarantine.as_deref()
+    }
+
+    /// Apply all modifications in `response`, in order.
+    ///
+    /// The final action of `response` is not considered, acting on it is
+    /// up to the caller.
+    pub fn apply(&mut self, response: &ModificationResponse) {
+        let mut body_replaced = false;
+
+        for modification in response.modifications() {
+            match modification {
+                ModificationAction::ReplaceBody(body) => {
+                    self.replace_body(body, body_replaced);
+                    body_replaced = true;
+                }
+                modification => self.apply_modification(modification),
+            }
+        }
+    }
+
+    /// Apply a single `modification`.
+    ///
+    /// A [`ReplaceBody`] replaces the whole body. Use [`Message::apply`] to
+    /// concatenate the body parts of a response.
+    pub fn apply_modification(&mut self, modification: &ModificationAction) {
+        match modification {
+            ModificationAction::AddRecipient(add) => {
+                self.recipients.push(add.recipient().into_owned());
+            }
+            ModificationAction::AddRecipientWithArgs(add) => {
+                self.recipients.push(add.recipient().into_owned());
+            }
+            ModificationAction::DeleteRecipient(delete) => {
+                let recipient = strip_angle_brackets(&delete.recipient()).to_owned();
+                self.recipients
+                    .retain(|r| strip_angle_brackets(r) != recipient);
+            }
+            ModificationAction::ReplaceBody(body) => self.replace_body(body, false),
+            ModificationAction::ChangeFrom(change) => {
+                self.sender = change.sender().into_owned();
+                self.esmtp_args = change
+                    .esmtp_args()
+                    .into_iter()
+                    .map(std::borrow::Cow::into_owned)
+                    .collect();
+            }
+            ModificationAction::AddHeader(add) => self.add_header(add),
+            ModificationAction::InsertHeader(insert) => self.insert_header(insert),
+            ModificationAction::ChangeHeader(change) => self.change_header(change),
+            ModificationAction::Quarantine(quarantine) => {
+                self.quarantine = Some(quarantine.reason().into_owned());
+            }
+        }
+    }
+
+    /// Replace the body, or append to an already replaced one
+    fn replace_body(&mut self, body: &ReplaceBody, append: bool) {
+        if !append {
+            self.body.clear();
+        }
+        self.body.extend_from_slice(body.as_bytes());
+    }
+
+    /// Append a header after all others
+    fn add_header(&mut self, add: &AddHeader) {
+        self.headers
+            .push((add.name().into_owned(), add.value().into_owned()));
+    }
+
+    /// Insert a header before the header at `index`, counting from 0.
+    ///
+    /// An index past the last header appends it.
+    fn insert_header(&mut self, insert: &InsertHeader) {
+        let index = (insert.index() as usize).min(self.headers.len());
+        self.headers.insert(
+            index,
+            (insert.name().into_owned(), insert.value().into_owned()),
+        );
+    }
+
+    /// Change the n-th header with a name, counting from 1.
+    ///
+    /// An empty value deletes the header. As Sendmail does, an index of 0
+    /// changes the first header and a non-existing header is appended.
+    fn change_header(&mut self, change: &ChangeHeader) {
+        let name = change.name();
+        let value = change.value();
+        let nth = (change.index() as usize).max(1) - 1;
+
+        let position = self
+            .headers
+            .iter()
+            .enumerate()
+            .filter(|(_i, (n, _value))| n.eq_ignore_ascii_case(&name))
+            .nth(nth)
+            .map(|(i, _header)| i);
+
+        match (position, value.is_empty()) {
+            (Some(position), true) => {
+                self.headers.remove(position);
+            }
+            (Some(position), false) => self.headers[position].1 = value.into_owned(),
+            (None, true) => {}
+            (None, false) => self.headers.push((name.into_owned(), value.into_owned())),
+        }
+    }
+}
+
+/// Compare addresses without the surrounding angle brackets
+fn strip_angle_brackets(address: &str) -> &str {
+    address
+        .strip_prefix('<')
+        .and_then(|a| a.strip_suffix('>'))
+        .unwrap_or(address)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use miltr_common::modifications::{
+        quarantine::Quarantine,
+        recipients::{AddRecipient, AddRecipientWithArgs, DeleteRecipient},
+        sender::ChangeFrom,
+    };
+    use rstest::rstest;
+
+    fn message() -> Message {
+        Message::new("<alice@example.com>")
+            .with_recipient("<bob@example.com>")
+            .with_recipient("<carol@example.com>")
+            .with_header("Received", "first")
+            .with_header("Subject", "Hi")
+            .with_header("Received", "second")
+            .with_body(b"Hello\r\n")
+    }
+
+    fn apply<M: Into<ModificationAction>>(modifications: Vec<M>) -> Message {
+        let mut builder = ModificationResponse::builder();
+        for modification in modifications {
+            builder.push(modification);
+        }
+
+        let mut message = message();
+        message.apply(&builder.contin());
+        message
+    }
+
+    fn header_names(message: &Message) -> Vec<&str> {
+        message.headers().iter().map(|(n, _v)| n.as_str()).collect()
+    }
+
+    #[test]
+    fn test_add_header() {
+        let message = apply(vec![AddHeader::new(b"X-Spam", b"no")]);
+
+        assert_eq!(
+            header_names(&message),
+            ["Received", "Subject", "Received", "X-Spam"]
+        );
+    }
+
+    #[rstest]
+    #[case(0, ["X-Spam", "Received", "Subject", "Received"])]
+    #[case(1, ["Received", "X-Spam", "Subject", "Received"])]
+    #[case(3, ["Received", "Subject", "Received", "X-Spam"])]
+    #[case(42, ["Received", "Subject", "Received", "X-Spam"])]
+    fn test_insert_header(#[case] index: u32, #[case] expected: [&str; 4]) {
+        let message = apply(vec![InsertHeader::new(index, b"X-Spam", b"no")]);
+
+        assert_eq!(header_names(&message), expected);
+    }
+
+    #[rstest]
+    #[case(ChangeHeader::new(1, b"received", b"changed"), &[("Received", "changed"), ("Subject", "Hi"), ("Received", "second")])]
+    #[case(ChangeHeader::new(2, b"Received", b"changed"), &[("Received", "first"), ("Subject", "Hi"), ("Received", "changed")])]
+    #[case(ChangeHeader::new(0, b"Subject", b"changed"), &[("Received", "first"), ("Subject", "changed"), ("Received", "second")])]
+    #[case(ChangeHeader::new(2, b"Received", b""), &[("Received", "first"), ("Subject", "Hi")])]
+    #[case(ChangeHeader::new(3, b"Received", b""), &[("Received", "first"), ("Subject", "Hi"), ("Received", "second")])]
+    #[case(ChangeHeader::new(1, b"X-Spam", b"yes"), &[("Received", "first"), ("Subject", "Hi"), ("Received", "second"), ("X-Spam", "yes")])]
+    fn test_change_header(#[case] change: ChangeHeader, #[case] expected: &[(&str, &str)]) {
+        let message = apply(vec![change]);
+
+        let headers: Vec<(&str, &str)> = message
+            .headers()
+            .iter()
+            .map(|(n, v)| (n.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(headers, expected);
+    }
+
+    #[test]
+    fn test_replace_body() {
+        let message = apply(vec![
+            ReplaceBody::new(b"Replaced "),
+            ReplaceBody::new(b"in chunks\r\n"),
+        ]);
+
+        assert_eq!(message.body(), b"Replaced in chunks\r\n");
+    }
+
+    #[test]
+    fn test_replace_body_per_response() {
+        let mut message = apply(vec![ReplaceBody::new(b"First\r\n")]);
+
+        let mut builder = ModificationResponse::builder();
+        builder.push(ReplaceBody::new(b"Second\r\n"));
+        message.apply(&builder.contin());
+
+        assert_eq!(message.body(), b"Second\r\n");
+    }
+
+    #[test]
+    fn test_recipients() {
+        let mut builder = ModificationResponse::builder();
+        builder.push(DeleteRecipient::new(b"bob@example.com"));
+        builder.push(AddRecipient::new(b"<dave@example.com>"));
+        builder.push(AddRecipientWithArgs::new(
+            b"<erin@example.com>",
+            b"NOTIFY=NEVER",
+        ));
+        let mut message = message();
+        message.apply(&builder.contin());
+
+        assert_eq!(
+            message.recipients(),
+            [
+                "<carol@example.com>",
+                "<dave@example.com>",
+                "<erin@example.com>"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_change_from() {
+        let message = apply(vec![ChangeFrom::with_esmtp_args(
+            b"<srs@example.com>",
+            b"SIZE=12",
+        )]);
+
+        assert_eq!(message.sender(), "<srs@example.com>");
+        assert_eq!(message.esmtp_args(), ["SIZE=12"]);
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let message = apply(vec![Quarantine::new(b"Looks suspicious")]);
+
+        assert_eq!(message.quarantine(), Some("Looks suspicious"));
+    }
+}
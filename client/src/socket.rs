@@ -0,0 +1,101 @@
+//! Connect to milters using a [`SocketSpec`]
+
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{AsyncRead, AsyncWrite};
+use miltr_common::socket::{resolve, SocketSpec};
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+
+/// A connection to a milter opened by [`Client::connect`](crate::Client::connect)
+#[derive(Debug)]
+pub enum Stream {
+    /// A tcp connection
+    Tcp(Compat<TcpStream>),
+    /// A unix domain socket connection
+    #[cfg(unix)]
+    Unix(Compat<UnixStream>),
+}
+
+impl Stream {
+    /// Connect to the socket described by `spec`.
+    ///
+    /// Inet sockets without a host connect to the loopback address of their
+    /// family.
+    pub(crate) async fn connect(spec: &SocketSpec) -> io::Result<Self> {
+        match spec {
+            SocketSpec::Inet { host, port } => {
+                let host = host.as_deref().unwrap_or("127.0.0.1");
+                let addrs = resolve(host, *port, SocketAddr::is_ipv4).await?;
+                Ok(Self::Tcp(
+                    TcpStream::connect(addrs.as_slice()).await?.compat(),
+                ))
+            }
+            SocketSpec::Inet6 { host, port } => {
+                let host = host.as_deref().unwrap_or("::1");
+                let addrs = resolve(host, *port, SocketAddr::is_ipv6).await?;
+                Ok(Self::Tcp(
+                    TcpStream::connect(addrs.as_slice()).await?.compat(),
+                ))
+            }
+            #[cfg(unix)]
+            SocketSpec::Unix(path) => Ok(Self::Unix(UnixStream::connect(path).await?.compat())),
+            #[cfg(not(unix))]
+            SocketSpec::Unix(_path) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix domain sockets are not supported on this platform",
+            )),
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Self::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_close(cx),
+            #[cfg(unix)]
+            Self::Unix(stream) => Pin::new(stream).poll_close(cx),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! Limit how long to wait for the milter
+
+use std::{pin::pin, time::Duration};
+
+use futures::{
+    future::{select, Either},
+    Future,
+};
+use futures_timer::Delay;
+use miltr_common::commands::Command;
+
+use crate::ResponseError;
+
+/// The timeouts of a connection, modeled after the postfix milter timeouts
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Timeouts {
+    /// Option negotiation, `milter_connect_timeout`
+    pub(crate) connect: Option<Duration>,
+    /// Smtp commands, `milter_command_timeout`
+    pub(crate) command: Option<Duration>,
+    /// Message content, `milter_content_timeout`
+    pub(crate) content: Option<Duration>,
+}
+
+impl Timeouts {
+    /// The timeout to send `command` and receive it's response
+    pub(crate) fn for_command(&self, command: &Command) -> Option<Duration> {
+        match command {
+            Command::Header(_)
+            | Command::EndOfHeader(_)
+            | Command::Body(_)
+            | Command::EndOfBody(_) => self.content,
+            _ => self.command,
+        }
+    }
+}
+
+/// Await `future`, erroring if it takes longer than `limit`
+pub(crate) async fn timeout<F: Future>(
+    limit: Option<Duration>,
+    future: F,
+) -> Result<F::Output, ResponseError> {
+    let Some(limit) = limit else {
+        return Ok(future.await);
+    };
+
+    match select(pin!(future), Delay::new(limit)).await {
+        Either::Left((output, _delay)) => Ok(output),
+        Either::Right(((), _future)) => Err(ResponseError::Timeout(limit)),
+    }
+}
@@ -0,0 +1,65 @@
+//! The answers of a milter server to single commands
+
+use miltr_common::{
+    actions::{Action, Continue, Discard, Reject, Replycode, Skip, Tempfail},
+    decoding::ServerCommand,
+};
+
+use crate::ResponseError;
+
+/// How the milter server would like the MTA to proceed after a command.
+#[derive(Debug, Clone)]
+pub enum Verdict {
+    /// Continue processing the mail.
+    ///
+    /// Also returned if the command was not sent or no answer was awaited,
+    /// as negotiated in the protocol.
+    Continue,
+    /// Reject the command or the mail
+    Reject,
+    /// Reject the command or the mail with a temporary failure
+    Tempfail,
+    /// Accept the mail, but silently discard it
+    Discard,
+    /// Skip sending the remaining body chunks
+    Skip,
+    /// Reject with a custom reply code
+    Replycode(Replycode),
+}
+
+impl Verdict {
+    /// Whether the MTA may just continue processing the mail
+    #[must_use]
+    pub fn is_continue(&self) -> bool {
+        matches!(self, Self::Continue)
+    }
+}
+
+impl TryFrom<ServerCommand> for Verdict {
+    type Error = ResponseError;
+
+    fn try_from(value: ServerCommand) -> Result<Self, Self::Error> {
+        match value {
+            ServerCommand::Continue(_) => Ok(Self::Continue),
+            ServerCommand::Reject(_) => Ok(Self::Reject),
+            ServerCommand::Tempfail(_) => Ok(Self::Tempfail),
+            ServerCommand::Discard(_) => Ok(Self::Discard),
+            ServerCommand::Skip(_) => Ok(Self::Skip),
+            ServerCommand::Replycode(replycode) => Ok(Self::Replycode(replycode)),
+            command => Err(ResponseError::Unexpected(command)),
+        }
+    }
+}
+
+impl From<Verdict> for Action {
+    fn from(value: Verdict) -> Self {
+        match value {
+            Verdict::Continue => Continue.into(),
+            Verdict::Reject => Reject.into(),
+            Verdict::Tempfail => Tempfail.into(),
+            Verdict::Discard => Discard.into(),
+            Verdict::Skip => Skip.into(),
+            Verdict::Replycode(replycode) => replycode.into(),
+        }
+    }
+}
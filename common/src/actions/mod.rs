@@ -4,12 +4,14 @@
 //! the last command.
 
 mod bidirectional;
+mod progress;
 mod quit;
 mod to_mta_only;
 
 use enum_dispatch::enum_dispatch;
 
 pub use self::bidirectional::{Abort, Continue};
+pub use self::progress::Progress;
 pub use self::quit::{Quit, QuitNc};
 pub use self::to_mta_only::{Discard, Reject, Replycode, Skip, Tempfail};
 
@@ -19,6 +21,7 @@ pub use self::to_mta_only::{Discard, Reject, Replycode, Skip, Tempfail};
 #[allow(missing_docs)]
 #[enum_dispatch]
 #[cfg_attr(feature = "tracing", derive(strum::Display))]
+#[cfg_attr(feature = "metrics", derive(strum::IntoStaticStr))]
 #[derive(Debug, Clone)]
 pub enum Action {
     Continue,
@@ -0,0 +1,41 @@
+use bytes::BytesMut;
+
+use crate::decoding::Parsable;
+use crate::encoding::Writable;
+use crate::ProtocolError;
+
+/// Keepalive sent to the MTA while processing takes a long time.
+///
+/// The MTA resets its timeout on receiving this, but keeps waiting for
+/// the final answer to the current command. This is only sent in response
+/// to an end of body, it is therefore not a regular [`Action`](super::Action).
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Progress;
+
+impl Progress {
+    const CODE: u8 = b'p';
+}
+
+impl Parsable for Progress {
+    const CODE: u8 = Self::CODE;
+
+    fn parse(_buffer: BytesMut) -> Result<Self, ProtocolError> {
+        Ok(Self)
+    }
+}
+
+impl Writable for Progress {
+    fn write(&self, _buffer: &mut BytesMut) {}
+
+    fn len(&self) -> usize {
+        0
+    }
+
+    fn code(&self) -> u8 {
+        Self::CODE
+    }
+
+    fn is_empty(&self) -> bool {
+        false
+    }
+}
@@ -176,7 +176,7 @@ impl Replycode {
 
     /// The message associated with this reply code
     #[must_use]
-    pub fn message(&self) -> Cow<str> {
+    pub fn message(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(&self.message)
     }
 
@@ -58,7 +58,7 @@ impl Connect {
     }
     /// Get the received hostname as as string-like type.
     #[must_use]
-    pub fn hostname(&self) -> Cow<str> {
+    pub fn hostname(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(&self.hostname)
     }
 
@@ -66,7 +66,7 @@ impl Connect {
     ///
     /// Remember, this can contain an IP-Address or a unix socket.
     #[must_use]
-    pub fn address(&self) -> Cow<str> {
+    pub fn address(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(&self.address)
     }
 }
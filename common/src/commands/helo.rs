@@ -24,7 +24,7 @@ impl Helo {
     const CODE: u8 = b'H';
     /// The helo greeting sent by the client
     #[must_use]
-    pub fn helo(&self) -> Cow<str> {
+    pub fn helo(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(&self.buffer[..])
     }
 }
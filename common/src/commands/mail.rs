@@ -27,7 +27,7 @@ impl Mail {
     const CODE: u8 = b'M';
     /// The sender of this email
     #[must_use]
-    pub fn sender(&self) -> Cow<str> {
+    pub fn sender(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(&self.sender)
     }
 
@@ -35,7 +35,7 @@ impl Mail {
     ///
     /// If those are empty, an empty vector is returned.
     #[must_use]
-    pub fn esmtp_args(&self) -> Vec<Cow<str>> {
+    pub fn esmtp_args(&self) -> Vec<Cow<'_, str>> {
         let Some(args) = &self.esmtp_args else {
             return Vec::new();
         };
@@ -1,7 +1,9 @@
 use crate::decoding::Parsable;
+use crate::encoding::Writable;
 use crate::error::STAGE_DECODING;
+use crate::optneg::MacroStage;
 use crate::{NotEnoughData, ProtocolError};
-use bytes::BytesMut;
+use bytes::{BufMut, BytesMut};
 use miltr_utils::ByteParsing;
 
 /// A macro received for the command identified by `Macro.code`.
@@ -13,10 +15,30 @@ pub struct Macro {
 }
 
 impl Macro {
+    /// Create macros for the command identified by `code`
+    #[must_use]
+    pub fn new(code: u8, macros: &[(&[u8], &[u8])]) -> Self {
+        Self {
+            code,
+            macros: macros
+                .iter()
+                .map(|(name, value)| (BytesMut::from(*name), BytesMut::from(*value)))
+                .collect(),
+        }
+    }
+
     /// An iterator over received macros in (key, value) format.
     pub fn macros(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
         self.macros.iter().map(|(b, c)| (&b[..], &c[..]))
     }
+
+    /// The macro stage these macros belong to.
+    ///
+    /// Returns `None` if `code` does not identify a command macros are sent for.
+    #[must_use]
+    pub fn stage(&self) -> Option<MacroStage> {
+        MacroStage::from_code(self.code)
+    }
 }
 
 impl Parsable for Macro {
@@ -65,6 +87,35 @@ impl Parsable for Macro {
     }
 }
 
+impl Writable for Macro {
+    /// buffer = code(name\0value\0)*
+    fn write(&self, buffer: &mut BytesMut) {
+        buffer.put_u8(self.code);
+        for (name, value) in &self.macros {
+            buffer.extend_from_slice(name);
+            buffer.put_u8(0);
+            buffer.extend_from_slice(value);
+            buffer.put_u8(0);
+        }
+    }
+
+    fn len(&self) -> usize {
+        1 + self
+            .macros
+            .iter()
+            .map(|(name, value)| name.len() + 1 + value.len() + 1)
+            .sum::<usize>()
+    }
+
+    fn code(&self) -> u8 {
+        Self::CODE
+    }
+
+    fn is_empty(&self) -> bool {
+        self.macros.is_empty()
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -93,6 +144,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_write_macro() {
+        let macro_ = Macro::new(b'M', &[(b"{auth_authen}", b"alice"), (b"i", b"4sdsfstwg")]);
+        let mut buffer = BytesMut::new();
+        macro_.write(&mut buffer);
+
+        assert_eq!(buffer.len(), macro_.len());
+        assert_eq!(
+            buffer,
+            BytesMut::from("M{auth_authen}\0alice\0i\x004sdsfstwg\0")
+        );
+
+        let parsed = Macro::parse(buffer).expect("Failed parsing written macro");
+        assert_eq!(parsed, macro_);
+    }
+
+    #[rstest]
+    #[case(b'C', Some(MacroStage::Connect))]
+    #[case(b'R', Some(MacroStage::RcptTo))]
+    #[case(b'E', Some(MacroStage::EndOfBody))]
+    #[case(b'N', Some(MacroStage::EndOfHeaders))]
+    #[case(b'X', None)]
+    fn test_stage(#[case] code: u8, #[case] expected: Option<MacroStage>) {
+        let macro_ = Macro::new(code, &[(b"i", b"4sdsfstwg")]);
+
+        assert_eq!(macro_.stage(), expected);
+    }
+
     #[cfg(feature = "count-allocations")]
     #[test]
     fn test_parse_mmacro() {
@@ -43,4 +43,6 @@ pub enum Command {
     EndOfBody,
     // Unknown
     Unknown,
+    // Macros for the following command
+    Macro,
 }
@@ -27,14 +27,14 @@ impl Recipient {
     const CODE: u8 = b'R';
     /// The recipient as received by the milter client
     #[must_use]
-    pub fn recipient(&self) -> Cow<str> {
+    pub fn recipient(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(&self.recipient)
     }
 
     /// Optional esmtp arguments regarding the recipients.
     ///
     /// Returns an empty `Vec` if no esmtp args where received
-    pub fn esmtp_args(&self) -> Vec<Cow<str>> {
+    pub fn esmtp_args(&self) -> Vec<Cow<'_, str>> {
         let Some(args) = &self.esmtp_args else {
             return Vec::new();
         };
@@ -3,11 +3,14 @@
 use bytes::{Buf, BytesMut};
 use enum_dispatch::enum_dispatch;
 
-use crate::actions::{Abort, Continue, Discard, Quit, QuitNc, Reject, Replycode, Skip, Tempfail};
+use crate::actions::{
+    Abort, Continue, Discard, Progress, Quit, QuitNc, Reject, Replycode, Skip, Tempfail,
+};
 
 use crate::{
-    error::STAGE_DECODING, AddHeader, AddRecipient, ChangeHeader, DeleteRecipient, InsertHeader,
-    InvalidData, NotEnoughData, ProtocolError, Quarantine, ReplaceBody,
+    error::STAGE_DECODING, AddHeader, AddRecipient, AddRecipientWithArgs, ChangeFrom, ChangeHeader,
+    DeleteRecipient, InsertHeader, InvalidData, NotEnoughData, ProtocolError, Quarantine,
+    ReplaceBody,
 };
 
 use super::commands::Connect;
@@ -37,6 +40,7 @@ macro_rules! parse_command {
         /// See the contained variants for more.
         #[allow(missing_docs)]
         #[cfg_attr(feature = "tracing", derive(strum::Display))]
+        #[cfg_attr(feature = "metrics", derive(strum::IntoStaticStr))]
         #[enum_dispatch]
         #[derive(Debug, Clone)]
         pub enum $container_name {
@@ -120,14 +124,18 @@ parse_command!(
     Tempfail,
     Skip,
     Replycode,
+    // Keepalive
+    Progress,
     // Modifications
     AddRecipient,
+    AddRecipientWithArgs,
     DeleteRecipient,
     ReplaceBody,
     AddHeader,
     InsertHeader,
     ChangeHeader,
     Quarantine,
+    ChangeFrom,
 );
 
 #[cfg(test)]
@@ -156,4 +164,24 @@ mod tests {
 
         assert_matches!(command, ClientCommand::OptNeg(o) if o.version == 6);
     }
+
+    #[test]
+    fn test_roundtrip_add_recipient_with_args() {
+        use crate::encoding::{ServerMessage, Writable};
+        use crate::modifications::ModificationAction;
+
+        let message = ServerMessage::ModificationAction(ModificationAction::from(
+            AddRecipientWithArgs::new(b"<rcpt@example.com>", b"NOTIFY=NEVER"),
+        ));
+        let mut buffer = BytesMut::new();
+        buffer.extend_from_slice(&[message.code()]);
+        message.write(&mut buffer);
+
+        let command = ServerCommand::parse(buffer).expect("Failed parsing add recipient");
+
+        assert_matches!(
+            command,
+            ServerCommand::AddRecipientWithArgs(a) if a.recipient() == "<rcpt@example.com>" && a.esmtp_args() == vec!["NOTIFY=NEVER"]
+        );
+    }
 }
@@ -7,12 +7,13 @@ use bytes::BytesMut;
 use enum_dispatch::enum_dispatch;
 
 use super::actions::{
-    Abort, Action, Continue, Discard, Quit, QuitNc, Reject, Replycode, Skip, Tempfail,
+    Abort, Action, Continue, Discard, Progress, Quit, QuitNc, Reject, Replycode, Skip, Tempfail,
 };
 use super::modifications::ModificationAction;
 
 use super::commands::{
-    Body, Command, Connect, Data, EndOfBody, EndOfHeader, Header, Helo, Mail, Recipient, Unknown,
+    Body, Command, Connect, Data, EndOfBody, EndOfHeader, Header, Helo, Macro, Mail, Recipient,
+    Unknown,
 };
 use super::optneg::OptNeg;
 
@@ -49,6 +50,8 @@ pub enum ServerMessage {
     Action,
     /// Modifications requested by the server to be applied to the mail
     ModificationAction,
+    /// Keepalive while the server is still busy processing
+    Progress,
 }
 
 #[cfg(feature = "tracing")]
@@ -60,6 +63,7 @@ impl Display for ServerMessage {
             ServerMessage::ModificationAction(mod_action) => {
                 write!(f, "ModificationAction/{mod_action}")
             }
+            ServerMessage::Progress(_progress) => write!(f, "Progress"),
         }
     }
 }
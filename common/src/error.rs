@@ -3,9 +3,10 @@ use std::io;
 use bytes::BytesMut;
 use thiserror::Error;
 
-use super::optneg::CompatibilityError;
+use super::{optneg::CompatibilityError, state::OutOfOrder};
 
 /// Encapsulating error for the different de-/encoding problems
+#[cfg_attr(feature = "metrics", derive(strum::IntoStaticStr))]
 #[derive(Debug, Error)]
 pub enum ProtocolError {
     /// Data that could not be interpreted
@@ -23,6 +24,9 @@ pub enum ProtocolError {
     /// An io error from the underlying codec implementation
     #[error(transparent)]
     CodecError(#[from] io::Error),
+    /// A command was received out of the protocol order
+    #[error(transparent)]
+    OutOfOrder(#[from] OutOfOrder),
 }
 
 /// Error when receiving bogus data from the other end
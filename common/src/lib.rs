@@ -4,8 +4,12 @@ pub mod actions;
 pub mod commands;
 pub mod decoding;
 pub mod encoding;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod modifications;
 pub mod optneg;
+pub mod socket;
+pub mod state;
 
 mod error;
 
@@ -17,5 +21,6 @@ use modifications::{
     body::ReplaceBody,
     headers::{AddHeader, ChangeHeader, InsertHeader},
     quarantine::Quarantine,
-    recipients::{AddRecipient, DeleteRecipient},
+    recipients::{AddRecipient, AddRecipientWithArgs, DeleteRecipient},
+    sender::ChangeFrom,
 };
@@ -0,0 +1,287 @@
+//! Metrics recorded by milter servers and clients.
+//!
+//! Metrics are recorded using the [`metrics`] facade. Nothing is recorded
+//! until the application installs a recorder, e.g. from
+//! [`metrics-exporter-prometheus`](https://docs.rs/metrics-exporter-prometheus).
+//! Call [`describe`] once after installing it to register descriptions and
+//! units.
+//!
+//! Every metric carries a `side` label, either `server` or `client`.
+
+use std::time::Duration;
+
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram};
+
+use crate::{
+    actions::Action, decoding::ServerCommand, encoding::ClientMessage,
+    modifications::ModificationAction, state::CommandKind, ProtocolError,
+};
+
+/// Milter sessions opened, a counter
+pub const SESSIONS_OPENED: &str = "miltr_sessions_opened_total";
+/// Milter sessions closed, a counter
+pub const SESSIONS_CLOSED: &str = "miltr_sessions_closed_total";
+/// Milter sessions currently open, a gauge
+pub const SESSIONS_ACTIVE: &str = "miltr_sessions_active";
+/// Commands handled, a counter labeled by `command`
+pub const COMMANDS: &str = "miltr_commands_total";
+/// Actions returned, a counter labeled by `action`
+pub const ACTIONS: &str = "miltr_actions_total";
+/// Modifications requested, a counter labeled by `modification`.
+///
+/// A body replaced in several packets counts once per packet.
+pub const MODIFICATIONS: &str = "miltr_modifications_total";
+/// Duration of milter callbacks in seconds, a histogram labeled by `stage`
+pub const CALLBACK_DURATION: &str = "miltr_callback_duration_seconds";
+/// Errors encoding or decoding packets, a counter labeled by `error`
+pub const CODEC_ERRORS: &str = "miltr_codec_errors_total";
+/// Bytes received, a counter
+pub const BYTES_RECEIVED: &str = "miltr_received_bytes_total";
+/// Bytes sent, a counter
+pub const BYTES_SENT: &str = "miltr_sent_bytes_total";
+
+/// Which end of a milter connection records a metric
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// The milter, e.g. `miltr-server`
+    Server,
+    /// The MTA, e.g. `miltr-client`
+    Client,
+}
+
+impl Side {
+    /// The value of the `side` label
+    #[must_use]
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Server => "server",
+            Self::Client => "client",
+        }
+    }
+}
+
+/// Register descriptions and units of all metrics with the installed
+/// recorder
+pub fn describe() {
+    describe_counter!(SESSIONS_OPENED, "Milter sessions opened");
+    describe_counter!(SESSIONS_CLOSED, "Milter sessions closed");
+    describe_gauge!(SESSIONS_ACTIVE, "Milter sessions currently open");
+    describe_counter!(COMMANDS, "Milter commands handled by type");
+    describe_counter!(ACTIONS, "Milter actions returned by type");
+    describe_counter!(MODIFICATIONS, "Milter modifications requested by type");
+    describe_histogram!(
+        CALLBACK_DURATION,
+        metrics::Unit::Seconds,
+        "Duration of milter callbacks by stage"
+    );
+    describe_counter!(CODEC_ERRORS, "Errors encoding or decoding milter packets");
+    describe_counter!(BYTES_RECEIVED, metrics::Unit::Bytes, "Bytes received");
+    describe_counter!(BYTES_SENT, metrics::Unit::Bytes, "Bytes sent");
+}
+
+/// An open milter session, counted as closed once dropped
+#[derive(Debug)]
+pub struct Session {
+    side: Side,
+}
+
+impl Session {
+    /// Count a newly opened session
+    #[must_use]
+    pub fn open(side: Side) -> Self {
+        counter!(SESSIONS_OPENED, "side" => side.label()).increment(1);
+        gauge!(SESSIONS_ACTIVE, "side" => side.label()).increment(1);
+        Self { side }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        counter!(SESSIONS_CLOSED, "side" => self.side.label()).increment(1);
+        gauge!(SESSIONS_ACTIVE, "side" => self.side.label()).decrement(1);
+    }
+}
+
+/// Count a command handled
+pub fn command<C: Into<CommandKind>>(side: Side, command: C) {
+    let command: &'static str = command.into().into();
+    counter!(COMMANDS, "side" => side.label(), "command" => command).increment(1);
+}
+
+/// Count an action returned
+pub fn action(side: Side, action: &Action) {
+    let action: &'static str = action.into();
+    counter!(ACTIONS, "side" => side.label(), "action" => action).increment(1);
+}
+
+/// Count a modification requested
+pub fn modification(side: Side, modification: &ModificationAction) {
+    let modification: &'static str = modification.into();
+    counter!(MODIFICATIONS, "side" => side.label(), "modification" => modification).increment(1);
+}
+
+/// Count a command, action or modification the client sent to the server
+pub fn client_message(side: Side, message: &ClientMessage) {
+    let command = match message {
+        ClientMessage::Optneg(_) => CommandKind::OptNeg,
+        ClientMessage::Command(command) => command.into(),
+        ClientMessage::Action(Action::Abort(_)) => CommandKind::Abort,
+        ClientMessage::Action(Action::Quit(_)) => CommandKind::Quit,
+        ClientMessage::Action(Action::QuitNc(_)) => CommandKind::QuitNc,
+        ClientMessage::Action(action) => return self::action(side, action),
+    };
+    self::command(side, command);
+}
+
+/// Count an action or modification the server sent to the client
+pub fn server_command(side: Side, command: &ServerCommand) {
+    let name: &'static str = command.into();
+    let metric = match command {
+        ServerCommand::OptNeg(_) | ServerCommand::Progress(_) => return,
+        ServerCommand::AddRecipient(_)
+        | ServerCommand::AddRecipientWithArgs(_)
+        | ServerCommand::DeleteRecipient(_)
+        | ServerCommand::ReplaceBody(_)
+        | ServerCommand::AddHeader(_)
+        | ServerCommand::InsertHeader(_)
+        | ServerCommand::ChangeHeader(_)
+        | ServerCommand::Quarantine(_)
+        | ServerCommand::ChangeFrom(_) => {
+            counter!(MODIFICATIONS, "side" => side.label(), "modification" => name)
+        }
+        ServerCommand::Abort(_)
+        | ServerCommand::Continue(_)
+        | ServerCommand::Discard(_)
+        | ServerCommand::Reject(_)
+        | ServerCommand::Tempfail(_)
+        | ServerCommand::Skip(_)
+        | ServerCommand::Replycode(_) => {
+            counter!(ACTIONS, "side" => side.label(), "action" => name)
+        }
+    };
+    metric.increment(1);
+}
+
+/// Record how long the milter callback for `stage` took
+pub fn callback_duration(side: Side, stage: CommandKind, duration: Duration) {
+    let stage: &'static str = stage.into();
+    histogram!(CALLBACK_DURATION, "side" => side.label(), "stage" => stage).record(duration);
+}
+
+/// Count an error encoding or decoding a packet
+pub fn codec_error(side: Side, error: &ProtocolError) {
+    let error: &'static str = error.into();
+    counter!(CODEC_ERRORS, "side" => side.label(), "error" => error).increment(1);
+}
+
+/// Count `len` bytes received
+pub fn bytes_received(side: Side, len: usize) {
+    counter!(BYTES_RECEIVED, "side" => side.label()).increment(len as u64);
+}
+
+/// Count `len` bytes sent
+pub fn bytes_sent(side: Side, len: usize) {
+    counter!(BYTES_SENT, "side" => side.label()).increment(len as u64);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        actions::{Abort, Progress, Reject},
+        commands::{Command, Helo},
+        modifications::headers::AddHeader,
+    };
+
+    /// The counters incremented by `record`, formatted as `name{labels}`
+    fn counters(record: impl FnOnce()) -> Vec<(String, u64)> {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, record);
+
+        let mut counters: Vec<(String, u64)> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter_map(|(key, _unit, _description, value)| {
+                let DebugValue::Counter(value) = value else {
+                    return None;
+                };
+                let labels: Vec<String> = key
+                    .key()
+                    .labels()
+                    .map(|l| format!("{}={}", l.key(), l.value()))
+                    .collect();
+                Some((
+                    format!("{}{{{}}}", key.key().name(), labels.join(",")),
+                    value,
+                ))
+            })
+            .collect();
+        counters.sort();
+        counters
+    }
+
+    #[test]
+    fn test_client_message() {
+        let counters = counters(|| {
+            let helo: Command = Helo::from(&b"localhost"[..]).into();
+            client_message(Side::Client, &helo.into());
+            client_message(Side::Client, &Action::from(Abort).into());
+            client_message(Side::Client, &Action::from(Abort).into());
+        });
+
+        assert_eq!(
+            counters,
+            [
+                (
+                    "miltr_commands_total{side=client,command=Abort}".to_string(),
+                    2
+                ),
+                (
+                    "miltr_commands_total{side=client,command=Helo}".to_string(),
+                    1
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_server_command() {
+        let counters = counters(|| {
+            server_command(Side::Client, &Progress.into());
+            server_command(Side::Client, &AddHeader::new(b"X-Spam", b"no").into());
+            server_command(Side::Client, &Reject.into());
+        });
+
+        assert_eq!(
+            counters,
+            [
+                (
+                    "miltr_actions_total{side=client,action=Reject}".to_string(),
+                    1
+                ),
+                (
+                    "miltr_modifications_total{side=client,modification=AddHeader}".to_string(),
+                    1
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_session() {
+        let counters = counters(|| drop(Session::open(Side::Server)));
+
+        assert_eq!(
+            counters,
+            [
+                ("miltr_sessions_closed_total{side=server}".to_string(), 1),
+                ("miltr_sessions_opened_total{side=server}".to_string(), 1),
+            ]
+        );
+    }
+}
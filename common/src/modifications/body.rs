@@ -1,8 +1,9 @@
 //! Replace body parts
 
-use std::borrow::Cow;
+use std::{borrow::Cow, fmt, io};
 
 use bytes::BytesMut;
+use futures::{AsyncRead, AsyncReadExt};
 
 use crate::decoding::Parsable;
 use crate::encoding::Writable;
@@ -29,6 +30,9 @@ impl<'a> FromIterator<&'a u8> for ReplaceBody {
 impl ReplaceBody {
     const CODE: u8 = b'b';
 
+    /// The largest body part libmilter sends in a single packet
+    pub const MAX_CHUNK_SIZE: usize = 65535;
+
     /// A body part to replace the original
     #[must_use]
     pub fn new(body: &[u8]) -> Self {
@@ -41,9 +45,129 @@ impl ReplaceBody {
     ///
     /// Will be interpreted by the client as a valid mail.
     #[must_use]
-    pub fn body(&self) -> Cow<str> {
+    pub fn body(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(&self.body)
     }
+
+    /// The raw bytes of the body part
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Split this body part into parts of at most `size` bytes.
+    ///
+    /// An empty body part is kept as a single empty part.
+    pub fn into_chunks(mut self, size: usize) -> impl Iterator<Item = Self> {
+        let size = size.max(1);
+        let mut done = false;
+
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+
+            let chunk = self.body.split_to(size.min(self.body.len()));
+            done = self.body.is_empty();
+            Some(Self { body: chunk })
+        })
+    }
+}
+
+/// A replacement body produced while it is sent to the client.
+///
+/// Instead of holding the whole body in memory as [`ReplaceBody`] parts, the
+/// body is read chunk by chunk from a reader or an iterator. Attach it to a
+/// [`ModificationResponse`](super::ModificationResponse) using
+/// [`with_body_stream`](super::ModificationResponse::with_body_stream).
+///
+/// ```
+/// use futures::io::Cursor;
+/// use miltr_common::modifications::{body::ReplaceBodyStream, ModificationResponse};
+///
+/// let body = Cursor::new(b"A new body\r\n".to_vec());
+/// let response = ModificationResponse::builder()
+///     .contin()
+///     .with_body_stream(ReplaceBodyStream::from_reader(body));
+/// ```
+pub struct ReplaceBodyStream {
+    source: Source,
+    pending: BytesMut,
+}
+
+/// Where a streamed body comes from
+enum Source {
+    Chunks(Box<dyn Iterator<Item = BytesMut> + Send>),
+    Reader(Box<dyn AsyncRead + Send + Unpin>),
+}
+
+impl ReplaceBodyStream {
+    /// Stream a body from `reader` until it is exhausted
+    pub fn from_reader<R: AsyncRead + Send + Unpin + 'static>(reader: R) -> Self {
+        Self {
+            source: Source::Reader(Box::new(reader)),
+            pending: BytesMut::new(),
+        }
+    }
+
+    /// Stream a body from the parts yielded by `chunks`.
+    ///
+    /// Parts larger than the chunk size are split when sending.
+    pub fn from_chunks<I, B>(chunks: I) -> Self
+    where
+        I: IntoIterator<Item = B>,
+        I::IntoIter: Send + 'static,
+        B: AsRef<[u8]>,
+    {
+        let chunks = chunks
+            .into_iter()
+            .map(|chunk| BytesMut::from(chunk.as_ref()));
+
+        Self {
+            source: Source::Chunks(Box::new(chunks)),
+            pending: BytesMut::new(),
+        }
+    }
+
+    /// The next part of the body, at most `size` bytes long.
+    ///
+    /// Returns `None` once the body is complete.
+    ///
+    /// # Errors
+    /// Errors if reading from the underlying reader fails.
+    pub async fn next_chunk(&mut self, size: usize) -> io::Result<Option<ReplaceBody>> {
+        let size = size.max(1);
+
+        match &mut self.source {
+            Source::Chunks(chunks) => {
+                while self.pending.is_empty() {
+                    let Some(chunk) = chunks.next() else {
+                        return Ok(None);
+                    };
+                    self.pending = chunk;
+                }
+            }
+            Source::Reader(reader) => {
+                self.pending.resize(size, 0);
+                let read = reader.read(&mut self.pending).await?;
+                self.pending.truncate(read);
+                if read == 0 {
+                    return Ok(None);
+                }
+            }
+        }
+
+        let at = size.min(self.pending.len());
+        Ok(Some(ReplaceBody {
+            body: self.pending.split_to(at),
+        }))
+    }
+}
+
+impl fmt::Debug for ReplaceBodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplaceBodyStream").finish_non_exhaustive()
+    }
 }
 
 impl Parsable for ReplaceBody {
@@ -76,6 +200,8 @@ impl Writable for ReplaceBody {
 #[cfg(test)]
 mod test {
     use super::*;
+    use rstest::rstest;
+
     #[test]
     fn test_replace_body() {
         let mut buffer = BytesMut::from("b");
@@ -86,4 +212,47 @@ mod test {
 
         assert_eq!(buffer, BytesMut::from("bnew body"));
     }
+
+    #[rstest]
+    #[case("", 4, &[""])]
+    #[case("abc", 4, &["abc"])]
+    #[case("abcd", 4, &["abcd"])]
+    #[case("abcdefghij", 4, &["abcd", "efgh", "ij"])]
+    fn test_into_chunks(#[case] body: &str, #[case] size: usize, #[case] expected: &[&str]) {
+        let chunks: Vec<_> = ReplaceBody::new(body.as_bytes())
+            .into_chunks(size)
+            .map(|chunk| chunk.body().into_owned())
+            .collect();
+
+        assert_eq!(chunks, expected);
+    }
+
+    async fn collect(mut stream: ReplaceBodyStream, size: usize) -> Vec<Vec<u8>> {
+        let mut chunks = Vec::new();
+        while let Some(chunk) = stream.next_chunk(size).await.expect("Reading chunk") {
+            chunks.push(chunk.as_bytes().to_vec());
+        }
+        chunks
+    }
+
+    #[tokio::test]
+    async fn test_stream_from_chunks() {
+        let stream = ReplaceBodyStream::from_chunks(vec![&b"abcdef"[..], b"", b"gh"]);
+
+        assert_eq!(
+            collect(stream, 4).await,
+            [b"abcd".to_vec(), b"ef".to_vec(), b"gh".to_vec()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_from_reader() {
+        let reader = futures::io::Cursor::new(b"abcdefghij".to_vec());
+        let stream = ReplaceBodyStream::from_reader(reader);
+
+        assert_eq!(
+            collect(stream, 4).await,
+            [b"abcd".to_vec(), b"efgh".to_vec(), b"ij".to_vec()]
+        );
+    }
 }
@@ -0,0 +1,83 @@
+//! An address with optional esmtp args, shared by sender and recipient
+//! modifications
+
+use std::borrow::Cow;
+
+use bytes::{BufMut, BytesMut};
+
+use crate::{InvalidData, ProtocolError};
+use miltr_utils::ByteParsing;
+
+/// `address\0[esmtp args\0]`
+#[derive(Debug, Clone)]
+pub(crate) struct AddressWithArgs {
+    address: BytesMut,
+    esmtp_args: Option<BytesMut>,
+}
+
+/// Error messages for a package missing a null byte
+pub(crate) struct ParseErrors {
+    /// After the address
+    pub(crate) address: &'static str,
+    /// After the esmtp args
+    pub(crate) esmtp_args: &'static str,
+}
+
+impl AddressWithArgs {
+    pub(crate) fn new(address: &[u8], esmtp_args: Option<&[u8]>) -> Self {
+        Self {
+            address: BytesMut::from_iter(address),
+            esmtp_args: esmtp_args.map(BytesMut::from_iter),
+        }
+    }
+
+    pub(crate) fn address(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.address)
+    }
+
+    /// The space separated esmtp args, empty if there are none
+    pub(crate) fn esmtp_args(&self) -> Vec<Cow<'_, str>> {
+        let Some(args) = &self.esmtp_args else {
+            return Vec::new();
+        };
+
+        args[..]
+            .split(|&b| b == b' ')
+            .filter(|a| !a.is_empty())
+            .map(String::from_utf8_lossy)
+            .collect()
+    }
+
+    pub(crate) fn parse(mut buffer: BytesMut, errors: &ParseErrors) -> Result<Self, ProtocolError> {
+        let Some(address) = buffer.delimited(0) else {
+            return Err(InvalidData::new(errors.address, buffer).into());
+        };
+
+        let esmtp_args = if buffer.is_empty() {
+            None
+        } else {
+            let Some(args) = buffer.delimited(0) else {
+                return Err(InvalidData::new(errors.esmtp_args, buffer).into());
+            };
+            Some(args)
+        };
+
+        Ok(Self {
+            address,
+            esmtp_args,
+        })
+    }
+
+    pub(crate) fn write(&self, buffer: &mut BytesMut) {
+        buffer.extend_from_slice(&self.address);
+        buffer.put_u8(0);
+        if let Some(args) = &self.esmtp_args {
+            buffer.extend_from_slice(args);
+            buffer.put_u8(0);
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.address.len() + 1 + self.esmtp_args.as_ref().map_or(0, |a| a.len() + 1)
+    }
+}
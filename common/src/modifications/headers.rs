@@ -30,13 +30,13 @@ impl AddHeader {
 
     /// The name of the header
     #[must_use]
-    pub fn name(&self) -> Cow<str> {
+    pub fn name(&self) -> Cow<'_, str> {
         self.header.name()
     }
 
     /// The value of the header
     #[must_use]
-    pub fn value(&self) -> Cow<str> {
+    pub fn value(&self) -> Cow<'_, str> {
         self.header.value()
     }
 }
@@ -94,13 +94,13 @@ impl ChangeHeader {
 
     /// The name of the header
     #[must_use]
-    pub fn name(&self) -> Cow<str> {
+    pub fn name(&self) -> Cow<'_, str> {
         self.header.name()
     }
 
     /// The value of the header
     #[must_use]
-    pub fn value(&self) -> Cow<str> {
+    pub fn value(&self) -> Cow<'_, str> {
         self.header.value()
     }
 
@@ -182,13 +182,13 @@ impl InsertHeader {
 
     /// The name of the header
     #[must_use]
-    pub fn name(&self) -> Cow<str> {
+    pub fn name(&self) -> Cow<'_, str> {
         self.header.name()
     }
 
     /// The value of the header
     #[must_use]
-    pub fn value(&self) -> Cow<str> {
+    pub fn value(&self) -> Cow<'_, str> {
         self.header.value()
     }
 
@@ -7,6 +7,9 @@ pub mod body;
 pub mod headers;
 pub mod quarantine;
 pub mod recipients;
+pub mod sender;
+
+mod esmtp;
 
 use enum_dispatch::enum_dispatch;
 
@@ -19,10 +22,11 @@ use crate::encoding::Writable;
 use crate::{actions::Abort, optneg::Capability};
 use bytes::BytesMut;
 
-use body::ReplaceBody;
+use body::{ReplaceBody, ReplaceBodyStream};
 use headers::{AddHeader, ChangeHeader, InsertHeader};
 use quarantine::Quarantine;
-use recipients::{AddRecipient, DeleteRecipient};
+use recipients::{AddRecipient, AddRecipientWithArgs, DeleteRecipient};
+use sender::ChangeFrom;
 
 /// A container for multiple modification requests towards the milter client.
 ///
@@ -42,9 +46,15 @@ use recipients::{AddRecipient, DeleteRecipient};
 /// they might not all be sent.
 /// During option negotiation, client and server agree on supported
 /// [`Capability`].
+///
+/// # Note on large bodies
+/// A [`ReplaceBody`] larger than a single packet is split into several
+/// packets when sent. To avoid holding a large body in memory at all, attach
+/// a [`ReplaceBodyStream`] using [`ModificationResponse::with_body_stream`].
 #[derive(Debug)]
 pub struct ModificationResponse {
     modifications: Vec<ModificationAction>,
+    body_stream: Option<ReplaceBodyStream>,
     final_action: Action,
 }
 
@@ -62,15 +72,61 @@ impl ModificationResponse {
     pub fn empty_continue() -> Self {
         Self {
             modifications: Vec::new(),
+            body_stream: None,
             final_action: Continue.into(),
         }
     }
 
+    /// Replace the body with one read from `stream` while it is sent.
+    ///
+    /// The streamed body is sent after all other modifications. It is
+    /// appended to any [`ReplaceBody`] already part of this response.
+    #[must_use]
+    pub fn with_body_stream(mut self, stream: ReplaceBodyStream) -> Self {
+        self.body_stream = Some(stream);
+        self
+    }
+
+    /// Merge the modifications of `other` into this response.
+    ///
+    /// The modifications of `other` are applied after those of `self`. If
+    /// `other` replaces the body, it replaces the body replacements of
+    /// `self`. The final action of `other` is only taken if `self` would
+    /// continue.
+    pub fn merge(&mut self, mut other: Self) {
+        let replaces_body = other.body_stream.is_some()
+            || other
+                .modifications
+                .iter()
+                .any(|m| matches!(m, ModificationAction::ReplaceBody(_)));
+        if replaces_body {
+            self.modifications
+                .retain(|m| !matches!(m, ModificationAction::ReplaceBody(_)));
+            self.body_stream = None;
+        }
+
+        self.modifications.append(&mut other.modifications);
+        if other.body_stream.is_some() {
+            self.body_stream = other.body_stream;
+        }
+        if matches!(self.final_action, Action::Continue(_)) {
+            self.final_action = other.final_action;
+        }
+    }
+
+    /// Take the streamed body replacement out of this response, if any
+    pub fn take_body_stream(&mut self) -> Option<ReplaceBodyStream> {
+        self.body_stream.take()
+    }
+
     /// Filter modification actions in `self`, keep only those which have been
     /// allowed by the specified `capabilities`.
     pub fn filter_mods_by_caps(&mut self, capabilities: Capability) {
         self.modifications
             .retain(|m| Self::mod_matches_caps(m, capabilities));
+        if !capabilities.contains(Capability::SMFIF_CHGBODY) {
+            self.body_stream = None;
+        }
     }
 
     /// Returns true, if a single modification action matches the set `capabilities`
@@ -79,6 +135,9 @@ impl ModificationResponse {
             ModificationAction::AddHeader(_) => capabilities.contains(Capability::SMFIF_ADDHDRS),
             ModificationAction::ReplaceBody(_) => capabilities.contains(Capability::SMFIF_CHGBODY),
             ModificationAction::AddRecipient(_) => capabilities.contains(Capability::SMFIF_ADDRCPT),
+            ModificationAction::AddRecipientWithArgs(_) => {
+                capabilities.contains(Capability::SMFIF_ADDRCPT_PAR)
+            }
             ModificationAction::DeleteRecipient(_) => {
                 capabilities.contains(Capability::SMFIF_DELRCPT)
             }
@@ -88,6 +147,7 @@ impl ModificationResponse {
             ModificationAction::Quarantine(_) => {
                 capabilities.contains(Capability::SMFIF_QUARANTINE)
             }
+            ModificationAction::ChangeFrom(_) => capabilities.contains(Capability::SMFIF_CHGFROM),
         }
     }
 
@@ -102,8 +162,17 @@ impl ModificationResponse {
     pub fn final_action(&self) -> &Action {
         &self.final_action
     }
+
+    /// Replace the final action to be done to the mail
+    pub fn set_final_action<A: Into<Action>>(&mut self, final_action: A) {
+        self.final_action = final_action.into();
+    }
 }
 
+/// Convert into the messages to send.
+///
+/// A streamed body is not part of the converted messages, take it out using
+/// [`ModificationResponse::take_body_stream`] before.
 impl From<ModificationResponse> for Vec<ServerMessage> {
     fn from(value: ModificationResponse) -> Self {
         let mut resp: Vec<ServerMessage> = Vec::with_capacity(value.modifications.len() + 1);
@@ -148,6 +217,7 @@ impl ModificationResponseBuilder {
     pub fn build<A: Into<Action>>(self, final_action: A) -> ModificationResponse {
         ModificationResponse {
             modifications: self.modifications,
+            body_stream: None,
             final_action: final_action.into(),
         }
     }
@@ -156,23 +226,22 @@ impl ModificationResponseBuilder {
 /// The container of possible milter modification actions
 #[enum_dispatch]
 #[cfg_attr(feature = "tracing", derive(strum::Display))]
+#[cfg_attr(feature = "metrics", derive(strum::IntoStaticStr))]
 #[derive(Debug, Clone)]
 pub enum ModificationAction {
     /// Add recipient
     AddRecipient,
     /// Delete recipient
     DeleteRecipient,
-    // /* add recipient (incl. ESMTP args) */
-    // currently not supported, feel free to implement
-    // SmfirAddrcptPar,
+    /// Add recipient (incl. ESMTP args)
+    AddRecipientWithArgs,
     // /* 421: shutdown (internal to MTA) */
     // Not implemented in Milter
     // SmfirShutdown,
     /// Replace mail body
     ReplaceBody,
-    // /* change envelope sender (from) */
-    // currently not supported, feel free to implement
-    // SmfirChgfrom,
+    /// Change envelope sender (from)
+    ChangeFrom,
     // /* cause a connection failure */
     // currently not supported, feel free to implement. But why would you
     // need the connection to fail? Please, at least try to reason why you
@@ -187,8 +256,7 @@ pub enum ModificationAction {
     /// Change an existing header
     ChangeHeader,
     // /* progress */
-    // currently not supported, feel free to implement. May be a bit complicated
-    // and config needed to handle timeouts and when to send and stuff
+    // Not a modification, see `actions::Progress`
     // SmfirProgress,
     /// Quarantine this mail
     Quarantine,
@@ -29,7 +29,7 @@ impl Quarantine {
 
     /// Give a reason to the client why this was quarantined
     #[must_use]
-    pub fn reason(&self) -> Cow<str> {
+    pub fn reason(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(&self.reason)
     }
 }
@@ -4,6 +4,7 @@ use std::borrow::Cow;
 
 use bytes::{BufMut, BytesMut};
 
+use super::esmtp::{AddressWithArgs, ParseErrors};
 use crate::decoding::Parsable;
 use crate::encoding::Writable;
 use crate::{InvalidData, ProtocolError};
@@ -29,7 +30,7 @@ impl AddRecipient {
 
     /// The recipient to add
     #[must_use]
-    pub fn recipient(&self) -> Cow<str> {
+    pub fn recipient(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(&self.recipient)
     }
 }
@@ -69,6 +70,74 @@ impl Writable for AddRecipient {
     }
 }
 
+/// Add a recipient including esmtp args, e.g. `NOTIFY=NEVER ORCPT=...`.
+///
+/// Does not change To in Header
+#[derive(Debug, Clone)]
+pub struct AddRecipientWithArgs {
+    recipient: AddressWithArgs,
+}
+
+impl AddRecipientWithArgs {
+    const CODE: u8 = b'2';
+
+    /// Add the specified recipient with the (space separated) `esmtp_args`
+    #[must_use]
+    pub fn new(recipient: &[u8], esmtp_args: &[u8]) -> Self {
+        Self {
+            recipient: AddressWithArgs::new(recipient, Some(esmtp_args)),
+        }
+    }
+
+    /// The recipient to add
+    #[must_use]
+    pub fn recipient(&self) -> Cow<'_, str> {
+        self.recipient.address()
+    }
+
+    /// The esmtp args to add alongside the recipient.
+    ///
+    /// If those are empty, an empty vector is returned.
+    #[must_use]
+    pub fn esmtp_args(&self) -> Vec<Cow<'_, str>> {
+        self.recipient.esmtp_args()
+    }
+}
+
+impl Parsable for AddRecipientWithArgs {
+    const CODE: u8 = Self::CODE;
+
+    fn parse(buffer: BytesMut) -> Result<Self, ProtocolError> {
+        let errors = ParseErrors {
+            address: "Received add recipient package without null byte terminating it",
+            esmtp_args: "Received add recipient package without null byte terminating esmtp args",
+        };
+
+        Ok(Self {
+            recipient: AddressWithArgs::parse(buffer, &errors)?,
+        })
+    }
+}
+
+impl Writable for AddRecipientWithArgs {
+    /// buffer = recipient\0[esmtp args\0]
+    fn write(&self, buffer: &mut BytesMut) {
+        self.recipient.write(buffer);
+    }
+
+    fn len(&self) -> usize {
+        self.recipient.len()
+    }
+
+    fn code(&self) -> u8 {
+        Self::CODE
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 #[derive(Debug, Clone)]
 /// Does not change To in Header
 pub struct DeleteRecipient {
@@ -88,7 +157,7 @@ impl DeleteRecipient {
 
     /// The (exact) recipient to be deleted
     #[must_use]
-    pub fn recipient(&self) -> Cow<str> {
+    pub fn recipient(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(&self.recipient)
     }
 }
@@ -155,4 +224,33 @@ mod test {
         assert_eq!(buffer.len(), add_rcpt.len());
         assert_eq!(buffer, BytesMut::from("alex@gmail\0"));
     }
+
+    #[test]
+    fn test_add_recipient_with_args() {
+        let mut buffer = BytesMut::new();
+        let add_rcpt = AddRecipientWithArgs::new(b"<alex@gmail>", b"NOTIFY=NEVER ORCPT=rfc822;a@b");
+        add_rcpt.write(&mut buffer);
+
+        assert_eq!(buffer.len(), add_rcpt.len());
+        assert_eq!(
+            buffer,
+            BytesMut::from("<alex@gmail>\0NOTIFY=NEVER ORCPT=rfc822;a@b\0")
+        );
+
+        let parsed = AddRecipientWithArgs::parse(buffer).expect("Failed parsing add recipient");
+        assert_eq!(parsed.recipient(), "<alex@gmail>");
+        assert_eq!(
+            parsed.esmtp_args(),
+            vec!["NOTIFY=NEVER", "ORCPT=rfc822;a@b"]
+        );
+    }
+
+    #[test]
+    fn test_add_recipient_with_args_without_args() {
+        let parsed = AddRecipientWithArgs::parse(BytesMut::from("<alex@gmail>\0"))
+            .expect("Failed parsing add recipient");
+
+        assert_eq!(parsed.recipient(), "<alex@gmail>");
+        assert!(parsed.esmtp_args().is_empty());
+    }
 }
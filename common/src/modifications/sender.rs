@@ -0,0 +1,130 @@
+//! Change the envelope sender
+
+use std::borrow::Cow;
+
+use bytes::BytesMut;
+
+use super::esmtp::{AddressWithArgs, ParseErrors};
+use crate::decoding::Parsable;
+use crate::encoding::Writable;
+use crate::ProtocolError;
+
+/// Change the envelope sender (`MAIL FROM`) of the current mail.
+///
+/// Does not change From in Header
+#[derive(Debug, Clone)]
+pub struct ChangeFrom {
+    sender: AddressWithArgs,
+}
+
+impl ChangeFrom {
+    const CODE: u8 = b'e';
+
+    /// Replace the envelope sender with `sender`
+    #[must_use]
+    pub fn new(sender: &[u8]) -> Self {
+        Self {
+            sender: AddressWithArgs::new(sender, None),
+        }
+    }
+
+    /// Replace the envelope sender with `sender` and the given esmtp args.
+    ///
+    /// The `esmtp_args` are space separated, e.g. `SIZE=1024 BODY=8BITMIME`.
+    #[must_use]
+    pub fn with_esmtp_args(sender: &[u8], esmtp_args: &[u8]) -> Self {
+        Self {
+            sender: AddressWithArgs::new(sender, Some(esmtp_args)),
+        }
+    }
+
+    /// The new envelope sender
+    #[must_use]
+    pub fn sender(&self) -> Cow<'_, str> {
+        self.sender.address()
+    }
+
+    /// The esmtp args to set alongside the new sender.
+    ///
+    /// If those are empty, an empty vector is returned.
+    #[must_use]
+    pub fn esmtp_args(&self) -> Vec<Cow<'_, str>> {
+        self.sender.esmtp_args()
+    }
+}
+
+impl Parsable for ChangeFrom {
+    const CODE: u8 = Self::CODE;
+
+    fn parse(buffer: BytesMut) -> Result<Self, ProtocolError> {
+        let errors = ParseErrors {
+            address: "Received change from package without sender terminated by null byte",
+            esmtp_args: "Received change from package without esmtp args terminated by null byte",
+        };
+
+        Ok(Self {
+            sender: AddressWithArgs::parse(buffer, &errors)?,
+        })
+    }
+}
+
+impl Writable for ChangeFrom {
+    /// buffer = sender\0[esmtp args\0]
+    fn write(&self, buffer: &mut BytesMut) {
+        self.sender.write(buffer);
+    }
+
+    fn len(&self) -> usize {
+        self.sender.len()
+    }
+
+    fn code(&self) -> u8 {
+        Self::CODE
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(
+        ChangeFrom::new(b"<srs@example.com>"),
+        BytesMut::from("<srs@example.com>\0")
+    )]
+    #[case(
+        ChangeFrom::with_esmtp_args(b"<srs@example.com>", b"SIZE=12 BODY=8BITMIME"),
+        BytesMut::from("<srs@example.com>\0SIZE=12 BODY=8BITMIME\0")
+    )]
+    fn test_change_from(#[case] input: ChangeFrom, #[case] expected: BytesMut) {
+        let mut buffer = BytesMut::new();
+        input.write(&mut buffer);
+
+        assert_eq!(buffer.len(), input.len());
+        assert_eq!(buffer, expected);
+
+        let parsed = ChangeFrom::parse(buffer).expect("Failed parsing written change from");
+        assert_eq!(parsed.sender(), input.sender());
+        assert_eq!(parsed.esmtp_args(), input.esmtp_args());
+    }
+
+    #[test]
+    fn test_change_from_esmtp_args() {
+        let change_from = ChangeFrom::with_esmtp_args(b"<a@b.c>", b"SIZE=12 BODY=8BITMIME");
+
+        assert_eq!(change_from.esmtp_args(), vec!["SIZE=12", "BODY=8BITMIME"]);
+        assert!(ChangeFrom::new(b"<a@b.c>").esmtp_args().is_empty());
+    }
+
+    #[test]
+    fn test_change_from_missing_null_byte() {
+        let err = ChangeFrom::parse(BytesMut::from("<a@b.c>")).expect_err("Parsed invalid data");
+        assert!(matches!(err, ProtocolError::InvalidData(_)));
+    }
+}
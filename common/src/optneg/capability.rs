@@ -16,13 +16,14 @@ bitflags::bitflags! {
         const SMFIF_CHGHDRS = 0x0000_0010;
         /// Quarantine message (SMFIR_QUARANTINE)
         const SMFIF_QUARANTINE = 0x0000_0020;
-        /// Change the from address
+        /// Change the envelope sender (SMFIR_CHGFROM)
         const SMFIF_CHGFROM = 0x0000_0040;
-        /// Add a recipient
+        /// Add recipients incl. esmtp args (SMFIR_ADDRCPT_PAR)
         const SMFIF_ADDRCPT_PAR = 0x0000_0080;
-        // SMFIF_SETSYMLIST currently not supported
-        // const SMFIF_SETSYMLIST = 0x0000_0100;
-
+        /// Request a set of macros per stage (SMFIR_SETSYMLIST)
+        ///
+        /// See [`MacroStages`](super::MacroStages).
+        const SMFIF_SETSYMLIST = 0x0000_0100;
     }
 }
 
@@ -34,18 +35,40 @@ impl Default for Capability {
 }
 
 impl Capability {
+    /// All capabilities known to milter protocol `version`.
+    ///
+    /// Versions 2 up to 5 know everything up to quarantine, version 6
+    /// added changing the sender, recipient args and macro requests.
+    #[must_use]
+    pub fn for_version(version: u32) -> Self {
+        match version {
+            0..=1 => Self::empty(),
+            2..=5 => {
+                Self::SMFIF_ADDHDRS
+                    | Self::SMFIF_CHGBODY
+                    | Self::SMFIF_ADDRCPT
+                    | Self::SMFIF_DELRCPT
+                    | Self::SMFIF_CHGHDRS
+                    | Self::SMFIF_QUARANTINE
+            }
+            _ => Self::all(),
+        }
+    }
+
     /// Merge `other` capabilities with `self`
     ///
-    /// Currently no version dependent merging implemented
+    /// Capabilities unknown to `version` are masked out.
     #[must_use]
-    pub fn merge_regarding_version(self, _version: u32, other: Self) -> Self {
+    pub fn merge_regarding_version(self, version: u32, other: Self) -> Self {
         self.intersection(other)
+            .intersection(Self::for_version(version))
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use rstest::rstest;
 
     #[test]
     fn test_create_valid() {
@@ -57,12 +80,43 @@ mod test {
     }
 
     #[test]
-    fn test_create_invalid() {
-        // SMFIF_SETSYMLIST is currently not supported
+    fn test_create_setsymlist() {
         let input: u32 = 0x0000_0100;
 
         let bitflags = Capability::from_bits(input);
 
+        assert_eq!(bitflags, Some(Capability::SMFIF_SETSYMLIST));
+    }
+
+    #[test]
+    fn test_create_invalid() {
+        // Unknown bits are not supported
+        let input: u32 = 0x0000_0200;
+
+        let bitflags = Capability::from_bits(input);
+
         assert!(bitflags.is_none());
     }
+
+    #[rstest]
+    #[case(1, Capability::empty())]
+    #[case(2, Capability::from_bits_truncate(0x3f))]
+    #[case(3, Capability::from_bits_truncate(0x3f))]
+    #[case(4, Capability::from_bits_truncate(0x3f))]
+    #[case(5, Capability::from_bits_truncate(0x3f))]
+    #[case(6, Capability::all())]
+    #[case(7, Capability::all())]
+    fn test_merge_regarding_version(#[case] version: u32, #[case] expected: Capability) {
+        let merged = Capability::all().merge_regarding_version(version, Capability::all());
+
+        assert_eq!(merged, expected);
+    }
+
+    #[test]
+    fn test_merge_intersects() {
+        let merged = (Capability::SMFIF_ADDHDRS | Capability::SMFIF_CHGFROM)
+            .merge_regarding_version(6, Capability::SMFIF_CHGFROM);
+
+        assert_eq!(merged, Capability::SMFIF_CHGFROM);
+    }
 }
@@ -7,6 +7,10 @@ use bytes::{BufMut, BytesMut};
 use itertools::Itertools;
 use num_enum::IntoPrimitive;
 
+use crate::error::STAGE_DECODING;
+use crate::{NotEnoughData, ProtocolError};
+use miltr_utils::ByteParsing;
+
 /// Macro stages requested by this milter server
 #[derive(Clone, PartialEq, Debug, Default)]
 pub struct MacroStages {
@@ -50,6 +54,52 @@ impl MacroStages {
         }
     }
 
+    /// Parse macro stage requests trailing an option negotiation.
+    ///
+    /// Requests for stages unknown to this implementation are ignored.
+    pub(crate) fn parse(mut buffer: BytesMut) -> Result<Self, ProtocolError> {
+        let mut macro_stages = Self::default();
+
+        while !buffer.is_empty() {
+            let Some(stage_id) = buffer.safe_get_u32() else {
+                return Err(NotEnoughData::new(
+                    STAGE_DECODING,
+                    "MacroStages",
+                    "stage id missing",
+                    MacroStage::CODE_SIZE,
+                    buffer.len(),
+                    buffer,
+                )
+                .into());
+            };
+
+            let Some(symbols) = buffer.delimited(0) else {
+                return Err(NotEnoughData::new(
+                    STAGE_DECODING,
+                    "MacroStages",
+                    "missing null byte delimiter after symbols",
+                    1,
+                    0,
+                    buffer,
+                )
+                .into());
+            };
+
+            if stage_id as usize >= MACRO_STAGE_MAX_ID {
+                continue;
+            }
+
+            macro_stages[MacroStage::from(stage_id)].extend(
+                symbols[..]
+                    .split(|&b| b == b' ')
+                    .filter(|s| !s.is_empty())
+                    .map(|s| String::from_utf8_lossy(s).into_owned()),
+            );
+        }
+
+        Ok(macro_stages)
+    }
+
     #[must_use]
     pub(crate) fn len(&self) -> usize {
         let mut accumulator = 0;
@@ -75,6 +125,12 @@ impl MacroStages {
         accumulator
     }
 
+    /// The macros requested for `stage`
+    #[must_use]
+    pub fn symbols(&self, stage: MacroStage) -> &[String] {
+        self.stages.get(stage.as_usize()).map_or(&[], Vec::as_slice)
+    }
+
     /// Request `macros` for the `stage` provided.
     pub fn with_stage<S: ToString>(&mut self, stage: MacroStage, macros: &[S]) {
         let stage = &mut self[stage];
@@ -82,12 +138,23 @@ impl MacroStages {
             stage.push(m.to_string());
         }
     }
+
+    /// Additionally request all macros requested by `other`
+    pub fn merge(&mut self, other: &Self) {
+        for (stage, other) in self.stages.iter_mut().zip(&other.stages) {
+            for symbol in other {
+                if !stage.contains(symbol) {
+                    stage.push(symbol.clone());
+                }
+            }
+        }
+    }
 }
 
 const MACRO_STAGE_MAX_ID: usize = 9;
 
 /// A macro stage index into [`MacroStages`]
-#[derive(Debug, Copy, Clone, IntoPrimitive, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, IntoPrimitive, PartialEq, Eq, Hash)]
 #[repr(u32)]
 pub enum MacroStage {
     /// `SMFIM_CONNECT`
@@ -140,6 +207,29 @@ impl From<u32> for MacroStage {
 impl MacroStage {
     const CODE_SIZE: usize = 4;
 
+    /// The stage macros are sent for, identified by the command `code` of
+    /// that stage.
+    ///
+    /// Returns `None` if `code` does not identify a command macros are sent for.
+    #[must_use]
+    pub fn from_code(code: u8) -> Option<Self> {
+        let stage = match code {
+            b'C' => Self::Connect,
+            b'H' => Self::Helo,
+            b'M' => Self::MailFrom,
+            b'R' => Self::RcptTo,
+            b'T' => Self::Data,
+            b'E' => Self::EndOfBody,
+            b'N' => Self::EndOfHeaders,
+            b'L' => Self::Header,
+            b'B' => Self::Body,
+            b'U' => Self::Unknown,
+            _ => return None,
+        };
+
+        Some(stage)
+    }
+
     fn as_usize(self) -> usize {
         let self_u32: u32 = self.into();
         self_u32 as usize
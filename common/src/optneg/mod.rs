@@ -49,7 +49,7 @@ pub enum CompatibilityError {
     UnsupportedVersion {
         /// The version received
         received: u32,
-        /// The version supported
+        /// The (minimum) version supported
         supported: u32,
     },
 }
@@ -77,6 +77,7 @@ impl OptNeg {
     The remedy is to lower the Postfix milter_protocol version number. Postfix 2.8 and later will automatically turn off protocol features that the application's libmilter library does not expect. */
 
     const VERSION: u32 = 6;
+    const MIN_VERSION: u32 = 2;
 
     const DATA_SIZE: usize = 4 + 4 + 4;
     const CODE: u8 = b'O';
@@ -85,15 +86,22 @@ impl OptNeg {
     ///
     /// This includes comparing versions, the protocol and capabilities.
     ///
+    /// Both ends agree on the lower of their versions, so a newer peer is
+    /// downgraded to the version of `self`. Protocol flags and capabilities
+    /// unknown to the agreed upon version are masked out.
+    ///
     /// # Errors
-    /// This errors when discovering an incompatibility between `self` and `other`
+    /// This errors when discovering an incompatibility between `self` and `other`,
+    /// e.g. if the agreed upon version is lower than 2.
     pub fn merge_compatible(mut self, other: &Self) -> Result<Self, CompatibilityError> {
-        if self.version < other.version {
+        let version = self.version.min(other.version);
+        if version < Self::MIN_VERSION {
             return Err(CompatibilityError::UnsupportedVersion {
-                received: other.version,
-                supported: self.version,
+                received: version,
+                supported: Self::MIN_VERSION,
             });
         }
+        self.version = version;
 
         self.protocol = self
             .protocol
@@ -116,7 +124,7 @@ impl Parsable for OptNeg {
     const CODE: u8 = Self::CODE;
 
     fn parse(mut buffer: BytesMut) -> Result<Self, ProtocolError> {
-        if buffer.len() != Self::DATA_SIZE {
+        if buffer.len() < Self::DATA_SIZE {
             return Err(NotEnoughData::new(
                 STAGE_DECODING,
                 "Option negotiation",
@@ -142,18 +150,17 @@ impl Parsable for OptNeg {
         let protocol: Protocol = Protocol::from_bits_retain(u32::from_be_bytes(protocol));
 
         buffer.advance(12);
+        let macro_stages = MacroStages::parse(buffer)?;
+
         Ok(Self {
             version,
             capabilities,
             protocol,
-            // todo actually parse incoming macros
-            macro_stages: MacroStages::default(),
+            macro_stages,
         })
     }
 }
 
-//const MACRO_TEST: &[u8] = b"\x00\x00\x00\x01j {client_ptr}\x00\x00\x00\x00\x03j {rcpt_addr}\x00";
-
 impl Writable for OptNeg {
     fn write(&self, buffer: &mut BytesMut) {
         buffer.extend_from_slice(&self.version.to_be_bytes());
@@ -181,10 +188,11 @@ mod tests {
 
     use super::*;
     use pretty_assertions::assert_eq;
+    use rstest::rstest;
 
     fn ver_caps_prot() -> ([u8; 4], [u8; 4], [u8; 4]) {
         let version = [0u8, 0u8, 0u8, 6u8];
-        let capabilities = [0u8, 0u8, 0u8, 255u8];
+        let capabilities = [0u8, 0u8, 1u8, 255u8];
         let protocol = [0u8, 0u8, 0u8, 0u8];
 
         (version, capabilities, protocol)
@@ -241,4 +249,140 @@ mod tests {
         assert_eq!(optneg.code(), b'O');
         assert_eq!(expected, buffer.to_vec());
     }
+
+    #[rstest]
+    #[case(2, 0x3f, 0x7f)]
+    #[case(3, 0x3f, 0x17f)]
+    #[case(4, 0x3f, 0x37f)]
+    #[case(5, 0x3f, 0x37f)]
+    #[case(6, 0x1ff, 0x1f_ffff)]
+    #[case(7, 0x1ff, 0x1f_ffff)]
+    #[case(u32::MAX, 0x1ff, 0x1f_ffff)]
+    fn test_merge_compatible_versions(
+        #[case] theirs: u32,
+        #[case] capabilities: u32,
+        #[case] protocol: u32,
+    ) {
+        let ours = OptNeg {
+            protocol: Protocol::all(),
+            ..Default::default()
+        };
+        let theirs = OptNeg {
+            version: theirs,
+            capabilities: Capability::all(),
+            protocol: Protocol::all(),
+            ..Default::default()
+        };
+
+        let merged = ours
+            .merge_compatible(&theirs)
+            .expect("Failed merging compatible versions");
+
+        assert_eq!(merged.version, theirs.version.min(OptNeg::VERSION));
+        assert_eq!(merged.capabilities.bits(), capabilities);
+        assert_eq!(merged.protocol.bits(), protocol);
+    }
+
+    #[rstest]
+    #[case(0)]
+    #[case(1)]
+    fn test_merge_incompatible_versions(#[case] theirs: u32) {
+        let theirs = OptNeg {
+            version: theirs,
+            ..Default::default()
+        };
+
+        let err = OptNeg::default()
+            .merge_compatible(&theirs)
+            .expect_err("Merged an unsupported version");
+
+        assert!(matches!(
+            err,
+            CompatibilityError::UnsupportedVersion { received, supported: 2 } if received == theirs.version
+        ));
+    }
+
+    #[test]
+    fn test_merge_compatible_downgrades_ours() {
+        let ours = OptNeg {
+            version: 2,
+            ..Default::default()
+        };
+
+        let merged = ours
+            .merge_compatible(&OptNeg::default())
+            .expect("Failed merging compatible versions");
+
+        assert_eq!(merged.version, 2);
+        assert_eq!(merged.capabilities, Capability::for_version(2));
+    }
+
+    #[test]
+    fn test_parse_optneg_macros() {
+        let mut buffer = BytesMut::new();
+        let (version, capabilities, protocol) = ver_caps_prot();
+        buffer.extend_from_slice(&version);
+        buffer.extend_from_slice(&capabilities);
+        buffer.extend_from_slice(&protocol);
+        buffer.extend_from_slice(
+            b"\x00\x00\x00\x00j {client_ptr}\x00\x00\x00\x00\x03{rcpt_addr}\x00",
+        );
+
+        let optneg = OptNeg::parse(buffer).expect("Failed parsing optneg with macros");
+
+        assert_eq!(
+            optneg.macro_stages[MacroStage::Connect],
+            vec!["j", "{client_ptr}"]
+        );
+        assert_eq!(optneg.macro_stages[MacroStage::RcptTo], vec!["{rcpt_addr}"]);
+        assert!(optneg.macro_stages[MacroStage::Helo].is_empty());
+    }
+
+    #[test]
+    fn test_roundtrip_optneg_macros() {
+        let mut optneg = OptNeg::default();
+        optneg
+            .macro_stages
+            .with_stage(MacroStage::Connect, &["j", "{daemon_addr}"]);
+        optneg.macro_stages.with_stage(MacroStage::Helo, &["z"]);
+        optneg
+            .macro_stages
+            .with_stage(MacroStage::EndOfBody, &["{i}", "{auth_authen}"]);
+
+        let mut buffer = BytesMut::new();
+        optneg.write(&mut buffer);
+        assert_eq!(optneg.len(), buffer.len());
+
+        let parsed = OptNeg::parse(buffer).expect("Failed parsing written optneg");
+
+        assert_eq!(parsed, optneg);
+    }
+
+    #[test]
+    fn test_parse_optneg_macros_unterminated() {
+        let mut buffer = BytesMut::new();
+        let (version, capabilities, protocol) = ver_caps_prot();
+        buffer.extend_from_slice(&version);
+        buffer.extend_from_slice(&capabilities);
+        buffer.extend_from_slice(&protocol);
+        buffer.extend_from_slice(b"\x00\x00\x00\x00j {client_ptr}");
+
+        let err = OptNeg::parse(buffer).expect_err("Parsed unterminated macros");
+
+        assert!(matches!(err, ProtocolError::NotEnoughData(_)));
+    }
+
+    #[test]
+    fn test_parse_optneg_unknown_macro_stage() {
+        let mut buffer = BytesMut::new();
+        let (version, capabilities, protocol) = ver_caps_prot();
+        buffer.extend_from_slice(&version);
+        buffer.extend_from_slice(&capabilities);
+        buffer.extend_from_slice(&protocol);
+        buffer.extend_from_slice(b"\x00\x00\x00\x2a{foo}\x00\x00\x00\x00\x01z\x00");
+
+        let optneg = OptNeg::parse(buffer).expect("Failed parsing optneg with macros");
+
+        assert_eq!(optneg.macro_stages[MacroStage::Helo], vec!["z"]);
+    }
 }
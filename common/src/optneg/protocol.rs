@@ -86,7 +86,7 @@ impl Protocol {
             Command::EndOfHeader(_) => self.contains(Protocol::NO_END_OF_HEADER),
             Command::Data(_) => self.contains(Protocol::NO_DATA),
             Command::Body(_) => self.contains(Protocol::NO_BODY),
-            Command::EndOfBody(_) => false,
+            Command::EndOfBody(_) | Command::Macro(_) => false,
             Command::Unknown(_) => self.contains(Protocol::NO_UNKNOWN),
         }
     }
@@ -105,15 +105,74 @@ impl Protocol {
             Command::Body(_) => self.contains(Protocol::NR_BODY),
             Command::EndOfBody(_) => false,
             Command::Unknown(_) => self.contains(Protocol::NR_UNKNOWN),
+            // Macros are never answered
+            Command::Macro(_) => true,
+        }
+    }
+
+    /// All protocol flags known to milter protocol `version`.
+    ///
+    /// This follows the masks postfix applies per version: Version 2 only
+    /// knows how to skip commands, 3 adds skipping unknown commands, 4 (and 5)
+    /// adds skipping data. Version 6 knows all, including 'no reply' flags.
+    #[must_use]
+    pub fn for_version(version: u32) -> Self {
+        let v2 = Self::NO_CONNECT
+            | Self::NO_HELO
+            | Self::NO_MAIL
+            | Self::NO_RECIPIENT
+            | Self::NO_BODY
+            | Self::NO_HEADER
+            | Self::NO_END_OF_HEADER;
+        let v3 = v2 | Self::NO_UNKNOWN;
+        let v4 = v3 | Self::NO_DATA;
+
+        match version {
+            0..=1 => Self::empty(),
+            2 => v2,
+            3 => v3,
+            4..=5 => v4,
+            _ => Self::all(),
         }
     }
 
     /// Merge `other` protocol with `self`
     ///
-    /// Currently no version dependent merging implemented
+    /// Protocol flags unknown to `version` are masked out.
     #[must_use]
-    pub fn merge_regarding_version(self, _version: u32, other: Self) -> Self {
-        // No version dependent merging implemented yet
+    pub fn merge_regarding_version(self, version: u32, other: Self) -> Self {
         self.intersection(other)
+            .intersection(Self::for_version(version))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(1, Protocol::empty())]
+    #[case(2, Protocol::from_bits_truncate(0x7f))]
+    #[case(3, Protocol::from_bits_truncate(0x17f))]
+    #[case(4, Protocol::from_bits_truncate(0x37f))]
+    #[case(5, Protocol::from_bits_truncate(0x37f))]
+    #[case(6, Protocol::all())]
+    #[case(7, Protocol::all())]
+    fn test_merge_regarding_version(#[case] version: u32, #[case] expected: Protocol) {
+        let merged = Protocol::all().merge_regarding_version(version, Protocol::all());
+
+        assert_eq!(merged, expected);
+    }
+
+    #[test]
+    fn test_merge_masks_no_reply_before_v6() {
+        let ours = Protocol::NO_HELO | Protocol::NR_HEADER | Protocol::SMFIP_SKIP;
+
+        assert_eq!(
+            ours.merge_regarding_version(4, Protocol::all()),
+            Protocol::NO_HELO
+        );
+        assert_eq!(ours.merge_regarding_version(6, Protocol::all()), ours);
     }
 }
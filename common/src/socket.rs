@@ -0,0 +1,229 @@
+//! Socket specifications as used by sendmail and postfix.
+//!
+//! Both MTAs configure milters using strings like `inet:10025@127.0.0.1`
+//! or `unix:/run/milter.sock`. [`SocketSpec`] parses and displays these:
+//!
+//! ```
+//! # use miltr_common::socket::SocketSpec;
+//! let spec: SocketSpec = "inet6:10025@[::1]".parse().unwrap();
+//! assert_eq!(
+//!     spec,
+//!     SocketSpec::Inet6 {
+//!         host: Some("::1".to_string()),
+//!         port: 10025
+//!     }
+//! );
+//! assert_eq!(spec.to_string(), "inet6:10025@[::1]");
+//! ```
+
+use std::{fmt, path::PathBuf, str::FromStr};
+#[cfg(feature = "tokio")]
+use std::{io, net::SocketAddr};
+
+use thiserror::Error;
+
+/// Where a milter listens, or the MTA connects to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SocketSpec {
+    /// An ipv4 tcp socket, `inet:port@host`.
+    ///
+    /// The postfix form `inet:host:port` is accepted as well.
+    Inet {
+        /// The host name or address, `None` for any address
+        host: Option<String>,
+        /// The tcp port
+        port: u16,
+    },
+    /// An ipv6 tcp socket, `inet6:port@host`
+    Inet6 {
+        /// The host name or address without brackets, `None` for any address
+        host: Option<String>,
+        /// The tcp port
+        port: u16,
+    },
+    /// A unix domain socket, `unix:/path` or `local:/path`.
+    ///
+    /// A plain absolute path is accepted as well.
+    Unix(PathBuf),
+}
+
+/// Error parsing a [`SocketSpec`]
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SocketSpecError {
+    /// The protocol is neither `inet`, `inet6`, `unix` nor `local`
+    #[error("Unknown socket protocol '{0}'")]
+    UnknownProtocol(String),
+    /// Nothing follows the protocol
+    #[error("Missing the socket address after '{0}:'")]
+    MissingAddress(String),
+    /// The port is not a number
+    #[error("Invalid socket port '{0}'")]
+    InvalidPort(String),
+}
+
+impl FromStr for SocketSpec {
+    type Err = SocketSpecError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let Some((protocol, address)) = spec.split_once(':') else {
+            if spec.starts_with('/') {
+                return Ok(Self::Unix(spec.into()));
+            }
+            return Err(SocketSpecError::UnknownProtocol(spec.to_string()));
+        };
+        if address.is_empty() {
+            return Err(SocketSpecError::MissingAddress(protocol.to_string()));
+        }
+
+        match protocol {
+            "inet" => {
+                let (host, port) = parse_inet(address)?;
+                Ok(Self::Inet { host, port })
+            }
+            "inet6" => {
+                let (host, port) = parse_inet(address)?;
+                Ok(Self::Inet6 { host, port })
+            }
+            "unix" | "local" => Ok(Self::Unix(address.into())),
+            protocol => Err(SocketSpecError::UnknownProtocol(protocol.to_string())),
+        }
+    }
+}
+
+/// Parse `port@host`, `host:port`, `[host]:port` or just `port`
+fn parse_inet(address: &str) -> Result<(Option<String>, u16), SocketSpecError> {
+    let (host, port) = if let Some((port, host)) = address.split_once('@') {
+        (Some(host), port)
+    } else if let Some((host, port)) = address
+        .strip_prefix('[')
+        .and_then(|address| address.split_once("]:"))
+    {
+        (Some(host), port)
+    } else if let Some((host, port)) = address.split_once(':').filter(|(host, _)| !host.is_empty())
+    {
+        (Some(host), port)
+    } else {
+        (None, address)
+    };
+
+    let port = port
+        .parse()
+        .map_err(|_| SocketSpecError::InvalidPort(port.to_string()))?;
+    let host = host
+        .map(|host| {
+            host.strip_prefix('[')
+                .and_then(|host| host.strip_suffix(']'))
+                .unwrap_or(host)
+        })
+        .filter(|host| !host.is_empty())
+        .map(ToString::to_string);
+
+    Ok((host, port))
+}
+
+/// Resolve `host`, keeping only addresses of the family accepted by `family`,
+/// e.g. [`SocketAddr::is_ipv4`] for [`SocketSpec::Inet`].
+///
+/// # Errors
+/// - if resolving `host` fails
+/// - [`io::ErrorKind::AddrNotAvailable`] if no address of the family is left
+#[cfg(feature = "tokio")]
+pub async fn resolve(
+    host: &str,
+    port: u16,
+    family: fn(&SocketAddr) -> bool,
+) -> io::Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await?
+        .filter(family)
+        .collect();
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            format!("{host} does not resolve to an address of the socket's family"),
+        ));
+    }
+
+    Ok(addrs)
+}
+
+impl fmt::Display for SocketSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inet { host: None, port } => write!(f, "inet:{port}"),
+            Self::Inet {
+                host: Some(host),
+                port,
+            } => write!(f, "inet:{port}@{host}"),
+            Self::Inet6 { host: None, port } => write!(f, "inet6:{port}"),
+            Self::Inet6 {
+                host: Some(host),
+                port,
+            } => write!(f, "inet6:{port}@[{host}]"),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    fn inet(host: Option<&str>, port: u16) -> SocketSpec {
+        SocketSpec::Inet {
+            host: host.map(ToString::to_string),
+            port,
+        }
+    }
+
+    fn inet6(host: Option<&str>, port: u16) -> SocketSpec {
+        SocketSpec::Inet6 {
+            host: host.map(ToString::to_string),
+            port,
+        }
+    }
+
+    #[rstest]
+    #[case("inet:10025@127.0.0.1", inet(Some("127.0.0.1"), 10025))]
+    #[case("inet:10025@localhost", inet(Some("localhost"), 10025))]
+    #[case("inet:10025@[127.0.0.1]", inet(Some("127.0.0.1"), 10025))]
+    #[case("inet:127.0.0.1:10025", inet(Some("127.0.0.1"), 10025))]
+    #[case("inet:10025", inet(None, 10025))]
+    #[case("inet6:10025@[::1]", inet6(Some("::1"), 10025))]
+    #[case("inet6:10025@::1", inet6(Some("::1"), 10025))]
+    #[case("inet6:[::1]:10025", inet6(Some("::1"), 10025))]
+    #[case("inet6:10025", inet6(None, 10025))]
+    #[case("unix:/run/milter.sock", SocketSpec::Unix("/run/milter.sock".into()))]
+    #[case("local:/run/milter.sock", SocketSpec::Unix("/run/milter.sock".into()))]
+    #[case("/run/milter.sock", SocketSpec::Unix("/run/milter.sock".into()))]
+    fn test_parse(#[case] spec: &str, #[case] expected: SocketSpec) {
+        let parsed: SocketSpec = spec.parse().expect("Failed parsing spec");
+        assert_eq!(parsed, expected);
+
+        let reparsed: SocketSpec = parsed.to_string().parse().expect("Failed parsing display");
+        assert_eq!(reparsed, expected);
+    }
+
+    #[rstest]
+    #[case(inet(Some("127.0.0.1"), 10025), "inet:10025@127.0.0.1")]
+    #[case(inet(None, 10025), "inet:10025")]
+    #[case(inet6(Some("::1"), 10025), "inet6:10025@[::1]")]
+    #[case(SocketSpec::Unix("/run/milter.sock".into()), "unix:/run/milter.sock")]
+    fn test_display(#[case] spec: SocketSpec, #[case] expected: &str) {
+        assert_eq!(spec.to_string(), expected);
+    }
+
+    #[rstest]
+    #[case("tcp:10025@localhost", SocketSpecError::UnknownProtocol("tcp".to_string()))]
+    #[case("milter.sock", SocketSpecError::UnknownProtocol("milter.sock".to_string()))]
+    #[case("unix:", SocketSpecError::MissingAddress("unix".to_string()))]
+    #[case("inet:smtp@localhost", SocketSpecError::InvalidPort("smtp".to_string()))]
+    #[case("inet:70000@localhost", SocketSpecError::InvalidPort("70000".to_string()))]
+    #[case("inet:localhost", SocketSpecError::InvalidPort("localhost".to_string()))]
+    fn test_parse_invalid(#[case] spec: &str, #[case] expected: SocketSpecError) {
+        let err = spec.parse::<SocketSpec>().expect_err("Parsed invalid spec");
+        assert_eq!(err, expected);
+    }
+}
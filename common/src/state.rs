@@ -0,0 +1,235 @@
+//! Track the state of a milter session to detect out of order commands
+//!
+//! The client sends commands in the order they appear in the smtp session.
+//! Stages disabled during option negotiation are skipped, so every stage
+//! may be left out, but a session never moves backwards, except when the
+//! client aborts a message or re-uses the connection.
+
+use thiserror::Error;
+
+use crate::{commands::Command, decoding::ClientCommand};
+
+/// The stage a milter session is in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SessionState {
+    /// Waiting for option negotiation
+    #[default]
+    Initial,
+    /// Options are negotiated, waiting for the smtp connection
+    Negotiated,
+    /// Connection information or helo received
+    Connected,
+    /// Sender or recipients of a message received
+    Envelope,
+    /// Headers of a message received
+    Headers,
+    /// The body of a message received
+    Body,
+    /// A message was completely received
+    EndOfMessage,
+}
+
+/// The kind of a command sent by the client
+#[cfg_attr(feature = "metrics", derive(strum::IntoStaticStr))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandKind {
+    /// Option negotiation
+    OptNeg,
+    /// Macros for the following command
+    Macro,
+    /// Smtp connection information
+    Connect,
+    /// Smtp helo
+    Helo,
+    /// Envelope sender
+    Mail,
+    /// Envelope recipient
+    Recipient,
+    /// Smtp data
+    Data,
+    /// A single header
+    Header,
+    /// All headers sent
+    EndOfHeader,
+    /// A body chunk
+    Body,
+    /// The message is complete
+    EndOfBody,
+    /// An unknown smtp command
+    Unknown,
+    /// Abort the current message
+    Abort,
+    /// Close the connection
+    Quit,
+    /// Start a new session on this connection
+    QuitNc,
+}
+
+/// A command was received in a state it is not valid in
+#[derive(Debug, Clone, Error)]
+#[error("Received {command:?} out of order in state {state:?}")]
+pub struct OutOfOrder {
+    /// The offending command
+    pub command: CommandKind,
+    /// The state the session was in
+    pub state: SessionState,
+}
+
+impl SessionState {
+    /// Whether `command` is valid in this state
+    #[must_use]
+    pub fn allows(self, command: CommandKind) -> bool {
+        use CommandKind as C;
+        use SessionState as S;
+
+        match command {
+            C::Quit => true,
+            C::OptNeg => self == S::Initial,
+            _ if self == S::Initial => false,
+            C::Macro | C::Abort | C::QuitNc => true,
+            C::Connect => self == S::Negotiated,
+            // Again after a transaction, e.g. EHLO after STARTTLS
+            C::Helo => matches!(self, S::Negotiated | S::Connected | S::EndOfMessage),
+            C::Mail => matches!(self, S::Negotiated | S::Connected | S::EndOfMessage),
+            C::Unknown => matches!(
+                self,
+                S::Negotiated | S::Connected | S::Envelope | S::EndOfMessage
+            ),
+            C::Recipient | C::Data => self == S::Envelope,
+            C::Header | C::EndOfHeader => matches!(self, S::Envelope | S::Headers),
+            C::Body | C::EndOfBody => matches!(self, S::Envelope | S::Headers | S::Body),
+        }
+    }
+
+    /// The state after `command` was received in this state
+    #[must_use]
+    pub fn after(self, command: CommandKind) -> Self {
+        match command {
+            // Back to the smtp connection, if there was one
+            CommandKind::Abort if self == Self::Negotiated => self,
+            CommandKind::OptNeg | CommandKind::QuitNc => Self::Negotiated,
+            CommandKind::Connect | CommandKind::Helo | CommandKind::Abort => Self::Connected,
+            CommandKind::Mail | CommandKind::Recipient => Self::Envelope,
+            CommandKind::Data | CommandKind::Header => Self::Headers,
+            CommandKind::EndOfHeader | CommandKind::Body => Self::Body,
+            CommandKind::EndOfBody => Self::EndOfMessage,
+            CommandKind::Macro | CommandKind::Unknown | CommandKind::Quit => self,
+        }
+    }
+
+    /// Move on to the state after `command`.
+    ///
+    /// The state is moved on even if `command` was out of order, so following
+    /// commands are checked against what the client sent.
+    ///
+    /// # Errors
+    /// Returns [`OutOfOrder`] if `command` is not valid in this state.
+    pub fn advance(&mut self, command: CommandKind) -> Result<(), OutOfOrder> {
+        let state = *self;
+        *self = state.after(command);
+
+        if state.allows(command) {
+            Ok(())
+        } else {
+            Err(OutOfOrder { command, state })
+        }
+    }
+}
+
+impl From<&ClientCommand> for CommandKind {
+    fn from(command: &ClientCommand) -> Self {
+        match command {
+            ClientCommand::Abort(_) => Self::Abort,
+            ClientCommand::OptNeg(_) => Self::OptNeg,
+            ClientCommand::Quit(_) => Self::Quit,
+            ClientCommand::QuitNc(_) => Self::QuitNc,
+            ClientCommand::Macro(_) => Self::Macro,
+            ClientCommand::Unknown(_) => Self::Unknown,
+            ClientCommand::Connect(_) => Self::Connect,
+            ClientCommand::Helo(_) => Self::Helo,
+            ClientCommand::Mail(_) => Self::Mail,
+            ClientCommand::Recipient(_) => Self::Recipient,
+            ClientCommand::Header(_) => Self::Header,
+            ClientCommand::EndOfHeader(_) => Self::EndOfHeader,
+            ClientCommand::Data(_) => Self::Data,
+            ClientCommand::Body(_) => Self::Body,
+            ClientCommand::EndOfBody(_) => Self::EndOfBody,
+        }
+    }
+}
+
+impl From<&Command> for CommandKind {
+    fn from(command: &Command) -> Self {
+        match command {
+            Command::Connect(_) => Self::Connect,
+            Command::Helo(_) => Self::Helo,
+            Command::Mail(_) => Self::Mail,
+            Command::Recipient(_) => Self::Recipient,
+            Command::Header(_) => Self::Header,
+            Command::EndOfHeader(_) => Self::EndOfHeader,
+            Command::Data(_) => Self::Data,
+            Command::Body(_) => Self::Body,
+            Command::EndOfBody(_) => Self::EndOfBody,
+            Command::Unknown(_) => Self::Unknown,
+            Command::Macro(_) => Self::Macro,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    use CommandKind as C;
+    use SessionState as S;
+
+    fn run(commands: &[CommandKind]) -> Result<SessionState, OutOfOrder> {
+        let mut state = SessionState::default();
+        for command in commands {
+            state.advance(*command)?;
+        }
+        Ok(state)
+    }
+
+    #[rstest]
+    #[case::full(&[C::OptNeg, C::Macro, C::Connect, C::Helo, C::Mail, C::Recipient, C::Recipient, C::Data, C::Header, C::Header, C::EndOfHeader, C::Body, C::Body, C::EndOfBody], S::EndOfMessage)]
+    #[case::stages_disabled(&[C::OptNeg, C::Mail, C::Body, C::EndOfBody], S::EndOfMessage)]
+    #[case::next_mail(&[C::OptNeg, C::Connect, C::Mail, C::EndOfBody, C::Mail], S::Envelope)]
+    #[case::abort(&[C::OptNeg, C::Connect, C::Mail, C::Header, C::Abort, C::Helo], S::Connected)]
+    #[case::quit_nc(&[C::OptNeg, C::Connect, C::Mail, C::EndOfBody, C::QuitNc, C::Connect], S::Connected)]
+    #[case::quit(&[C::OptNeg, C::Mail, C::Quit], S::Envelope)]
+    #[case::helo_after_message(&[C::OptNeg, C::Helo, C::Mail, C::EndOfBody, C::Helo, C::Mail], S::Envelope)]
+    fn test_in_order(#[case] commands: &[CommandKind], #[case] expected: SessionState) {
+        assert_eq!(run(commands).expect("Commands are in order"), expected);
+    }
+
+    #[rstest]
+    #[case::before_optneg(&[C::Connect], C::Connect, S::Initial)]
+    #[case::body_before_mail(&[C::OptNeg, C::Connect, C::Body], C::Body, S::Connected)]
+    #[case::second_optneg(&[C::OptNeg, C::Mail, C::OptNeg], C::OptNeg, S::Envelope)]
+    #[case::recipient_after_data(&[C::OptNeg, C::Mail, C::Data, C::Recipient], C::Recipient, S::Headers)]
+    #[case::header_after_body(&[C::OptNeg, C::Mail, C::Body, C::Header], C::Header, S::Body)]
+    #[case::connect_twice(&[C::OptNeg, C::Connect, C::Connect], C::Connect, S::Connected)]
+    #[case::helo_in_message(&[C::OptNeg, C::Mail, C::Helo], C::Helo, S::Envelope)]
+    fn test_out_of_order(
+        #[case] commands: &[CommandKind],
+        #[case] command: CommandKind,
+        #[case] state: SessionState,
+    ) {
+        let err = run(commands).expect_err("Commands are out of order");
+
+        assert_eq!(err.command, command);
+        assert_eq!(err.state, state);
+    }
+
+    #[test]
+    fn test_advance_out_of_order() {
+        let mut state = S::Negotiated;
+
+        state.advance(C::Body).expect_err("Body before mail");
+        assert_eq!(state, S::Body);
+        state.advance(C::EndOfBody).expect("End of body after body");
+    }
+}
@@ -10,7 +10,7 @@ use miltr_common::{
     commands::{Body, Header},
     modifications::{body::ReplaceBody, headers::ChangeHeader, ModificationResponse},
 };
-use miltr_server::{Milter, Server};
+use miltr_server::{Context, Milter, Server};
 
 #[derive(Debug, Default)]
 struct ModMilter {
@@ -22,17 +22,20 @@ struct ModMilter {
 impl Milter for ModMilter {
     type Error = &'static str;
 
-    async fn header(&mut self, header: Header) -> Result<Action, Self::Error> {
+    async fn header(&mut self, _context: &Context, header: Header) -> Result<Action, Self::Error> {
         self.headers.push(header);
         Ok(Continue.into())
     }
 
-    async fn body(&mut self, body: Body) -> Result<Action, Self::Error> {
+    async fn body(&mut self, _context: &Context, body: Body) -> Result<Action, Self::Error> {
         self.body_parts.push(body);
         Ok(Continue.into())
     }
 
-    async fn end_of_body(&mut self) -> Result<ModificationResponse, Self::Error> {
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
         let mut builder = ModificationResponse::builder();
 
         if let Some(last_header) = self.headers.last() {
@@ -53,7 +56,7 @@ impl Milter for ModMilter {
         Ok(builder.build(Replycode::new([1, 2, 3], [4, 5, 6], "What a message!")))
     }
 
-    async fn abort(&mut self) -> Result<(), Self::Error> {
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
         println!("\n======== ABORT ========");
         Ok(())
     }
@@ -8,7 +8,7 @@ use miltr_common::{
     commands::{Body, Recipient},
     optneg::{Capability, OptNeg, Protocol},
 };
-use miltr_server::{Error, Milter, Server};
+use miltr_server::{Context, Error, Milter, Server};
 use tokio::net::TcpListener;
 use tokio_util::compat::TokioAsyncReadCompatExt;
 
@@ -48,7 +48,7 @@ impl Milter for PrintBodyMilter {
 
     /// This example errors on the rcpt command: Option negotiation told postfix
     /// to omit this command, this is just to demonstrate you can skip commands.
-    async fn rcpt(&mut self, _: Recipient) -> Result<Action, Self::Error> {
+    async fn rcpt(&mut self, _context: &Context, _: Recipient) -> Result<Action, Self::Error> {
         println!("This should not be printed as optneg said SMFIP_NORCPT");
 
         Err("Got unexpected command")
@@ -56,14 +56,14 @@ impl Milter for PrintBodyMilter {
 
     /// The body command might be received multiple times, so we push all the
     /// received bodies on a vec.
-    async fn body(&mut self, body: Body) -> Result<Action, Self::Error> {
+    async fn body(&mut self, _context: &Context, body: Body) -> Result<Action, Self::Error> {
         self.body_parts.push(body);
         Ok(Continue.into())
     }
 
     /// Receiving an abort denotes the point in time we will have the most
     /// body parts. The client must not send more afterwards.
-    async fn abort(&mut self) -> Result<(), Self::Error> {
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
         println!("\n======== ABORT ========");
 
         println!("Captured body:");
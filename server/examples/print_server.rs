@@ -12,7 +12,7 @@ use miltr_common::{
     modifications::ModificationResponse,
     optneg::OptNeg,
 };
-use miltr_server::{Error, Milter, Server};
+use miltr_server::{Context, Error, Milter, Server};
 
 struct PrintMilter;
 
@@ -27,7 +27,11 @@ impl Milter for PrintMilter {
         Ok(opts)
     }
 
-    async fn connect(&mut self, connect_info: Connect) -> Result<Action, Self::Error> {
+    async fn connect(
+        &mut self,
+        _context: &Context,
+        connect_info: Connect,
+    ) -> Result<Action, Self::Error> {
         println!("\n======== CONNECT ========");
         println!("  hostname: {}", connect_info.hostname());
         println!(
@@ -39,13 +43,13 @@ impl Milter for PrintMilter {
         Ok(Continue.into())
     }
 
-    async fn helo(&mut self, helo: Helo) -> Result<Action, Self::Error> {
+    async fn helo(&mut self, _context: &Context, helo: Helo) -> Result<Action, Self::Error> {
         println!("\n======== HELO ========");
         println!("  hostname: {}", helo.helo());
         Ok(Continue.into())
     }
 
-    async fn mail(&mut self, mail: Mail) -> Result<Action, Self::Error> {
+    async fn mail(&mut self, _context: &Context, mail: Mail) -> Result<Action, Self::Error> {
         println!("\n======== MAIL ========");
         println!("  sender: {}", mail.sender());
         for arg in mail.esmtp_args() {
@@ -54,7 +58,11 @@ impl Milter for PrintMilter {
         Ok(Continue.into())
     }
 
-    async fn rcpt(&mut self, recipient: Recipient) -> Result<Action, Self::Error> {
+    async fn rcpt(
+        &mut self,
+        _context: &Context,
+        recipient: Recipient,
+    ) -> Result<Action, Self::Error> {
         println!("\n======== RCPT ========");
         println!("  recipient: {:?}", recipient.recipient());
         for arg in recipient.esmtp_args() {
@@ -63,56 +71,59 @@ impl Milter for PrintMilter {
         Ok(Continue.into())
     }
 
-    async fn data(&mut self) -> Result<Action, Self::Error> {
+    async fn data(&mut self, _context: &Context) -> Result<Action, Self::Error> {
         println!("\n======== DATA ========");
         Ok(Continue.into())
     }
 
-    async fn header(&mut self, header: Header) -> Result<Action, Self::Error> {
+    async fn header(&mut self, _context: &Context, header: Header) -> Result<Action, Self::Error> {
         println!("\n======== HEADER ========");
         println!("  name: {}", header.name());
         println!("  value: {}", header.value());
         Ok(Continue.into())
     }
 
-    async fn end_of_header(&mut self) -> Result<Action, Self::Error> {
+    async fn end_of_header(&mut self, _context: &Context) -> Result<Action, Self::Error> {
         println!("\n======== EOH ========");
         Ok(Continue.into())
     }
 
-    async fn body(&mut self, body: Body) -> Result<Action, Self::Error> {
+    async fn body(&mut self, _context: &Context, body: Body) -> Result<Action, Self::Error> {
         println!("\n======== BODY ========");
         println!("  body part: {}", String::from_utf8_lossy(body.as_bytes()));
         Ok(Continue.into())
     }
 
-    async fn end_of_body(&mut self) -> Result<ModificationResponse, Self::Error> {
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
         println!("\n======== END OF BODY ========");
         Ok(ModificationResponse::empty_continue())
     }
 
-    async fn abort(&mut self) -> Result<(), Self::Error> {
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
         println!("\n======== ABORT ========");
         Ok(())
     }
 
-    async fn quit(&mut self) -> Result<(), Self::Error> {
+    async fn quit(&mut self, _context: &Context) -> Result<(), Self::Error> {
         println!("\n======== QUIT ========");
         Ok(())
     }
 
-    async fn quit_nc(&mut self) -> Result<(), Self::Error> {
+    async fn quit_nc(&mut self, _context: &Context) -> Result<(), Self::Error> {
         println!("\n======== QUIT NEXT CONNECTION ========");
         Ok(())
     }
 
-    async fn unknown(&mut self, cmd: Unknown) -> Result<Action, Self::Error> {
+    async fn unknown(&mut self, _context: &Context, cmd: Unknown) -> Result<Action, Self::Error> {
         println!("\n======== UNKNOWN ========");
         println!("  Raw: {cmd:?}");
         Ok(Continue.into())
     }
 
-    async fn macro_(&mut self, macro_: Macro) -> Result<(), Self::Error> {
+    async fn macro_(&mut self, _context: &Context, macro_: Macro) -> Result<(), Self::Error> {
         println!("\n======== MACRO ========");
         println!(
             "  code: {}",
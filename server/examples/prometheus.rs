@@ -0,0 +1,67 @@
+//! An example serving a milter while exposing it's metrics to prometheus.
+//!
+//! Metrics are served on `http://127.0.0.1:9000/metrics` by default.
+use std::{env, net::SocketAddr};
+
+use async_trait::async_trait;
+use metrics_exporter_prometheus::PrometheusBuilder;
+use miette::{IntoDiagnostic, Result, WrapErr};
+use miltr_common::{
+    actions::{Action, Continue, Reject},
+    commands::Recipient,
+    metrics,
+};
+use miltr_server::{
+    serve::{Listener, Serve},
+    Context, Milter,
+};
+
+/// Rejects recipients at example.org
+#[derive(Debug, Default)]
+struct RejectMilter;
+
+#[async_trait]
+impl Milter for RejectMilter {
+    type Error = &'static str;
+
+    async fn rcpt(&mut self, _context: &Context, rcpt: Recipient) -> Result<Action, Self::Error> {
+        if rcpt.recipient().ends_with("@example.org>") {
+            return Ok(Reject.into());
+        }
+        Ok(Continue.into())
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let metrics_addr: SocketAddr = env::var("METRICS_ADDR")
+        .unwrap_or("127.0.0.1:9000".to_string())
+        .parse()
+        .into_diagnostic()
+        .wrap_err("Invalid metrics addr")?;
+    PrometheusBuilder::new()
+        .with_http_listener(metrics_addr)
+        .install()
+        .into_diagnostic()
+        .wrap_err("Failed to install the prometheus exporter")?;
+    metrics::describe();
+    println!("Serving metrics on http://{metrics_addr}/metrics");
+
+    let addr = env::var("LISTEN_ADDR").unwrap_or("0.0.0.0:8080".to_string());
+    let listener = Listener::bind_tcp(&addr)
+        .await
+        .into_diagnostic()
+        .wrap_err("Failed to bind to addr")?;
+
+    Serve::new(RejectMilter::default)
+        .run_until(listener, async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await
+        .into_diagnostic()
+        .wrap_err("Failed accepting connections")
+}
@@ -0,0 +1,68 @@
+//! An example serving a milter per connection until ctrl-c is received.
+use std::env;
+
+use async_trait::async_trait;
+use miette::{IntoDiagnostic, Result, WrapErr};
+use miltr_common::{
+    actions::{Action, Continue},
+    commands::Recipient,
+};
+use miltr_server::{
+    serve::{Listener, Serve, UnixSocket},
+    Context, Milter,
+};
+
+#[derive(Debug, Default)]
+struct CountRcptMilter {
+    recipients: usize,
+}
+
+#[async_trait]
+impl Milter for CountRcptMilter {
+    type Error = &'static str;
+
+    async fn rcpt(&mut self, context: &Context, _rcpt: Recipient) -> Result<Action, Self::Error> {
+        self.recipients += 1;
+        println!(
+            "Session {}: {} recipients",
+            context.session_id(),
+            self.recipients
+        );
+
+        Ok(Continue.into())
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        self.recipients = 0;
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // Either listen on a unix socket or a tcp socket
+    let listener = if let Ok(path) = env::var("LISTEN_SOCKET") {
+        UnixSocket::new(path)
+            .with_mode(0o660)
+            .bind()
+            .into_diagnostic()
+            .wrap_err("Failed to bind unix socket")?
+    } else {
+        let addr = env::var("LISTEN_ADDR").unwrap_or("0.0.0.0:8080".to_string());
+        Listener::bind_tcp(&addr)
+            .await
+            .into_diagnostic()
+            .wrap_err("Failed to bind to addr")?
+    };
+    println!("Bound to socket");
+
+    Serve::new(CountRcptMilter::default)
+        .with_max_connections(16)
+        .run_until(listener, async {
+            tokio::signal::ctrl_c().await.ok();
+            println!("Received ctrl-c, draining connections");
+        })
+        .await
+        .into_diagnostic()
+        .wrap_err("Failed accepting connections")
+}
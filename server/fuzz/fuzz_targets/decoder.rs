@@ -7,14 +7,14 @@ use bytes::BytesMut;
 use miltr_common::{
         actions::{Action, Continue},
 };
-use miltr_server::{Milter, fuzzing::fuzz_parse};
+use miltr_server::{Context, Milter, fuzzing::fuzz_parse};
 
 struct DecodingMilter;
 
 #[async_trait]
 impl Milter for DecodingMilter {
     type Error = &'static str;
-    async fn abort(&mut self) -> Result<(), Self::Error> {
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
         Ok(())
     }
 }
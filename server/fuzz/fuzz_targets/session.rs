@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use bytes::BytesMut;
+use miltr_server::fuzzing::fuzz_session;
+
+fuzz_target!(|data: &[u8]| {
+    let mut buffer = BytesMut::from(data);
+    let _state = fuzz_session(&mut buffer);
+});
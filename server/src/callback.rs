@@ -0,0 +1,53 @@
+//! Run milter callbacks, isolating their failures from the connection
+
+use std::{any::Any, panic::AssertUnwindSafe, time::Duration};
+
+use futures::{Future, FutureExt};
+
+use crate::{timeout::Timeouts, Error};
+
+/// How a milter callback finished
+pub(crate) enum Outcome<T, E> {
+    /// The callback returned `Ok`
+    Done(T),
+    /// The callback returned `Err`
+    Failed(E),
+    /// The callback did not finish within the callback timeout
+    TimedOut(Duration),
+}
+
+/// Await a milter `callback`, limited by the callback timeout.
+///
+/// # Errors
+/// A panic of the callback is returned as [`Error::Panic`].
+pub(crate) async fn call<T, E, I>(
+    timeouts: &Timeouts,
+    callback: impl Future<Output = Result<T, E>>,
+) -> Result<Outcome<T, E>, Error<I>> {
+    match timeouts.callback(catch_panic(callback)).await {
+        Ok(Ok(Ok(output))) => Ok(Outcome::Done(output)),
+        Ok(Ok(Err(error))) => Ok(Outcome::Failed(error)),
+        Ok(Err(panic)) => Err(panic),
+        Err(limit) => Ok(Outcome::TimedOut(limit)),
+    }
+}
+
+/// Await `future`, returning a panic as [`Error::Panic`] instead of
+/// unwinding further
+async fn catch_panic<F: Future, I>(future: F) -> Result<F::Output, Error<I>> {
+    AssertUnwindSafe(future)
+        .catch_unwind()
+        .await
+        .map_err(|payload| Error::Panic(panic_message(payload.as_ref())))
+}
+
+/// The message a panic was raised with
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return (*message).to_string();
+    }
+    if let Some(message) = payload.downcast_ref::<String>() {
+        return message.clone();
+    }
+    "Box<dyn Any>".to_string()
+}
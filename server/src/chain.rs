@@ -0,0 +1,451 @@
+//! Run several milters as one
+
+use std::fmt;
+
+use async_trait::async_trait;
+use miltr_common::{
+    actions::{Action, Continue, Skip},
+    commands::{Body, Connect, Header, Helo, Macro, Mail, Recipient, Unknown},
+    modifications::ModificationResponse,
+    optneg::{Capability, MacroStage, OptNeg, Protocol},
+    ProtocolError,
+};
+
+use crate::{Context, Error, Milter};
+
+/// Protocol flags restricting what the client sends or awaits, or changing
+/// what every milter receives, like the leading space of header values.
+///
+/// Those are only negotiated if all milters of a [`Chain`] agree on them.
+const RESTRICTING: Protocol = Protocol::NO_CONNECT
+    .union(Protocol::NO_HELO)
+    .union(Protocol::NO_MAIL)
+    .union(Protocol::NO_RECIPIENT)
+    .union(Protocol::NO_BODY)
+    .union(Protocol::NO_HEADER)
+    .union(Protocol::NO_END_OF_HEADER)
+    .union(Protocol::NO_UNKNOWN)
+    .union(Protocol::NO_DATA)
+    .union(Protocol::NR_CONNECT)
+    .union(Protocol::NR_HELO)
+    .union(Protocol::NR_MAIL)
+    .union(Protocol::NR_RECIPIENT)
+    .union(Protocol::NR_DATA)
+    .union(Protocol::NR_UNKNOWN)
+    .union(Protocol::NR_HEADER)
+    .union(Protocol::NR_END_OF_HEADER)
+    .union(Protocol::NR_BODY)
+    .union(Protocol::SMFIP_HDR_LEADSPC);
+
+/// The macro telling the mailer of a recipient, `error` for recipients the
+/// MTA rejected
+const RCPT_MAILER: &str = "{rcpt_mailer}";
+
+/// Fan a stage callback out to all members which did not disable the stage,
+/// or those matching `notify`, returning the first action that does not
+/// continue.
+macro_rules! fan_out {
+    ($self:ident, |$member:ident| $notify:expr, |$milter:ident| $call:expr) => {{
+        let Chain { members, failed } = $self;
+
+        for (index, member) in members.iter_mut().enumerate() {
+            let notify = {
+                let $member = &*member;
+                $notify
+            };
+            if !notify {
+                continue;
+            }
+
+            let $milter = &mut member.milter;
+            let action = $call.await.map_err(|error| {
+                *failed = Some(index);
+                error
+            })?;
+            if !matches!(action, Action::Continue(_)) {
+                return Ok(action);
+            }
+        }
+
+        Ok(Continue.into())
+    }};
+    ($self:ident, $no_send:expr, |$milter:ident| $call:expr) => {
+        fan_out!(
+            $self,
+            |member| !member.protocol.contains($no_send),
+            |$milter| $call
+        )
+    };
+}
+
+/// Notify all members, even if one of them fails, returning the first error
+macro_rules! notify_all {
+    ($self:ident, |$milter:ident| $call:expr) => {{
+        let Chain { members, failed } = $self;
+        let mut result = Ok(());
+
+        for (index, member) in members.iter_mut().enumerate() {
+            let $milter = &mut member.milter;
+            if let Err(error) = $call.await {
+                if result.is_ok() {
+                    *failed = Some(index);
+                    result = Err(error);
+                }
+            }
+        }
+
+        result
+    }};
+}
+
+/// Runs several milters in one server, in order.
+///
+/// Every callback is handed to each milter of the chain:
+/// - The first action not continuing is returned, later milters are not
+///   asked.
+/// - A milter skipping the body is not sent the remaining body chunks. The
+///   chain only skips once every milter receiving the body skipped.
+/// - Recipients already rejected by the MTA are only passed to milters
+///   which negotiated [`Protocol::SMFIP_RCPT_REJ`], recognized by the
+///   `{rcpt_mailer}` macro being `error`.
+/// - The modifications of all milters are merged, see
+///   [`ModificationResponse::merge`]. Every milter only sees the original
+///   message, not the modifications of milters before it.
+/// - A milter is not notified about stages it disabled during option
+///   negotiation and may only modify what it negotiated capabilities for.
+/// - `abort`, `quit` and `quit_nc` are called on all milters, even if one of
+///   them fails.
+///
+/// All milters share an error type. [`Milter::error`] is called on the
+/// milter which failed.
+///
+/// ```
+/// use async_trait::async_trait;
+/// use miltr_server::{Chain, Context, Milter, Server};
+///
+/// struct Signer;
+/// struct Disclaimer;
+///
+/// #[async_trait]
+/// impl Milter for Signer {
+///     type Error = &'static str;
+///     async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+///         Ok(())
+///     }
+/// }
+///
+/// #[async_trait]
+/// impl Milter for Disclaimer {
+///     type Error = &'static str;
+///     async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+///         Ok(())
+///     }
+/// }
+///
+/// let mut chain = Chain::new().with(Disclaimer).with(Signer);
+/// let _server = Server::default_postfix(&mut chain);
+/// ```
+pub struct Chain<E> {
+    members: Vec<Member<E>>,
+    /// The member whose callback failed last
+    failed: Option<usize>,
+}
+
+/// A milter in a chain and what it negotiated
+struct Member<E> {
+    milter: Box<dyn Milter<Error = E>>,
+    protocol: Protocol,
+    capabilities: Capability,
+    /// Skipped the remaining body of the current message
+    skipped: bool,
+}
+
+impl<E> Default for Chain<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E> Chain<E> {
+    /// An empty chain, continuing everything
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            members: Vec::new(),
+            failed: None,
+        }
+    }
+
+    /// Append `milter` to the end of the chain
+    #[must_use]
+    pub fn with<M: Milter<Error = E> + 'static>(mut self, milter: M) -> Self {
+        self.push(milter);
+        self
+    }
+
+    /// Append `milter` to the end of the chain
+    pub fn push<M: Milter<Error = E> + 'static>(&mut self, milter: M) {
+        self.members.push(Member {
+            milter: Box::new(milter),
+            protocol: Protocol::empty(),
+            capabilities: Capability::all(),
+            skipped: false,
+        });
+    }
+
+    /// The number of milters in the chain
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Whether the chain has no milters
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+impl<E> fmt::Debug for Chain<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chain")
+            .field("members", &self.members.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Combine the options of two milters, so both get what they need
+fn combine(mut ours: OptNeg, other: &OptNeg) -> OptNeg {
+    ours.version = ours.version.min(other.version);
+    ours.capabilities |= other.capabilities;
+    ours.protocol = (ours.protocol & other.protocol & RESTRICTING)
+        | ((ours.protocol | other.protocol) - RESTRICTING);
+    ours.macro_stages.merge(&other.macro_stages);
+    ours
+}
+
+#[async_trait]
+impl<E: Send> Milter for Chain<E> {
+    type Error = E;
+
+    async fn option_negotiation(&mut self, theirs: OptNeg) -> Result<OptNeg, Error<Self::Error>> {
+        let mut combined: Option<OptNeg> = None;
+
+        for member in &mut self.members {
+            let ours = member.milter.option_negotiation(theirs.clone()).await?;
+            member.protocol = ours.protocol;
+            member.capabilities = ours.capabilities;
+
+            combined = Some(match combined {
+                Some(combined) => combine(combined, &ours),
+                None => ours,
+            });
+        }
+
+        match combined {
+            Some(mut combined) => {
+                let rejected_for_some = combined.protocol.contains(Protocol::SMFIP_RCPT_REJ)
+                    && !self
+                        .members
+                        .iter()
+                        .all(|member| member.protocol.contains(Protocol::SMFIP_RCPT_REJ));
+                let rcpt_macros = combined.macro_stages.symbols(MacroStage::RcptTo);
+                // An empty request gets the MTA's defaults, which include it
+                if rejected_for_some
+                    && !rcpt_macros.is_empty()
+                    && !rcpt_macros.iter().any(|m| m == RCPT_MAILER)
+                {
+                    combined
+                        .macro_stages
+                        .with_stage(MacroStage::RcptTo, &[RCPT_MAILER]);
+                }
+                Ok(combined)
+            }
+            None => Ok(OptNeg::default()
+                .merge_compatible(&theirs)
+                .map_err(ProtocolError::CompatibilityError)?),
+        }
+    }
+
+    async fn macro_(&mut self, context: &Context, macro_: Macro) -> Result<(), Self::Error> {
+        notify_all!(self, |milter| milter.macro_(context, macro_.clone()))
+    }
+
+    async fn connect(
+        &mut self,
+        context: &Context,
+        connect_info: Connect,
+    ) -> Result<Action, Self::Error> {
+        fan_out!(self, Protocol::NO_CONNECT, |milter| milter
+            .connect(context, connect_info.clone()))
+    }
+
+    async fn helo(&mut self, context: &Context, helo: Helo) -> Result<Action, Self::Error> {
+        fan_out!(self, Protocol::NO_HELO, |milter| milter
+            .helo(context, helo.clone()))
+    }
+
+    async fn mail(&mut self, context: &Context, mail: Mail) -> Result<Action, Self::Error> {
+        fan_out!(self, Protocol::NO_MAIL, |milter| milter
+            .mail(context, mail.clone()))
+    }
+
+    async fn rcpt(
+        &mut self,
+        context: &Context,
+        recipient: Recipient,
+    ) -> Result<Action, Self::Error> {
+        let rejected = context
+            .macro_(RCPT_MAILER)
+            .is_some_and(|mailer| mailer == "error");
+
+        fan_out!(
+            self,
+            |member| !member.protocol.contains(Protocol::NO_RECIPIENT)
+                && (!rejected || member.protocol.contains(Protocol::SMFIP_RCPT_REJ)),
+            |milter| milter.rcpt(context, recipient.clone())
+        )
+    }
+
+    async fn data(&mut self, context: &Context) -> Result<Action, Self::Error> {
+        fan_out!(self, Protocol::NO_DATA, |milter| milter.data(context))
+    }
+
+    async fn header(&mut self, context: &Context, header: Header) -> Result<Action, Self::Error> {
+        fan_out!(self, Protocol::NO_HEADER, |milter| milter
+            .header(context, header.clone()))
+    }
+
+    async fn end_of_header(&mut self, context: &Context) -> Result<Action, Self::Error> {
+        fan_out!(self, Protocol::NO_END_OF_HEADER, |milter| milter
+            .end_of_header(context))
+    }
+
+    async fn body(&mut self, context: &Context, body: Body) -> Result<Action, Self::Error> {
+        // Without members receiving the body, there is nothing to skip
+        let mut all_skipped = self
+            .members
+            .iter()
+            .any(|member| !member.protocol.contains(Protocol::NO_BODY));
+
+        for (index, member) in self.members.iter_mut().enumerate() {
+            if member.protocol.contains(Protocol::NO_BODY) || member.skipped {
+                continue;
+            }
+
+            let action = match member.milter.body(context, body.clone()).await {
+                Ok(action) => action,
+                Err(error) => {
+                    self.failed = Some(index);
+                    return Err(error);
+                }
+            };
+            match action {
+                Action::Continue(_) => all_skipped = false,
+                Action::Skip(_) => member.skipped = true,
+                action => return Ok(action),
+            }
+        }
+
+        if all_skipped {
+            return Ok(Skip.into());
+        }
+        Ok(Continue.into())
+    }
+
+    async fn end_of_body(
+        &mut self,
+        context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
+        let mut merged = ModificationResponse::empty_continue();
+
+        for member in &mut self.members {
+            member.skipped = false;
+        }
+        for (index, member) in self.members.iter_mut().enumerate() {
+            let mut response = match member.milter.end_of_body(context).await {
+                Ok(response) => response,
+                Err(error) => {
+                    self.failed = Some(index);
+                    return Err(error);
+                }
+            };
+            response.filter_mods_by_caps(member.capabilities);
+
+            let done = !matches!(response.final_action(), Action::Continue(_));
+            merged.merge(response);
+            if done {
+                break;
+            }
+        }
+
+        Ok(merged)
+    }
+
+    async fn unknown(&mut self, context: &Context, cmd: Unknown) -> Result<Action, Self::Error> {
+        fan_out!(self, Protocol::NO_UNKNOWN, |milter| milter
+            .unknown(context, cmd.clone()))
+    }
+
+    async fn abort(&mut self, context: &Context) -> Result<(), Self::Error> {
+        for member in &mut self.members {
+            member.skipped = false;
+        }
+        notify_all!(self, |milter| milter.abort(context))
+    }
+
+    async fn quit(&mut self, context: &Context) -> Result<(), Self::Error> {
+        notify_all!(self, |milter| milter.quit(context))
+    }
+
+    async fn quit_nc(&mut self, context: &Context) -> Result<(), Self::Error> {
+        notify_all!(self, |milter| milter.quit_nc(context))
+    }
+
+    fn error(&mut self, context: &Context, error: Self::Error) {
+        if let Some(index) = self.failed.take() {
+            self.members[index].milter.error(context, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_combine_options() {
+        let mut ours = OptNeg {
+            capabilities: Capability::SMFIF_ADDHDRS,
+            protocol: Protocol::NO_CONNECT
+                | Protocol::NO_HELO
+                | Protocol::SMFIP_SKIP
+                | Protocol::SMFIP_HDR_LEADSPC,
+            ..Default::default()
+        };
+        ours.macro_stages
+            .with_stage(MacroStage::Connect, &["j", "{daemon_name}"]);
+        let mut other = OptNeg {
+            version: 2,
+            capabilities: Capability::SMFIF_CHGBODY,
+            protocol: Protocol::NO_HELO | Protocol::NR_HEADER,
+            ..Default::default()
+        };
+        other
+            .macro_stages
+            .with_stage(MacroStage::Connect, &["j", "_"]);
+
+        let combined = combine(ours, &other);
+
+        assert_eq!(combined.version, 2);
+        assert_eq!(
+            combined.capabilities,
+            Capability::SMFIF_ADDHDRS | Capability::SMFIF_CHGBODY
+        );
+        assert_eq!(combined.protocol, Protocol::NO_HELO | Protocol::SMFIP_SKIP);
+        assert_eq!(
+            combined.macro_stages.symbols(MacroStage::Connect),
+            ["j", "{daemon_name}", "_"]
+        );
+    }
+}
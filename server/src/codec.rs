@@ -4,8 +4,10 @@ use bytes::{Buf, BufMut, BytesMut};
 use miltr_common::decoding::ClientCommand;
 use miltr_common::encoding::ServerMessage;
 use miltr_common::encoding::Writable;
+#[cfg(feature = "metrics")]
+use miltr_common::metrics::{self, Side};
 use miltr_common::ProtocolError;
-use miltr_utils::trace;
+use miltr_utils::{metric, trace};
 
 /// The `MilterCodec` is responsible for decoding from and encoding to bits on
 /// the wire from structs provided by this crate.
@@ -20,13 +22,13 @@ impl MilterCodec {
     pub(crate) fn new(max_buffer_size: usize) -> Self {
         Self { max_buffer_size }
     }
-}
 
-impl Decoder for &mut MilterCodec {
-    type Item = ClientCommand;
-    type Error = ProtocolError;
+    pub(crate) fn max_buffer_size(&self) -> usize {
+        self.max_buffer_size
+    }
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+    /// Decode a single frame from `src`, if it arrived completely
+    fn decode_frame(&self, src: &mut BytesMut) -> Result<Option<ClientCommand>, ProtocolError> {
         if src.len() < 4 {
             // Not enough data to read length marker.
 
@@ -57,16 +59,13 @@ impl Decoder for &mut MilterCodec {
         parse_buf.advance(4);
 
         trace!(length = parse_buf.len(), "Read bytes from the network");
+        metric!(metrics::bytes_received(Side::Server, 4 + length));
 
         Ok(Some(ClientCommand::parse(parse_buf)?))
     }
-}
 
-impl Encoder for &mut MilterCodec {
-    type Item<'i> = &'i ServerMessage;
-    type Error = ProtocolError;
-
-    fn encode(&mut self, item: &ServerMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+    /// Encode `item` as a single frame into `dst`
+    fn encode_frame(&self, item: &ServerMessage, dst: &mut BytesMut) -> Result<(), ProtocolError> {
         // Don't send a string if it is longer than the other end will
         // accept or  larger than we will be able to compute.
         let item_len = item.len();
@@ -91,11 +90,47 @@ impl Encoder for &mut MilterCodec {
         item.write(dst);
 
         trace!(length = dst.len(), "Wrote bytes to the network");
+        metric!(metrics::bytes_sent(Side::Server, 4 + packet_len));
 
         Ok(())
     }
 }
 
+impl Decoder for &mut MilterCodec {
+    type Item = ClientCommand;
+    type Error = ProtocolError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let result = self.decode_frame(src);
+        metric!(match &result {
+            Ok(Some(command)) => metrics::command(Side::Server, command),
+            Ok(None) => {}
+            Err(error) => metrics::codec_error(Side::Server, error),
+        });
+        result
+    }
+}
+
+impl Encoder for &mut MilterCodec {
+    type Item<'i> = &'i ServerMessage;
+    type Error = ProtocolError;
+
+    fn encode(&mut self, item: &ServerMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let result = self.encode_frame(item, dst);
+        metric!(match &result {
+            Ok(()) => match item {
+                ServerMessage::Action(action) => metrics::action(Side::Server, action),
+                ServerMessage::ModificationAction(modification) => {
+                    metrics::modification(Side::Server, modification);
+                }
+                ServerMessage::Optneg(_) | ServerMessage::Progress(_) => {}
+            },
+            Err(error) => metrics::codec_error(Side::Server, error),
+        });
+        result
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -0,0 +1,304 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use miltr_common::commands::{Header, Mail, Recipient};
+
+/// A complete message, as collected by [`Collect`](super::Collect)
+#[derive(Debug)]
+pub struct Message {
+    pub(super) mail: Option<Mail>,
+    pub(super) recipients: Vec<Recipient>,
+    pub(super) headers: Vec<MessageHeader>,
+    pub(super) body: MessageBody,
+}
+
+impl Message {
+    pub(super) fn new(body: MessageBody) -> Self {
+        Self {
+            mail: None,
+            recipients: Vec::new(),
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    /// The envelope sender, if the client sent it
+    #[must_use]
+    pub fn mail(&self) -> Option<&Mail> {
+        self.mail.as_ref()
+    }
+
+    /// The envelope recipients
+    #[must_use]
+    pub fn recipients(&self) -> &[Recipient] {
+        &self.recipients
+    }
+
+    /// All headers in the order they were received
+    #[must_use]
+    pub fn headers(&self) -> &[MessageHeader] {
+        &self.headers
+    }
+
+    /// The value of the first header called `name`, ignoring case
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.headers
+            .iter()
+            .find(|h| h.name().eq_ignore_ascii_case(name))
+            .map(MessageHeader::value)
+    }
+
+    /// The message body
+    #[must_use]
+    pub fn body(&self) -> &MessageBody {
+        &self.body
+    }
+
+    pub(super) fn push_header(&mut self, header: Header) {
+        let index = self
+            .headers
+            .iter()
+            .filter(|h| h.name().eq_ignore_ascii_case(&header.name()))
+            .count()
+            + 1;
+
+        self.headers.push(MessageHeader {
+            header,
+            index: index as u32,
+        });
+    }
+}
+
+/// A header of a collected [`Message`]
+#[derive(Debug, Clone)]
+pub struct MessageHeader {
+    header: Header,
+    index: u32,
+}
+
+impl MessageHeader {
+    /// The header name
+    #[must_use]
+    pub fn name(&self) -> Cow<'_, str> {
+        self.header.name()
+    }
+
+    /// The header value
+    #[must_use]
+    pub fn value(&self) -> Cow<'_, str> {
+        self.header.value()
+    }
+
+    /// The occurrence of this header among all headers with the same name,
+    /// counting from 1.
+    ///
+    /// This is the index to change or delete this header with a
+    /// [`ChangeHeader`](miltr_common::modifications::headers::ChangeHeader).
+    #[must_use]
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
+/// The body of a collected [`Message`].
+///
+/// The body is kept in memory up to a limit. A larger body is spilled to an
+/// anonymous temporary file, which is removed once the body is dropped.
+///
+/// Reading a spilled body blocks on the file. Within
+/// [`MessageMilter::message`](super::MessageMilter::message), read large
+/// bodies using [`tokio::task::spawn_blocking`] to not stall other sessions
+/// handled by the runtime.
+#[derive(Debug)]
+pub struct MessageBody {
+    memory: Vec<u8>,
+    file: Option<File>,
+    len: u64,
+    memory_limit: usize,
+    temp_dir: Option<PathBuf>,
+}
+
+impl MessageBody {
+    pub(super) fn new(memory_limit: usize, temp_dir: Option<&Path>) -> Self {
+        Self {
+            memory: Vec::new(),
+            file: None,
+            len: 0,
+            memory_limit,
+            temp_dir: temp_dir.map(Path::to_path_buf),
+        }
+    }
+
+    /// The length of the body in bytes
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the body is empty
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the body exceeded the memory limit and was spilled to a
+    /// temporary file
+    #[must_use]
+    pub fn is_spilled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Read the body from the start.
+    ///
+    /// Blocks reading the temporary file if the body was spilled.
+    ///
+    /// # Errors
+    /// Errors if the body was spilled and the temporary file can not be read.
+    pub fn reader(&self) -> io::Result<impl Read + '_> {
+        let Some(mut file) = self.file.as_ref() else {
+            return Ok(BodyReader::Memory(&self.memory));
+        };
+
+        file.seek(SeekFrom::Start(0))?;
+        Ok(BodyReader::File(file))
+    }
+
+    /// Read the whole body into memory.
+    ///
+    /// Blocks reading the temporary file if the body was spilled.
+    ///
+    /// # Errors
+    /// Errors if the body was spilled and the temporary file can not be read.
+    pub fn to_vec(&self) -> io::Result<Vec<u8>> {
+        let mut body = Vec::with_capacity(usize::try_from(self.len).unwrap_or_default());
+        self.reader()?.read_to_end(&mut body)?;
+        Ok(body)
+    }
+
+    /// Append a body chunk, spilling to a temporary file once the memory
+    /// limit is exceeded.
+    ///
+    /// Writing to the file runs on tokio's blocking threads.
+    pub(super) async fn append(&mut self, chunk: Vec<u8>) -> io::Result<()> {
+        let len = chunk.len() as u64;
+        if self.file.is_none() && self.memory.len() + chunk.len() <= self.memory_limit {
+            self.memory.extend_from_slice(&chunk);
+            self.len += len;
+            return Ok(());
+        }
+
+        let file = self.file.take();
+        let memory = std::mem::take(&mut self.memory);
+        let temp_dir = self.temp_dir.clone();
+        let written = tokio::task::spawn_blocking(move || {
+            let mut file = match file {
+                Some(file) => file,
+                None => spill(&memory, temp_dir.as_deref())?,
+            };
+            file.write_all(&chunk)?;
+            Ok::<_, io::Error>(file)
+        })
+        .await;
+
+        let file = match written {
+            Ok(file) => file?,
+            Err(err) => match err.try_into_panic() {
+                Ok(payload) => std::panic::resume_unwind(payload),
+                Err(err) => return Err(io::Error::new(io::ErrorKind::Other, err)),
+            },
+        };
+        self.file = Some(file);
+        self.len += len;
+
+        Ok(())
+    }
+}
+
+/// Move the body kept in `memory` to a new temporary file
+fn spill(memory: &[u8], temp_dir: Option<&Path>) -> io::Result<File> {
+    let mut file = match temp_dir {
+        Some(dir) => tempfile::tempfile_in(dir)?,
+        None => tempfile::tempfile()?,
+    };
+    file.write_all(memory)?;
+    Ok(file)
+}
+
+/// Reads a body from wherever it is kept
+enum BodyReader<'b> {
+    Memory(&'b [u8]),
+    File(&'b File),
+}
+
+impl Read for BodyReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Memory(memory) => memory.read(buf),
+            Self::File(file) => file.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_body_in_memory() {
+        let mut body = MessageBody::new(16, None);
+        body.append(b"Hello ".to_vec())
+            .await
+            .expect("Append to memory");
+        body.append(b"World".to_vec())
+            .await
+            .expect("Append to memory");
+
+        assert!(!body.is_spilled());
+        assert_eq!(body.len(), 11);
+        assert_eq!(body.to_vec().expect("Read from memory"), b"Hello World");
+    }
+
+    #[tokio::test]
+    async fn test_body_spilled() {
+        let mut body = MessageBody::new(8, None);
+        body.append(b"Hello ".to_vec())
+            .await
+            .expect("Append to memory");
+        body.append(b"World".to_vec()).await.expect("Spill to file");
+        body.append(b"!".to_vec()).await.expect("Append to file");
+
+        assert!(body.is_spilled());
+        assert_eq!(body.len(), 12);
+        assert_eq!(body.to_vec().expect("Read from file"), b"Hello World!");
+        // Reading again starts from the beginning
+        assert_eq!(body.to_vec().expect("Read from file"), b"Hello World!");
+    }
+
+    #[test]
+    fn test_header_index() {
+        let mut message = Message::new(MessageBody::new(0, None));
+        message.push_header(Header::new(b"Received", b"first"));
+        message.push_header(Header::new(b"Subject", b"Hi"));
+        message.push_header(Header::new(b"received", b"second"));
+
+        let indices: Vec<(Cow<str>, u32)> = message
+            .headers()
+            .iter()
+            .map(|h| (h.name(), h.index()))
+            .collect();
+        assert_eq!(
+            indices,
+            [
+                (Cow::from("Received"), 1),
+                (Cow::from("Subject"), 1),
+                (Cow::from("received"), 2)
+            ]
+        );
+        assert_eq!(message.header("SUBJECT").as_deref(), Some("Hi"));
+    }
+}
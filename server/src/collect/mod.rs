@@ -0,0 +1,238 @@
+//! Collect a whole message and hand it to the milter at once.
+//!
+//! Content milters often only buffer headers and body to analyse the
+//! message at the end. Implement [`MessageMilter`] instead and wrap it in
+//! [`Collect`], which implements [`Milter`] and does the buffering:
+//!
+//! ```
+//! use async_trait::async_trait;
+//! use miltr_common::modifications::{headers::AddHeader, ModificationResponse};
+//! use miltr_server::{
+//!     collect::{Collect, Message, MessageMilter},
+//!     Context, Server,
+//! };
+//!
+//! struct SizeMilter;
+//!
+//! #[async_trait]
+//! impl MessageMilter for SizeMilter {
+//!     type Error = &'static str;
+//!
+//!     async fn message(
+//!         &mut self,
+//!         _context: &Context,
+//!         message: Message,
+//!     ) -> Result<ModificationResponse, Self::Error> {
+//!         let size = message.body().len().to_string();
+//!
+//!         let mut builder = ModificationResponse::builder();
+//!         builder.push(AddHeader::new(b"X-Body-Size", size.as_bytes()));
+//!         Ok(builder.contin())
+//!     }
+//! }
+//!
+//! let mut milter = Collect::new(SizeMilter).with_memory_limit(1024 * 1024);
+//! let _server = Server::default_postfix(&mut milter);
+//! ```
+
+mod message;
+
+use std::{io, path::PathBuf};
+
+use async_trait::async_trait;
+use miltr_common::{
+    actions::{Action, Continue},
+    commands::{Body, Header, Mail, Recipient},
+    modifications::ModificationResponse,
+    optneg::{OptNeg, Protocol},
+    ProtocolError,
+};
+use thiserror::Error;
+
+use crate::{Context, Error, Milter};
+
+pub use self::message::{Message, MessageBody, MessageHeader};
+
+/// The default size of a body kept in memory, 1 MiB
+pub const DEFAULT_MEMORY_LIMIT: usize = 1024 * 1024;
+
+/// A milter handling complete messages, see [`Collect`]
+#[async_trait]
+pub trait MessageMilter: Send {
+    /// A user error that might be returned handling a message
+    type Error: Send;
+
+    /// The options to negotiate with the client.
+    ///
+    /// The stages needed to collect the message are always requested,
+    /// regardless of the protocol returned here.
+    fn options(&self) -> OptNeg {
+        OptNeg::default()
+    }
+
+    /// Handle a complete `message`.
+    ///
+    /// The macros and connection information of the session are part of
+    /// `context`.
+    #[doc(alias = "xxfi_eom")]
+    async fn message(
+        &mut self,
+        context: &Context,
+        message: Message,
+    ) -> Result<ModificationResponse, Self::Error>;
+
+    /// The current message is done, see [`Milter::abort`].
+    ///
+    /// Any message collected so far is dropped.
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called with the error a callback returned, if the
+    /// [`ErrorPolicy`](crate::ErrorPolicy) keeps the session alive.
+    ///
+    /// See [`Milter::error`].
+    fn error(&mut self, _context: &Context, _error: CollectError<Self::Error>) {}
+}
+
+/// Errors collecting a message
+#[derive(Debug, Error)]
+pub enum CollectError<E> {
+    /// The body could not be spilled to a temporary file
+    #[error("Failed buffering the message body: {0}")]
+    Io(#[from] io::Error),
+    /// The [`MessageMilter`] returned an error
+    #[error(transparent)]
+    Milter {
+        /// The application error patched through
+        source: E,
+    },
+}
+
+/// Wraps a [`MessageMilter`], collecting every message before handing it
+/// over.
+///
+/// The envelope, the headers and the body are collected. Bodies larger than
+/// the memory limit are spilled to a temporary file, written on tokio's
+/// blocking threads. A tokio runtime is required for that.
+#[derive(Debug)]
+pub struct Collect<M> {
+    milter: M,
+    memory_limit: usize,
+    temp_dir: Option<PathBuf>,
+    message: Option<Message>,
+}
+
+impl<M: MessageMilter> Collect<M> {
+    /// Collect messages for `milter`
+    pub fn new(milter: M) -> Self {
+        Self {
+            milter,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            temp_dir: None,
+            message: None,
+        }
+    }
+
+    /// Keep bodies up to `limit` bytes in memory.
+    ///
+    /// Defaults to [`DEFAULT_MEMORY_LIMIT`].
+    #[must_use]
+    pub fn with_memory_limit(mut self, limit: usize) -> Self {
+        self.memory_limit = limit;
+        self
+    }
+
+    /// Spill larger bodies to temporary files in `dir`.
+    ///
+    /// Defaults to [`std::env::temp_dir`].
+    #[must_use]
+    pub fn with_temp_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.temp_dir = Some(dir.into());
+        self
+    }
+
+    /// The wrapped milter
+    pub fn milter(&mut self) -> &mut M {
+        &mut self.milter
+    }
+
+    /// The message currently collected, starting a new one if needed
+    fn message(&mut self) -> &mut Message {
+        let (limit, temp_dir) = (self.memory_limit, self.temp_dir.as_deref());
+        self.message
+            .get_or_insert_with(|| Message::new(MessageBody::new(limit, temp_dir)))
+    }
+}
+
+#[async_trait]
+impl<M: MessageMilter> Milter for Collect<M> {
+    type Error = CollectError<M::Error>;
+
+    async fn option_negotiation(&mut self, theirs: OptNeg) -> Result<OptNeg, Error<Self::Error>> {
+        let mut ours = self.milter.options();
+        ours.protocol.remove(
+            Protocol::NO_MAIL | Protocol::NO_RECIPIENT | Protocol::NO_HEADER | Protocol::NO_BODY,
+        );
+
+        Ok(ours
+            .merge_compatible(&theirs)
+            .map_err(ProtocolError::CompatibilityError)?)
+    }
+
+    async fn mail(&mut self, _context: &Context, mail: Mail) -> Result<Action, Self::Error> {
+        self.message = None;
+        self.message().mail = Some(mail);
+        Ok(Continue.into())
+    }
+
+    async fn rcpt(
+        &mut self,
+        _context: &Context,
+        recipient: Recipient,
+    ) -> Result<Action, Self::Error> {
+        self.message().recipients.push(recipient);
+        Ok(Continue.into())
+    }
+
+    async fn header(&mut self, _context: &Context, header: Header) -> Result<Action, Self::Error> {
+        self.message().push_header(header);
+        Ok(Continue.into())
+    }
+
+    async fn body(&mut self, _context: &Context, body: Body) -> Result<Action, Self::Error> {
+        self.message().body.append(body.into()).await?;
+        Ok(Continue.into())
+    }
+
+    async fn end_of_body(
+        &mut self,
+        context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
+        let message = match self.message.take() {
+            Some(message) => message,
+            // Every stage before was disabled or skipped
+            None => Message::new(MessageBody::new(
+                self.memory_limit,
+                self.temp_dir.as_deref(),
+            )),
+        };
+
+        self.milter
+            .message(context, message)
+            .await
+            .map_err(|source| CollectError::Milter { source })
+    }
+
+    async fn abort(&mut self, context: &Context) -> Result<(), Self::Error> {
+        self.message = None;
+        self.milter
+            .abort(context)
+            .await
+            .map_err(|source| CollectError::Milter { source })
+    }
+
+    fn error(&mut self, context: &Context, error: Self::Error) {
+        self.milter.error(context, error);
+    }
+}
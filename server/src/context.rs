@@ -0,0 +1,322 @@
+//! State kept by the server about the current smtp session
+
+use std::{
+    borrow::Cow,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use miltr_common::{
+    commands::{Connect, Helo, Macro},
+    decoding::ClientCommand,
+    optneg::{MacroStage, OptNeg},
+    state::{CommandKind, OutOfOrder, SessionState},
+};
+use miltr_utils::debug;
+
+#[cfg(feature = "tracing")]
+use crate::trace::Trace;
+
+/// Used to hand out unique session ids
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// The context of the smtp session currently handled by a milter.
+///
+/// The [`Server`](crate::Server) keeps track of everything the client told
+/// it about the current session and passes it to every [`Milter`](crate::Milter)
+/// callback, so implementations do not need to store those themselves.
+#[derive(Debug, Clone)]
+pub struct Context {
+    session_id: u64,
+    options: Option<OptNeg>,
+    macros: Macros,
+    connect: Option<Connect>,
+    helo: Option<Helo>,
+    state: SessionState,
+    #[cfg(feature = "tracing")]
+    trace: Trace,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Context {
+    /// Create an empty context for a new session
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            session_id: NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed),
+            options: None,
+            macros: Macros::default(),
+            connect: None,
+            helo: None,
+            state: SessionState::default(),
+            #[cfg(feature = "tracing")]
+            trace: Trace::new(),
+        }
+    }
+
+    /// An id unique to this session within this process.
+    ///
+    /// A new id is assigned if the connection is re-used for another
+    /// session using `QuitNc`.
+    #[must_use]
+    pub fn session_id(&self) -> u64 {
+        self.session_id
+    }
+
+    /// The options negotiated with the client.
+    ///
+    /// `None` until option negotiation finished.
+    #[must_use]
+    pub fn options(&self) -> Option<&OptNeg> {
+        self.options.as_ref()
+    }
+
+    /// All macros received during this session
+    #[must_use]
+    pub fn macros(&self) -> &Macros {
+        &self.macros
+    }
+
+    /// Lookup the most recently received value of the macro `name`.
+    ///
+    /// See [`Macros::get`].
+    #[must_use]
+    pub fn macro_(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.macros.get(name)
+    }
+
+    /// Connection information about the smtp client, if already received
+    #[must_use]
+    pub fn connect(&self) -> Option<&Connect> {
+        self.connect.as_ref()
+    }
+
+    /// The helo name sent by the smtp client, if already received
+    #[must_use]
+    pub fn helo(&self) -> Option<Cow<'_, str>> {
+        self.helo.as_ref().map(Helo::helo)
+    }
+
+    /// The stage of the protocol this session is in
+    #[must_use]
+    pub fn state(&self) -> SessionState {
+        self.state
+    }
+
+    pub(crate) fn set_options(&mut self, options: OptNeg) {
+        self.options = Some(options);
+    }
+
+    /// Remember the session information in `command`
+    pub(crate) fn record(&mut self, command: &ClientCommand) {
+        match command {
+            ClientCommand::Connect(connect) => self.connect = Some(connect.clone()),
+            ClientCommand::Helo(helo) => self.helo = Some(helo.clone()),
+            ClientCommand::Macro(macro_) => self.macros.insert(macro_.clone()),
+            ClientCommand::Mail(_) => self.macros.start_message(),
+            _ => {}
+        }
+        #[cfg(feature = "tracing")]
+        self.trace.command(command, self.session_id);
+    }
+
+    /// Move the session state on to after `command`.
+    ///
+    /// # Errors
+    /// Returns [`OutOfOrder`] if `command` is not valid in the current state.
+    pub(crate) fn advance(&mut self, command: &ClientCommand) -> Result<(), OutOfOrder> {
+        self.state.advance(CommandKind::from(command))
+    }
+
+    /// Forget everything about the current message.
+    ///
+    /// Connection level information is kept for the next message.
+    pub(crate) fn reset_message(&mut self) {
+        self.macros.clear_message();
+        #[cfg(feature = "tracing")]
+        self.trace.finish_message();
+    }
+
+    /// Start a new session on the same connection.
+    ///
+    /// The negotiated options and the session state are kept, everything
+    /// else is cleared.
+    pub(crate) fn reset_session(&mut self) {
+        *self = Self {
+            options: self.options.take(),
+            state: self.state,
+            #[cfg(feature = "tracing")]
+            trace: self.trace.restart(),
+            ..Self::new()
+        };
+    }
+
+    /// The spans this session is traced in
+    #[cfg(feature = "tracing")]
+    pub(crate) fn trace(&self) -> &Trace {
+        &self.trace
+    }
+}
+
+/// Macros received from the client, stored by the stage they were sent for.
+///
+/// For every stage only the most recent macros are kept, e.g. the macros of
+/// the last recipient.
+#[derive(Debug, Clone, Default)]
+pub struct Macros {
+    /// Ordered by the time they were received
+    stages: Vec<(MacroStage, Macro)>,
+}
+
+impl Macros {
+    /// Lookup the most recently received value of the macro `name`.
+    ///
+    /// Surrounding braces are not significant, `{auth_authen}` and
+    /// `auth_authen` find the same macro.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<Cow<'_, str>> {
+        let name = strip_braces(name.as_bytes());
+
+        self.stages.iter().rev().find_map(|(_stage, macro_)| {
+            macro_
+                .macros()
+                .find(|(key, _value)| strip_braces(key) == name)
+                .map(|(_key, value)| String::from_utf8_lossy(value))
+        })
+    }
+
+    /// The macros received for `stage`
+    #[must_use]
+    pub fn stage(&self, stage: MacroStage) -> Option<&Macro> {
+        self.stages
+            .iter()
+            .find(|(s, _macro)| *s == stage)
+            .map(|(_stage, macro_)| macro_)
+    }
+
+    fn insert(&mut self, macro_: Macro) {
+        let Some(stage) = macro_.stage() else {
+            debug!("Ignoring macros for unknown stage {}", macro_.code);
+            return;
+        };
+
+        self.stages.retain(|(s, _macro)| *s != stage);
+        self.stages.push((stage, macro_));
+    }
+
+    fn clear_message(&mut self) {
+        self.stages
+            .retain(|(stage, _macro)| matches!(stage, MacroStage::Connect | MacroStage::Helo));
+    }
+
+    /// Forget the macros of a previous message, keeping those sent along
+    /// with the new sender
+    fn start_message(&mut self) {
+        self.stages.retain(|(stage, _macro)| {
+            matches!(
+                stage,
+                MacroStage::Connect | MacroStage::Helo | MacroStage::MailFrom
+            )
+        });
+    }
+}
+
+/// Strip sendmail style braces around long macro names
+pub(crate) fn strip_braces(name: &[u8]) -> &[u8] {
+    name.strip_prefix(b"{")
+        .and_then(|n| n.strip_suffix(b"}"))
+        .unwrap_or(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use miltr_common::commands::Mail;
+
+    fn context() -> Context {
+        let mut context = Context::new();
+        context.record(&ClientCommand::Macro(Macro::new(
+            b'C',
+            &[(b"j", b"mx.example.com")],
+        )));
+        context.record(&ClientCommand::Macro(Macro::new(
+            b'M',
+            &[(b"{auth_authen}", b"alice")],
+        )));
+        context.record(&ClientCommand::Macro(Macro::new(
+            b'R',
+            &[(b"{rcpt_addr}", b"bob@example.com")],
+        )));
+        context
+    }
+
+    #[test]
+    fn test_macro_lookup() {
+        let context = context();
+
+        assert_eq!(context.macro_("j").as_deref(), Some("mx.example.com"));
+        assert_eq!(context.macro_("{auth_authen}").as_deref(), Some("alice"));
+        assert_eq!(context.macro_("auth_authen").as_deref(), Some("alice"));
+        assert_eq!(context.macro_("{i}"), None);
+    }
+
+    #[test]
+    fn test_macro_latest_wins() {
+        let mut context = context();
+        context.record(&ClientCommand::Macro(Macro::new(
+            b'R',
+            &[(b"{rcpt_addr}", b"carol@example.com")],
+        )));
+
+        assert_eq!(
+            context.macro_("rcpt_addr").as_deref(),
+            Some("carol@example.com")
+        );
+        assert!(context.macros().stage(MacroStage::RcptTo).is_some());
+    }
+
+    #[test]
+    fn test_reset_message() {
+        let mut context = context();
+        context.reset_message();
+
+        assert_eq!(context.macro_("j").as_deref(), Some("mx.example.com"));
+        assert_eq!(context.macro_("auth_authen"), None);
+        assert!(context.macros().stage(MacroStage::MailFrom).is_none());
+    }
+
+    #[test]
+    fn test_next_mail() {
+        let mut context = context();
+        context.record(&ClientCommand::Macro(Macro::new(
+            b'M',
+            &[(b"{auth_authen}", b"carol")],
+        )));
+        context.record(&ClientCommand::Mail(Mail::from(
+            &b"<carol@example.com>"[..],
+        )));
+
+        assert_eq!(context.macro_("j").as_deref(), Some("mx.example.com"));
+        assert_eq!(context.macro_("auth_authen").as_deref(), Some("carol"));
+        assert_eq!(context.macro_("rcpt_addr"), None);
+    }
+
+    #[test]
+    fn test_reset_session() {
+        let mut context = context();
+        context.set_options(OptNeg::default());
+        context.record(&ClientCommand::Helo(Helo::from(&b"localhost"[..])));
+        let session_id = context.session_id();
+
+        context.reset_session();
+
+        assert_ne!(context.session_id(), session_id);
+        assert!(context.options().is_some());
+        assert_eq!(context.helo(), None);
+        assert_eq!(context.macro_("j"), None);
+    }
+}
@@ -4,7 +4,11 @@
 
 use asynchronous_codec::Decoder;
 use bytes::BytesMut;
-use miltr_common::{decoding::ClientCommand, ProtocolError};
+use miltr_common::{
+    decoding::ClientCommand,
+    state::{CommandKind, SessionState},
+    ProtocolError,
+};
 
 use crate::codec::MilterCodec;
 
@@ -16,3 +20,22 @@ pub fn fuzz_parse(buffer: &mut BytesMut) -> Result<Option<ClientCommand>, Protoc
     let mut codec = MilterCodec::new(2_usize.pow(16));
     (&mut codec).decode(buffer)
 }
+
+/// Fuzzing harness to decode a whole session and check the command order
+///
+/// Decodes commands from `buffer` until it is exhausted and advances a
+/// [`SessionState`] with each of them. Out of order commands are expected and
+/// do not stop the session.
+///
+/// # Errors
+/// Transparently returns errors from the decode function
+pub fn fuzz_session(buffer: &mut BytesMut) -> Result<SessionState, ProtocolError> {
+    let mut codec = MilterCodec::new(2_usize.pow(16));
+    let mut state = SessionState::default();
+
+    while let Some(command) = (&mut codec).decode(buffer)? {
+        let _out_of_order = state.advance(CommandKind::from(&command));
+    }
+
+    Ok(state)
+}
@@ -1,26 +1,67 @@
 #![doc = include_str!("../Readme.md")]
 
+mod callback;
+mod chain;
 mod codec;
+mod context;
 mod milter;
+mod policy;
+mod timeout;
+#[cfg(feature = "tracing")]
+mod trace;
 
+#[cfg(feature = "collect")]
+pub mod collect;
 #[cfg(feature = "_fuzzing")]
 pub mod fuzzing;
+#[cfg(feature = "serve")]
+pub mod serve;
+#[cfg(feature = "tower")]
+pub mod service;
+
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+use std::{
+    ops::ControlFlow,
+    panic::{catch_unwind, AssertUnwindSafe},
+    pin::pin,
+    time::Duration,
+};
 
+use async_trait::async_trait;
 use asynchronous_codec::Framed;
+pub use chain::Chain;
+pub use context::{Context, Macros};
 pub use milter::{Error, Milter};
+pub use policy::{ActionPolicy, ErrorPolicy, OrderingPolicy};
 
-use futures::{AsyncRead, AsyncWrite, Future, SinkExt, StreamExt};
+use futures::{
+    future::{select, Either},
+    AsyncRead, AsyncWrite, Future, SinkExt, StreamExt,
+};
+use futures_timer::Delay;
 use miltr_common::{
-    actions::Action,
+    actions::{Action, Continue, Progress},
     decoding::ClientCommand,
     encoding::ServerMessage,
-    optneg::{Capability, OptNeg},
+    modifications::{body::ReplaceBody, ModificationAction, ModificationResponse},
+    optneg::{Capability, Protocol},
+    ProtocolError,
 };
-use miltr_utils::debug;
+#[cfg(feature = "metrics")]
+use miltr_common::{
+    metrics::{self, Side},
+    state::CommandKind,
+};
+use miltr_utils::{debug, metric, warn};
 #[cfg(feature = "tracing")]
-use tracing::instrument;
+use tracing::{instrument, Instrument};
 
 pub(crate) use self::codec::MilterCodec;
+use self::{
+    callback::{call, panic_message, Outcome},
+    timeout::Timeouts,
+};
 
 /// The entry point to host a milter server
 #[derive(Debug)]
@@ -28,6 +69,11 @@ pub struct Server<'m, M: Milter> {
     milter: &'m mut M,
     codec: MilterCodec,
     quit_on_abort: bool,
+    progress_interval: Option<Duration>,
+    action_policy: ActionPolicy,
+    error_policy: ErrorPolicy,
+    ordering_policy: OrderingPolicy,
+    timeouts: Timeouts,
 }
 
 impl<'m, M: Milter> Server<'m, M> {
@@ -38,9 +84,84 @@ impl<'m, M: Milter> Server<'m, M> {
             milter,
             codec,
             quit_on_abort,
+            progress_interval: None,
+            action_policy: ActionPolicy::default(),
+            error_policy: ErrorPolicy::default(),
+            ordering_policy: OrderingPolicy::default(),
+            timeouts: Timeouts::default(),
         }
     }
 
+    /// Set how to handle actions the client did not agree to.
+    ///
+    /// Defaults to [`ActionPolicy::Downgrade`].
+    #[must_use]
+    pub fn with_action_policy(mut self, action_policy: ActionPolicy) -> Self {
+        self.action_policy = action_policy;
+        self
+    }
+
+    /// Set how to handle errors returned by the milter implementation.
+    ///
+    /// Defaults to [`ErrorPolicy::Close`].
+    #[must_use]
+    pub fn with_error_policy(mut self, error_policy: ErrorPolicy) -> Self {
+        self.error_policy = error_policy;
+        self
+    }
+
+    /// Set how to handle commands the client sends out of order.
+    ///
+    /// Defaults to [`OrderingPolicy::Report`].
+    #[must_use]
+    pub fn with_ordering_policy(mut self, ordering_policy: OrderingPolicy) -> Self {
+        self.ordering_policy = ordering_policy;
+        self
+    }
+
+    /// Send progress keepalives while [`Milter::end_of_body`] is running.
+    ///
+    /// If the end of body handling takes longer than `interval`, a progress
+    /// packet is sent to the MTA every `interval`. This resets the MTA's
+    /// content timeout (e.g. postfix' `milter_content_timeout`), so `interval`
+    /// should be set well below it.
+    #[must_use]
+    #[doc(alias = "SMFIR_PROGRESS")]
+    #[doc(alias = "smfi_progress")]
+    pub fn with_progress_interval(mut self, interval: Duration) -> Self {
+        self.progress_interval = Some(interval);
+        self
+    }
+
+    /// Close the connection if the client does not send a command for
+    /// `timeout`.
+    ///
+    /// [`Server::handle_connection`] then returns [`Error::ReadTimeout`].
+    /// Keep in mind that a client may idle between mails of the same smtp
+    /// session, e.g. while the smtp client takes it's time.
+    #[must_use]
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.read = Some(timeout);
+        self
+    }
+
+    /// Limit the time a single milter callback may take to `timeout`.
+    ///
+    /// If a callback answering the client, including [`Milter::end_of_body`],
+    /// does not finish in time, it is cancelled and `action` is sent to the
+    /// client instead, e.g. [`Tempfail`](miltr_common::actions::Tempfail).
+    /// Other callbacks running out of time fail the connection with
+    /// [`Error::CallbackTimeout`].
+    ///
+    /// A cancelled callback may leave the milter in an intermediate state,
+    /// [`Milter::abort`] is called once the client aborts the mail.
+    #[must_use]
+    pub fn with_callback_timeout<A: Into<Action>>(mut self, timeout: Duration, action: A) -> Self {
+        self.timeouts.callback = Some(timeout);
+        self.timeouts.callback_action = action.into();
+        self
+    }
+
     /// Create a server with defaults working with postfix.
     ///
     /// AFAIK, originally there where three use cases individual methods:
@@ -79,118 +200,396 @@ impl<'m, M: Milter> Server<'m, M> {
     /// problems returned by the milter implementation.
     ///
     /// Have a look at [`enum@crate::Error`] for more information.
-    #[cfg_attr(feature = "tracing", instrument(skip_all))]
+    #[cfg_attr(feature = "tracing", instrument(name = "milter_connection", skip_all))]
     pub async fn handle_connection<RW: AsyncRead + AsyncWrite + Unpin + Send>(
         &mut self,
         socket: RW,
     ) -> Result<(), Error<M::Error>> {
-        let mut framed = Framed::new(socket, &mut self.codec);
+        // A copy of the codec, so the milter can be called while framing
+        let mut codec = self.codec.clone();
+        let mut framed = Framed::new(socket, &mut codec);
 
-        let mut options: Option<OptNeg> = Option::None;
+        let mut context = Context::new();
+        #[cfg(feature = "metrics")]
+        let _session = metrics::Session::open(Side::Server);
 
-        while let Some(command) = framed.next().await {
+        loop {
+            let next = self.timeouts.read(framed.next()).await;
+            let Some(command) = next.map_err(Error::ReadTimeout)? else {
+                break;
+            };
             let command = command?;
-            debug!("Received {}", command);
 
-            match command {
-                // First, all the regular smtp related commands
-                ClientCommand::Helo(helo) => {
-                    Self::notify_respond_answer(self.milter.helo(helo), &mut framed).await?;
-                }
-                ClientCommand::Connect(connect) => {
-                    Self::notify_respond_answer(self.milter.connect(connect), &mut framed).await?;
-                }
-                ClientCommand::Mail(mail) => {
-                    Self::notify_respond_answer(self.milter.mail(mail), &mut framed).await?;
-                }
-                ClientCommand::Recipient(rcpt) => {
-                    Self::notify_respond_answer(self.milter.rcpt(rcpt), &mut framed).await?;
-                }
-                ClientCommand::Data(_v) => {
-                    Self::notify_respond_answer(self.milter.data(), &mut framed).await?;
-                }
-                ClientCommand::Header(header) => {
-                    Self::notify_respond_answer(self.milter.header(header), &mut framed).await?;
-                }
-                ClientCommand::EndOfHeader(_v) => {
-                    Self::notify_respond_answer(self.milter.end_of_header(), &mut framed).await?;
-                }
-                ClientCommand::Body(body) => {
-                    Self::notify_respond_answer(self.milter.body(body), &mut framed).await?;
+            if self
+                .handle_command(command, &mut context, &mut framed)
+                .await?
+                .is_break()
+            {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle a single `command` received from the client, sending the
+    /// answers to `reply`.
+    ///
+    /// Breaks if the connection is done.
+    pub(crate) async fn handle_command<R: Reply>(
+        &mut self,
+        command: ClientCommand,
+        context: &mut Context,
+        reply: &mut R,
+    ) -> Result<ControlFlow<()>, Error<M::Error>> {
+        let protocol = context.options().map_or(Protocol::empty(), |o| o.protocol);
+        let stage = Stage::new(protocol, self.action_policy, &command);
+        if let Err(out_of_order) = context.advance(&command) {
+            if self.ordering_policy == OrderingPolicy::Reject {
+                return Err(ProtocolError::from(out_of_order).into());
+            }
+            debug!("{}", out_of_order);
+        }
+        context.record(&command);
+
+        #[cfg(feature = "tracing")]
+        let span = context.trace().span().clone();
+        let handled = self.command(command, stage, context, reply);
+        #[cfg(feature = "tracing")]
+        let handled = handled.instrument(span);
+        handled.await
+    }
+
+    /// Handle a single `command` received from the client.
+    ///
+    /// Breaks if the connection is done.
+    async fn command<R: Reply>(
+        &mut self,
+        command: ClientCommand,
+        stage: Stage,
+        context: &mut Context,
+        reply: &mut R,
+    ) -> Result<ControlFlow<()>, Error<M::Error>> {
+        match command {
+            // Regular smtp session related commands that need special responses
+            ClientCommand::EndOfBody(_v) => {
+                self.end_of_body(stage, context, reply).await?;
+                context.reset_message();
+            }
+            ClientCommand::Macro(macro_) => {
+                let outcome = call(&self.timeouts, self.milter.macro_(context, macro_)).await?;
+                self.notified(context, outcome)?;
+            }
+
+            // Control flow cases
+            // Option Negotiation
+            ClientCommand::OptNeg(opt_neg) => {
+                let callback = self.milter.option_negotiation(opt_neg);
+                let response = match call(&self.timeouts, callback).await? {
+                    Outcome::Done(response) => response,
+                    Outcome::Failed(error) => return Err(error),
+                    Outcome::TimedOut(limit) => return Err(Error::CallbackTimeout(limit)),
+                };
+                context.set_options(response.clone());
+                reply.reply(response.into()).await?;
+            }
+            // Abort the current smtp session handling
+            ClientCommand::Abort(_v) => {
+                let outcome = call(&self.timeouts, self.milter.abort(context)).await?;
+                self.notified(context, outcome)?;
+
+                if self.quit_on_abort {
+                    let outcome = call(&self.timeouts, self.milter.quit(context)).await?;
+                    self.notified(context, outcome)?;
+                    return Ok(ControlFlow::Break(()));
                 }
-                ClientCommand::Unknown(unknown) => {
-                    Self::notify_respond_answer(self.milter.unknown(unknown), &mut framed).await?;
+                context.reset_message();
+            }
+            // Quit this connection
+            ClientCommand::Quit(_v) => {
+                let outcome = call(&self.timeouts, self.milter.quit(context)).await?;
+                self.notified(context, outcome)?;
+                return Ok(ControlFlow::Break(()));
+            }
+            // Quit and re-use this connection
+            ClientCommand::QuitNc(_v) => {
+                let outcome = call(&self.timeouts, self.milter.quit_nc(context)).await?;
+                self.notified(context, outcome)?;
+                context.reset_session();
+            }
+
+            // All the regular smtp related commands
+            command => self.stage(command, stage, context, reply).await?,
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Notify the milter about a regular smtp command and respond with it's
+    /// answer.
+    ///
+    /// Respects the negotiated protocol: The milter is not notified about
+    /// disabled stages and no answer is sent if the client does not expect one.
+    async fn stage<R: Reply>(
+        &mut self,
+        command: ClientCommand,
+        stage: Stage,
+        context: &Context,
+        reply: &mut R,
+    ) -> Result<(), Error<M::Error>> {
+        #[cfg(feature = "metrics")]
+        let kind = (&command).into();
+        let milter = &mut *self.milter;
+        let callback = match command {
+            ClientCommand::Helo(helo) => milter.helo(context, helo),
+            ClientCommand::Connect(connect) => milter.connect(context, connect),
+            ClientCommand::Mail(mail) => milter.mail(context, mail),
+            ClientCommand::Recipient(rcpt) => milter.rcpt(context, rcpt),
+            ClientCommand::Data(_v) => milter.data(context),
+            ClientCommand::Header(header) => milter.header(context, header),
+            ClientCommand::EndOfHeader(_v) => milter.end_of_header(context),
+            ClientCommand::Body(body) => milter.body(context, body),
+            ClientCommand::Unknown(unknown) => milter.unknown(context, unknown),
+            _ => unreachable!("Not a regular smtp command: {command:?}"),
+        };
+
+        let response: Action = if stage.disabled {
+            debug!("Skip notifying milter, stage disabled by protocol");
+            Continue.into()
+        } else {
+            #[cfg(feature = "metrics")]
+            let start = Instant::now();
+            let response = match call(&self.timeouts, callback).await? {
+                Outcome::Done(response) => response,
+                Outcome::Failed(error) => self.recover(context, error)?,
+                Outcome::TimedOut(_limit) => {
+                    debug!("Milter callback timed out");
+                    self.timeouts.callback_action.clone()
                 }
-                // Regular smtp session related commands that need special responses
-                ClientCommand::EndOfBody(_v) => {
-                    // Notify the milter trait implementation
-                    let mut responses = self
-                        .milter
-                        .end_of_body()
-                        .await
-                        .map_err(Error::from_app_error)?;
-
-                    // Filter those returned mod requests, keep only those
-                    // which have been set by the current capabilities.
-                    responses.filter_mods_by_caps(
-                        options
-                            .as_ref()
-                            .map_or(Capability::all(), |o| o.capabilities),
-                    );
-
-                    // And send them back
-                    let responses: Vec<ServerMessage> = responses.into();
-                    for response in responses {
-                        debug!("Sending response");
-                        framed.send(&response).await?;
-                    }
+            };
+            metric!(metrics::callback_duration(
+                Side::Server,
+                kind,
+                start.elapsed()
+            ));
+            stage.policy.apply(response, stage)?
+        };
+        #[cfg(feature = "tracing")]
+        context.trace().action(&response);
+
+        if stage.no_reply {
+            if !matches!(response, Action::Continue(_)) {
+                warn!(%response, "Dropping verdict, no reply negotiated by protocol");
+            }
+            return Ok(());
+        }
+
+        reply.reply(response.into()).await?;
+        Ok(())
+    }
+
+    /// Notify the milter about the end of body and send back it's
+    /// modifications and final action
+    async fn end_of_body<R: Reply>(
+        &mut self,
+        stage: Stage,
+        context: &Context,
+        reply: &mut R,
+    ) -> Result<(), Error<M::Error>> {
+        // Notify the milter trait implementation
+        #[cfg(feature = "metrics")]
+        let start = Instant::now();
+        let callback = call(&self.timeouts, self.milter.end_of_body(context));
+        let mut responses =
+            match Self::with_progress(callback, self.progress_interval, reply).await? {
+                Outcome::Done(responses) => responses,
+                Outcome::Failed(error) => {
+                    ModificationResponse::builder().build(self.recover(context, error)?)
                 }
-                ClientCommand::Macro(macro_) => {
-                    self.milter
-                        .macro_(macro_)
-                        .await
-                        .map_err(Error::from_app_error)?;
+                Outcome::TimedOut(_limit) => {
+                    debug!("End of body timed out");
+                    ModificationResponse::builder().build(self.timeouts.callback_action.clone())
                 }
+            };
+        metric!(metrics::callback_duration(
+            Side::Server,
+            CommandKind::EndOfBody,
+            start.elapsed()
+        ));
 
-                // Control flow cases
-                // Option Negotiation
-                ClientCommand::OptNeg(opt_neg) => {
-                    let response = self.milter.option_negotiation(opt_neg).await?;
-                    options = Some(response.clone());
-                    framed.send(&response.into()).await?;
-                }
-                // Abort the current smtp session handling
-                ClientCommand::Abort(_v) => {
-                    self.milter.abort().await.map_err(Error::from_app_error)?;
+        // Check the final action against the negotiated protocol
+        let final_action = stage
+            .policy
+            .apply(responses.final_action().clone(), stage)?;
+        responses.set_final_action(final_action);
+
+        // Filter those returned mod requests, keep only those
+        // which have been set by the current capabilities.
+        responses.filter_mods_by_caps(
+            context
+                .options()
+                .map_or(Capability::all(), |o| o.capabilities),
+        );
+        #[cfg(feature = "tracing")]
+        context.trace().end_of_body(&responses);
 
-                    if self.quit_on_abort {
-                        self.milter.quit().await.map_err(Error::from_app_error)?;
-                        return Ok(());
+        // And send them back, splitting body replacements into packets the
+        // client accepts
+        let chunk_size =
+            ReplaceBody::MAX_CHUNK_SIZE.min(self.codec.max_buffer_size().saturating_sub(1));
+        let mut body_stream = responses.take_body_stream();
+        let mut responses: Vec<ServerMessage> = responses.into();
+        let final_action = responses.pop();
+
+        for response in responses {
+            debug!("Sending response");
+            match response {
+                ServerMessage::ModificationAction(ModificationAction::ReplaceBody(body)) => {
+                    for chunk in body.into_chunks(chunk_size) {
+                        reply
+                            .reply(ServerMessage::ModificationAction(chunk.into()))
+                            .await?;
                     }
                 }
-                // Quit this connection
-                ClientCommand::Quit(_v) => {
-                    self.milter.quit().await.map_err(Error::from_app_error)?;
-                    return Ok(());
-                }
-                // Quit and re-use this connection
-                ClientCommand::QuitNc(_v) => {
-                    self.milter.quit_nc().await.map_err(Error::from_app_error)?;
-                }
+                response => reply.reply(response).await?,
+            }
+        }
+
+        if let Some(body_stream) = &mut body_stream {
+            debug!("Sending streamed body");
+            loop {
+                let next = body_stream.next_chunk(chunk_size).await;
+                let Some(chunk) = next? else {
+                    break;
+                };
+                reply
+                    .reply(ServerMessage::ModificationAction(chunk.into()))
+                    .await?;
             }
         }
+
+        if let Some(final_action) = final_action {
+            reply.reply(final_action).await?;
+        }
         Ok(())
     }
 
-    /// Helper function to notify the milter, handle errors and respond
-    async fn notify_respond_answer<RW: AsyncRead + AsyncWrite + Unpin>(
-        milter_fn: impl Future<Output = Result<impl Into<Action>, M::Error>>,
-        framed: &mut Framed<RW, &mut MilterCodec>,
-    ) -> Result<(), milter::Error<M::Error>> {
-        let response = milter_fn.await.map_err(Error::from_app_error)?;
-        let response: Action = response.into();
+    /// Await `future`, sending progress packets every `progress_interval`
+    /// until it is ready.
+    async fn with_progress<R: Reply, T>(
+        future: impl Future<Output = Result<T, Error<M::Error>>>,
+        progress_interval: Option<Duration>,
+        reply: &mut R,
+    ) -> Result<T, Error<M::Error>> {
+        let Some(interval) = progress_interval else {
+            return future.await;
+        };
+
+        let mut future = pin!(future);
+        loop {
+            if let Either::Left((output, _delay)) =
+                select(future.as_mut(), Delay::new(interval)).await
+            {
+                return output;
+            }
+
+            debug!("Sending progress");
+            reply.reply(Progress.into()).await?;
+        }
+    }
+
+    /// Handle the outcome of a callback the client does not await an
+    /// answer to
+    fn notified(
+        &mut self,
+        context: &Context,
+        outcome: Outcome<(), M::Error>,
+    ) -> Result<(), Error<M::Error>> {
+        match outcome {
+            Outcome::Done(()) => Ok(()),
+            Outcome::Failed(error) => self.recover(context, error).map(drop),
+            Outcome::TimedOut(limit) => Err(Error::CallbackTimeout(limit)),
+        }
+    }
+
+    /// Apply the error policy to an `error` returned by the milter.
+    ///
+    /// Returns the action to reply with if the session is kept alive.
+    fn recover(&mut self, context: &Context, error: M::Error) -> Result<Action, Error<M::Error>> {
+        let ErrorPolicy::Reply(action) = &self.error_policy else {
+            return Err(Error::from_app_error(error));
+        };
+        let action = action.clone();
+
+        debug!("Milter callback failed, replying as configured");
+        catch_unwind(AssertUnwindSafe(|| self.milter.error(context, error)))
+            .map_err(|payload| Error::Panic(panic_message(payload.as_ref())))?;
+
+        Ok(action)
+    }
+}
+
+/// Where the answers to the client go
+#[async_trait]
+pub(crate) trait Reply: Send {
+    /// Send `message` to the client
+    async fn reply(&mut self, message: ServerMessage) -> Result<(), ProtocolError>;
+}
+
+#[async_trait]
+impl<RW: AsyncRead + AsyncWrite + Unpin + Send> Reply for Framed<RW, &mut MilterCodec> {
+    async fn reply(&mut self, message: ServerMessage) -> Result<(), ProtocolError> {
+        self.send(&message).await
+    }
+}
 
-        framed.send(&response.into()).await?;
+/// Collects the answers, e.g. for a [`MilterService`](service::MilterService)
+#[async_trait]
+impl Reply for Vec<ServerMessage> {
+    async fn reply(&mut self, message: ServerMessage) -> Result<(), ProtocolError> {
+        self.push(message);
         Ok(())
     }
 }
+
+/// Adapts the [`Server`] handling a connection or service call
+#[cfg(any(feature = "serve", feature = "tower"))]
+pub(crate) type Configure<M> = dyn for<'m> Fn(Server<'m, M>) -> Server<'m, M> + Send + Sync;
+
+/// How to handle a single stage according to the negotiated protocol
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Stage {
+    /// The client should not have sent this, do not notify the milter
+    pub(crate) disabled: bool,
+    /// The client does not expect a reply to this
+    pub(crate) no_reply: bool,
+    /// The client accepts a skip as response to this
+    pub(crate) skippable: bool,
+    /// How to handle invalid responses by the milter
+    pub(crate) policy: ActionPolicy,
+}
+
+impl Stage {
+    fn new(protocol: Protocol, policy: ActionPolicy, command: &ClientCommand) -> Self {
+        let (no_send, no_reply) = match command {
+            ClientCommand::Connect(_) => (Protocol::NO_CONNECT, Protocol::NR_CONNECT),
+            ClientCommand::Helo(_) => (Protocol::NO_HELO, Protocol::NR_HELO),
+            ClientCommand::Mail(_) => (Protocol::NO_MAIL, Protocol::NR_MAIL),
+            ClientCommand::Recipient(_) => (Protocol::NO_RECIPIENT, Protocol::NR_RECIPIENT),
+            ClientCommand::Data(_) => (Protocol::NO_DATA, Protocol::NR_DATA),
+            ClientCommand::Header(_) => (Protocol::NO_HEADER, Protocol::NR_HEADER),
+            ClientCommand::EndOfHeader(_) => {
+                (Protocol::NO_END_OF_HEADER, Protocol::NR_END_OF_HEADER)
+            }
+            ClientCommand::Body(_) => (Protocol::NO_BODY, Protocol::NR_BODY),
+            ClientCommand::Unknown(_) => (Protocol::NO_UNKNOWN, Protocol::NR_UNKNOWN),
+            _ => return Self::default(),
+        };
+
+        Self {
+            disabled: protocol.contains(no_send),
+            no_reply: protocol.contains(no_reply),
+            skippable: matches!(command, ClientCommand::Body(_))
+                && protocol.contains(Protocol::SMFIP_SKIP),
+            policy,
+        }
+    }
+}
@@ -1,8 +1,11 @@
-use std::io;
+#[cfg(feature = "serve")]
+use std::convert::Infallible;
+use std::{io, time::Duration};
 
 use async_trait::async_trait;
 use thiserror::Error;
 
+use crate::Context;
 use miltr_common::{
     actions::{Action, Continue},
     commands::{Body, Connect, Header, Helo, Macro, Mail, Recipient, Unknown},
@@ -13,6 +16,15 @@ use miltr_common::{
 
 /// A trait to implement a working milter server.
 ///
+/// Every callback but [`Milter::option_negotiation`] receives the
+/// [`Context`] of the current session, which holds the negotiated options
+/// and everything the client sent so far.
+///
+/// Stages negotiated with an `NR_*` [`Protocol`](miltr_common::optneg::Protocol)
+/// flag get no reply, so a verdict other than [`Continue`] returned there
+/// never reaches the client. Depending on the [`ActionPolicy`](crate::ActionPolicy)
+/// it fails the connection or is logged and ignored.
+///
 /// See examples on how to implement this.
 #[async_trait]
 pub trait Milter: Send {
@@ -31,22 +43,28 @@ pub trait Milter: Send {
     }
 
     /// A macro sent by the milter client.
+    ///
+    /// The macros have already been added to the [`Context`] passed along.
     #[doc(alias = "SMFIC_MACRO")]
-    async fn macro_(&mut self, _macro: Macro) -> Result<(), Self::Error> {
+    async fn macro_(&mut self, _context: &Context, _macro: Macro) -> Result<(), Self::Error> {
         Ok(())
     }
 
     /// Connection information about the smtp connection.
     #[doc(alias = "SMFIC_CONNECT")]
     #[doc(alias = "xxfi_connect")]
-    async fn connect(&mut self, _connect_info: Connect) -> Result<Action, Self::Error> {
+    async fn connect(
+        &mut self,
+        _context: &Context,
+        _connect_info: Connect,
+    ) -> Result<Action, Self::Error> {
         Ok(Continue.into())
     }
 
     /// The helo name sent by the smtp client.
     #[doc(alias = "SMFIC_HELO")]
     #[doc(alias = "xxfi_helo")]
-    async fn helo(&mut self, _helo: Helo) -> Result<Action, Self::Error> {
+    async fn helo(&mut self, _context: &Context, _helo: Helo) -> Result<Action, Self::Error> {
         Ok(Continue.into())
     }
 
@@ -54,7 +72,7 @@ pub trait Milter: Send {
     #[doc(alias = "SMFIC_MAIL")]
     #[doc(alias = "from")]
     #[doc(alias = "xxfi_envfrom")]
-    async fn mail(&mut self, _mail: Mail) -> Result<Action, Self::Error> {
+    async fn mail(&mut self, _context: &Context, _mail: Mail) -> Result<Action, Self::Error> {
         Ok(Continue.into())
     }
 
@@ -62,7 +80,11 @@ pub trait Milter: Send {
     #[doc(alias = "SMFIC_RCPT")]
     #[doc(alias = "to")]
     #[doc(alias = "xxfi_envrcpt")]
-    async fn rcpt(&mut self, _recipient: Recipient) -> Result<Action, Self::Error> {
+    async fn rcpt(
+        &mut self,
+        _context: &Context,
+        _recipient: Recipient,
+    ) -> Result<Action, Self::Error> {
         Ok(Continue.into())
     }
 
@@ -72,7 +94,7 @@ pub trait Milter: Send {
     /// data.
     #[doc(alias = "SMFIC_DATA")]
     #[doc(alias = "xxfi_data")]
-    async fn data(&mut self) -> Result<Action, Self::Error> {
+    async fn data(&mut self, _context: &Context) -> Result<Action, Self::Error> {
         Ok(Continue.into())
     }
 
@@ -81,14 +103,14 @@ pub trait Milter: Send {
     /// Header names are not unique and might be received multiple times.
     #[doc(alias = "SMFIC_HEADER")]
     #[doc(alias = "xxfi_header")]
-    async fn header(&mut self, _header: Header) -> Result<Action, Self::Error> {
+    async fn header(&mut self, _context: &Context, _header: Header) -> Result<Action, Self::Error> {
         Ok(Continue.into())
     }
 
     /// Called after all headers have been sent.
     #[doc(alias = "SMFIC_EOH")]
     #[doc(alias = "xxfi_eoh")]
-    async fn end_of_header(&mut self) -> Result<Action, Self::Error> {
+    async fn end_of_header(&mut self, _context: &Context) -> Result<Action, Self::Error> {
         Ok(Continue.into())
     }
 
@@ -97,7 +119,7 @@ pub trait Milter: Send {
     /// This may be called multiple times until the whole body was transmitted.
     #[doc(alias = "SMFIC_BODY")]
     #[doc(alias = "xxfi_body")]
-    async fn body(&mut self, _body: Body) -> Result<Action, Self::Error> {
+    async fn body(&mut self, _context: &Context, _body: Body) -> Result<Action, Self::Error> {
         Ok(Continue.into())
     }
 
@@ -107,14 +129,17 @@ pub trait Milter: Send {
     /// to the milter client.
     #[doc(alias = "SMFIC_BODYEOB")]
     #[doc(alias = "xxfi_eom")]
-    async fn end_of_body(&mut self) -> Result<ModificationResponse, Self::Error> {
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
         Ok(ModificationResponse::empty_continue())
     }
 
     /// A command not matching any Code is received as `unknown`.
     #[doc(alias = "SMFIC_UNKNOWN")]
     #[doc(alias = "xxfi_unknown")]
-    async fn unknown(&mut self, _cmd: Unknown) -> Result<Action, Self::Error> {
+    async fn unknown(&mut self, _context: &Context, _cmd: Unknown) -> Result<Action, Self::Error> {
         Ok(Continue.into())
     }
 
@@ -131,7 +156,7 @@ pub trait Milter: Send {
     /// See [`Server::default_postfix`](crate::Server::default_postfix).
     #[doc(alias = "SMFIC_ABORT")]
     #[doc(alias = "xxfi_abort")]
-    async fn abort(&mut self) -> Result<(), Self::Error>;
+    async fn abort(&mut self, context: &Context) -> Result<(), Self::Error>;
 
     /// Called on quitting a connection from a milter client.
     ///
@@ -141,15 +166,22 @@ pub trait Milter: Send {
     /// See [`Server::default_postfix`](crate::Server::default_postfix).
     #[doc(alias = "SMFIC_QUIT")]
     #[doc(alias = "xxfi_close")]
-    async fn quit(&mut self) -> Result<(), Self::Error> {
+    async fn quit(&mut self, _context: &Context) -> Result<(), Self::Error> {
         Ok(())
     }
 
     /// Called when a milter client want's to re-use this milter for a new mail.
     #[doc(alias = "SMFIC_QUIT_NC")]
-    async fn quit_nc(&mut self) -> Result<(), Self::Error> {
+    async fn quit_nc(&mut self, _context: &Context) -> Result<(), Self::Error> {
         Ok(())
     }
+
+    /// Called with the error another callback returned, if the
+    /// [`ErrorPolicy`](crate::ErrorPolicy) keeps the session alive.
+    ///
+    /// Use this to report the error, the client is answered as configured
+    /// afterwards.
+    fn error(&mut self, _context: &Context, _error: Self::Error) {}
 }
 
 /// The main error for this crate encapsulating the different error cases.
@@ -172,10 +204,56 @@ pub enum Error<ImplError> {
         /// The application error patched through
         source: ImplError,
     },
+
+    /// The milter trait implementation responded with an action the client
+    /// did not agree to or which is invalid at this stage.
+    ///
+    /// Only returned using [`ActionPolicy::Strict`](crate::ActionPolicy::Strict).
+    #[error("Milter responded with an invalid action {action:?}: {reason}")]
+    InvalidAction {
+        /// The offending action
+        action: Box<Action>,
+        /// Why this action is invalid
+        reason: &'static str,
+    },
+
+    /// The client did not send a command within the read timeout.
+    ///
+    /// See [`Server::with_read_timeout`](crate::Server::with_read_timeout).
+    #[error("Client did not send a command within {0:?}")]
+    ReadTimeout(Duration),
+
+    /// A milter callback not answering the client did not finish within the
+    /// callback timeout.
+    ///
+    /// See [`Server::with_callback_timeout`](crate::Server::with_callback_timeout).
+    #[error("Milter callback did not finish within {0:?}")]
+    CallbackTimeout(Duration),
+
+    /// A milter callback panicked.
+    ///
+    /// The panic is caught and only ends the current connection.
+    #[error("Milter callback panicked: {0}")]
+    Panic(String),
 }
 
 impl<AppError> Error<AppError> {
     pub(crate) fn from_app_error(source: AppError) -> Self {
         Self::Impl { source }
     }
+
+    /// Split off the application error, to display any other error
+    /// without requiring the application error to be displayable.
+    #[cfg(feature = "serve")]
+    pub(crate) fn into_app_error(self) -> Result<AppError, Error<Infallible>> {
+        match self {
+            Self::Impl { source } => Ok(source),
+            Self::Io(err) => Err(Error::Io(err)),
+            Self::Codec(err) => Err(Error::Codec(err)),
+            Self::InvalidAction { action, reason } => Err(Error::InvalidAction { action, reason }),
+            Self::ReadTimeout(limit) => Err(Error::ReadTimeout(limit)),
+            Self::CallbackTimeout(limit) => Err(Error::CallbackTimeout(limit)),
+            Self::Panic(message) => Err(Error::Panic(message)),
+        }
+    }
 }
@@ -0,0 +1,274 @@
+//! Policies on how to handle what the milter implementation responds with
+
+use miltr_common::actions::{Action, Continue, Replycode, Tempfail};
+use miltr_utils::warn;
+
+use crate::{Error, Stage};
+
+/// How to handle actions the MTA did not agree to during option negotiation
+/// or which are not valid at the current stage.
+///
+/// Invalid actions are:
+/// - [`Skip`](miltr_common::actions::Skip) without
+///   [`Protocol::SMFIP_SKIP`](miltr_common::optneg::Protocol::SMFIP_SKIP)
+///   negotiated or outside of a body chunk.
+/// - [`Replycode`] with an rcode outside of the 4xx and 5xx classes or an
+///   xcode of a different class than the rcode.
+/// - [`Abort`](miltr_common::actions::Abort),
+///   [`Quit`](miltr_common::actions::Quit) and
+///   [`QuitNc`](miltr_common::actions::QuitNc), which may only be sent by the client.
+/// - Anything but [`Continue`] at a stage the client does not expect a reply
+///   to, negotiated using the `NR_*` [`Protocol`](miltr_common::optneg::Protocol)
+///   flags. Such a verdict is never sent, so the client would not act on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ActionPolicy {
+    /// Error out of the connection with [`Error::InvalidAction`].
+    Strict,
+    /// Replace invalid actions with the closest valid one:
+    /// - Skip, Abort, Quit and `QuitNc` are replaced by Continue
+    /// - Verdicts at a stage without reply are logged and dropped
+    /// - A Replycode with a mismatching xcode is sent without the xcode
+    /// - A Replycode with an invalid rcode class is replaced by Tempfail
+    #[default]
+    Downgrade,
+    /// Send every action as is.
+    PassThrough,
+}
+
+impl ActionPolicy {
+    /// Apply this policy to `action`, responding to a command at `stage`.
+    pub(crate) fn apply<E>(self, action: Action, stage: Stage) -> Result<Action, Error<E>> {
+        let Err(reason) = validate(&action, stage) else {
+            return Ok(action);
+        };
+
+        match self {
+            Self::Strict => Err(Error::InvalidAction {
+                action: Box::new(action),
+                reason,
+            }),
+            Self::Downgrade => {
+                warn!(%action, reason, "Downgrading invalid action");
+                Ok(downgrade(action, stage))
+            }
+            Self::PassThrough => Ok(action),
+        }
+    }
+}
+
+/// How to handle errors returned by the milter implementation.
+///
+/// Panics of the milter implementation always close the connection with
+/// [`Error::Panic`].
+#[derive(Debug, Clone, Default)]
+pub enum ErrorPolicy {
+    /// Close the connection, returning [`Error::Impl`] from
+    /// [`Server::handle_connection`](crate::Server::handle_connection).
+    ///
+    /// The MTA then applies it's default action, e.g. postfix'
+    /// `milter_default_action`.
+    #[default]
+    Close,
+    /// Report the error to [`Milter::error`](crate::Milter::error) and
+    /// keep the session alive.
+    ///
+    /// If the client awaits an answer to the failed callback, it is sent
+    /// this action, e.g. [`Tempfail`] or a [`Replycode`] with a message.
+    Reply(Action),
+}
+
+/// How to handle commands the client sends out of the protocol order, e.g.
+/// a body before the envelope sender.
+///
+/// See [`SessionState`](miltr_common::state::SessionState) for the order
+/// commands are expected in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OrderingPolicy {
+    /// Log the out of order command and handle it regardless.
+    #[default]
+    Report,
+    /// Close the connection, returning [`Error::Codec`] with
+    /// [`ProtocolError::OutOfOrder`](miltr_common::ProtocolError::OutOfOrder).
+    Reject,
+}
+
+/// Check whether `action` is valid as a response at `stage`
+fn validate(action: &Action, stage: Stage) -> Result<(), &'static str> {
+    match action {
+        Action::Continue(_) => Ok(()),
+        _ if stage.no_reply => Err("the client does not expect a reply at this stage"),
+        Action::Skip(_) if !stage.skippable => {
+            Err("skip was not negotiated or is not valid at this stage")
+        }
+        Action::Abort(_) => Err("abort may only be sent by the client"),
+        Action::Quit(_) | Action::QuitNc(_) => Err("quit may only be sent by the client"),
+        Action::Replycode(replycode) => {
+            let class = replycode.rcode().code()[0];
+            if !matches!(class, 4 | 5) {
+                return Err("reply code is not of class 4xx or 5xx");
+            }
+            match replycode.xcode() {
+                Some(xcode) if xcode.code()[0] != u16::from(class) => {
+                    Err("extended reply code does not match reply code class")
+                }
+                _ => Ok(()),
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Replace `action` with the closest valid action
+fn downgrade(action: Action, stage: Stage) -> Action {
+    match action {
+        _ if stage.no_reply => Continue.into(),
+        Action::Skip(_) | Action::Abort(_) | Action::Quit(_) | Action::QuitNc(_) => Continue.into(),
+        Action::Replycode(replycode) => {
+            let rcode = replycode.rcode().code();
+            if matches!(rcode[0], 4 | 5) {
+                Replycode::without_xcode(rcode, &replycode.message()).into()
+            } else {
+                Tempfail.into()
+            }
+        }
+        action => action,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+    use miltr_common::actions::{Abort, Quit, Reject, Skip};
+
+    use super::*;
+
+    fn body_stage(skippable: bool) -> Stage {
+        Stage {
+            skippable,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_skip_negotiated() {
+        let action = ActionPolicy::Strict
+            .apply::<()>(Skip.into(), body_stage(true))
+            .expect("Skip was negotiated");
+
+        assert_matches!(action, Action::Skip(_));
+    }
+
+    #[test]
+    fn test_skip_not_negotiated() {
+        let err = ActionPolicy::Strict
+            .apply::<()>(Skip.into(), body_stage(false))
+            .expect_err("Skip was not negotiated");
+        assert_matches!(err, Error::InvalidAction { action, .. } if matches!(*action, Action::Skip(_)));
+
+        let action = ActionPolicy::Downgrade
+            .apply::<()>(Skip.into(), body_stage(false))
+            .expect("Downgrade does not error");
+        assert_matches!(action, Action::Continue(_));
+
+        let action = ActionPolicy::PassThrough
+            .apply::<()>(Skip.into(), body_stage(false))
+            .expect("Pass through does not error");
+        assert_matches!(action, Action::Skip(_));
+    }
+
+    #[test]
+    fn test_quit() {
+        let action = ActionPolicy::Downgrade
+            .apply::<()>(Quit.into(), Stage::default())
+            .expect("Downgrade does not error");
+
+        assert_matches!(action, Action::Continue(_));
+    }
+
+    #[test]
+    fn test_abort() {
+        let err = ActionPolicy::Strict
+            .apply::<()>(Abort.into(), Stage::default())
+            .expect_err("Abort is client only");
+        assert_matches!(err, Error::InvalidAction { action, .. } if matches!(*action, Action::Abort(_)));
+
+        let action = ActionPolicy::Downgrade
+            .apply::<()>(Abort.into(), Stage::default())
+            .expect("Downgrade does not error");
+        assert_matches!(action, Action::Continue(_));
+    }
+
+    #[test]
+    fn test_no_reply() {
+        let stage = Stage {
+            no_reply: true,
+            ..Default::default()
+        };
+
+        let err = ActionPolicy::Strict
+            .apply::<()>(Reject.into(), stage)
+            .expect_err("Reject would not be sent");
+        assert_matches!(err, Error::InvalidAction { action, .. } if matches!(*action, Action::Reject(_)));
+
+        let action = ActionPolicy::Strict
+            .apply::<()>(Continue.into(), stage)
+            .expect("Continue is fine without reply");
+        assert_matches!(action, Action::Continue(_));
+
+        let action = ActionPolicy::Downgrade
+            .apply::<()>(Reject.into(), stage)
+            .expect("Downgrade does not error");
+        assert_matches!(action, Action::Continue(_));
+    }
+
+    #[test]
+    fn test_replycode_valid() {
+        let action = ActionPolicy::Strict
+            .apply::<()>(
+                Replycode::new([5, 5, 0], [5, 7, 1], "Go away").into(),
+                Stage::default(),
+            )
+            .expect("Replycode is valid");
+
+        assert_matches!(action, Action::Replycode(r) if r.xcode().is_some());
+    }
+
+    #[test]
+    fn test_replycode_xcode_mismatch() {
+        let action = ActionPolicy::Downgrade
+            .apply::<()>(
+                Replycode::new([4, 5, 1], [5, 7, 1], "Try again").into(),
+                Stage::default(),
+            )
+            .expect("Downgrade does not error");
+
+        assert_matches!(
+            action,
+            Action::Replycode(r) if r.xcode().is_none() && r.rcode().code() == [4, 5, 1] && r.message() == "Try again"
+        );
+    }
+
+    #[test]
+    fn test_replycode_invalid_class() {
+        let replycode: Action = Replycode::new([2, 5, 0], [2, 0, 0], "Ok").into();
+
+        let err = ActionPolicy::Strict
+            .apply::<()>(replycode.clone(), Stage::default())
+            .expect_err("Replycode class is invalid");
+        assert_matches!(err, Error::InvalidAction { .. });
+
+        let action = ActionPolicy::Downgrade
+            .apply::<()>(replycode, Stage::default())
+            .expect("Downgrade does not error");
+        assert_matches!(action, Action::Tempfail(_));
+    }
+
+    #[test]
+    fn test_other_actions_untouched() {
+        let action = ActionPolicy::Strict
+            .apply::<()>(Reject.into(), Stage::default())
+            .expect("Reject is always valid");
+
+        assert_matches!(action, Action::Reject(_));
+    }
+}
@@ -0,0 +1,479 @@
+//! Sockets to accept milter connections on
+
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+use std::{io, net::SocketAddr, sync::Mutex};
+
+use miltr_common::socket::{resolve, SocketSpec};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::{
+    io::{Join, Stdin, Stdout},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use miltr_utils::debug;
+
+/// A socket accepting milter connections
+#[derive(Debug)]
+pub enum Listener {
+    /// Listen on a tcp socket
+    Tcp(TcpListener),
+    /// Listen on a unix domain socket, removing the socket file on drop
+    #[cfg(unix)]
+    Unix(UnixListener, SocketFileGuard),
+    /// The single connection on stdin and stdout, see [`Listener::stdio`]
+    Stdio(StdioListener),
+}
+
+/// A single connection accepted by a [`Listener`]
+#[derive(Debug)]
+pub enum Stream {
+    /// A tcp connection
+    Tcp(TcpStream),
+    /// A unix domain socket connection
+    #[cfg(unix)]
+    Unix(UnixStream),
+    /// The connection on stdin and stdout
+    Stdio(Join<Stdin, Stdout>),
+}
+
+impl Listener {
+    /// Listen on the socket described by `spec`, e.g. `inet:10025@127.0.0.1`.
+    ///
+    /// Inet sockets without a host listen on all addresses of their family.
+    /// Unix sockets are bound using the default [`UnixSocket`] setup.
+    ///
+    /// # Errors
+    /// - if the host does not resolve to an address of the spec's family
+    /// - [`io::ErrorKind::Unsupported`] for unix sockets on other platforms
+    /// - if binding the socket fails
+    pub async fn bind(spec: &SocketSpec) -> io::Result<Self> {
+        match spec {
+            SocketSpec::Inet { host, port } => {
+                let host = host.as_deref().unwrap_or("0.0.0.0");
+                Self::bind_tcp(resolve(host, *port, SocketAddr::is_ipv4).await?.as_slice()).await
+            }
+            SocketSpec::Inet6 { host, port } => {
+                let host = host.as_deref().unwrap_or("::");
+                Self::bind_tcp(resolve(host, *port, SocketAddr::is_ipv6).await?.as_slice()).await
+            }
+            #[cfg(unix)]
+            SocketSpec::Unix(path) => Self::bind_unix(path),
+            #[cfg(not(unix))]
+            SocketSpec::Unix(_path) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix domain sockets are not supported on this platform",
+            )),
+        }
+    }
+
+    /// Listen on the tcp socket `addr`
+    ///
+    /// # Errors
+    /// If binding to `addr` fails.
+    pub async fn bind_tcp<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Ok(Self::Tcp(TcpListener::bind(addr).await?))
+    }
+
+    /// Listen on the unix domain socket at `path` using the default
+    /// [`UnixSocket`] setup.
+    ///
+    /// # Errors
+    /// See [`UnixSocket::bind`].
+    #[cfg(unix)]
+    pub fn bind_unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        UnixSocket::new(path).bind()
+    }
+
+    /// Listen on the sockets passed by systemd socket activation, taken
+    /// by [`SystemdSockets::take`].
+    ///
+    /// The socket files of unix sockets are owned by systemd and not removed
+    /// on drop. This must be called within a tokio runtime.
+    ///
+    /// # Errors
+    /// If registering a socket with the tokio runtime fails.
+    #[cfg(unix)]
+    pub fn from_systemd(sockets: SystemdSockets) -> io::Result<Vec<Self>> {
+        sockets
+            .sockets
+            .into_iter()
+            .map(|socket| match socket {
+                SystemdSocket::Tcp(listener) => Ok(Self::Tcp(TcpListener::from_std(listener)?)),
+                SystemdSocket::Unix(listener) => {
+                    let guard = SocketFileGuard { path: None };
+                    Ok(Self::Unix(UnixListener::from_std(listener)?, guard))
+                }
+            })
+            .collect()
+    }
+
+    /// Handle a single connection on stdin and stdout, like inetd starts
+    /// `nowait` services.
+    ///
+    /// The connection is accepted once, afterwards
+    /// [`Serve`](super::Serve) returns as soon as it was handled. Nothing
+    /// else may write to stdout meanwhile, e.g. logs need to go to stderr.
+    #[must_use]
+    pub fn stdio() -> Self {
+        let stream = tokio::io::join(tokio::io::stdin(), tokio::io::stdout());
+        Self::Stdio(StdioListener {
+            stream: Mutex::new(Some(stream)),
+        })
+    }
+
+    /// Accept the next connection, `None` if no more connections will come
+    pub(crate) async fn accept(&self) -> io::Result<Option<Stream>> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, _addr) = listener.accept().await?;
+                Ok(Some(Stream::Tcp(stream)))
+            }
+            #[cfg(unix)]
+            Self::Unix(listener, _guard) => {
+                let (stream, _addr) = listener.accept().await?;
+                Ok(Some(Stream::Unix(stream)))
+            }
+            Self::Stdio(listener) => Ok(listener
+                .stream
+                .lock()
+                .expect("Stdio listener poisoned")
+                .take()
+                .map(Stream::Stdio)),
+        }
+    }
+}
+
+impl From<TcpListener> for Listener {
+    fn from(listener: TcpListener) -> Self {
+        Self::Tcp(listener)
+    }
+}
+
+/// The sockets passed by systemd socket activation.
+///
+/// Systemd passes the sockets of a `.socket` unit as file descriptors
+/// starting at 3, announced by `LISTEN_FDS` and `LISTEN_PID`. Taking them
+/// removes both variables, so child processes do not pick them up.
+/// Modifying the environment is only sound while no other thread reads it,
+/// so take the sockets before starting the tokio runtime and turn them into
+/// listeners within it:
+///
+/// ```no_run
+/// # use async_trait::async_trait;
+/// # use miltr_server::{Context, Milter};
+/// use miltr_server::serve::{Listener, Serve, SystemdSockets};
+///
+/// # #[derive(Default)]
+/// # struct MyMilter;
+/// # #[async_trait]
+/// # impl Milter for MyMilter {
+/// #     type Error = &'static str;
+/// #     async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+/// #         Ok(())
+/// #     }
+/// # }
+/// fn main() -> std::io::Result<()> {
+///     let sockets = SystemdSockets::take()?;
+///
+///     tokio::runtime::Runtime::new()?.block_on(async {
+///         let listeners = Listener::from_systemd(sockets)?;
+///         Serve::new(MyMilter::default).run_all(listeners).await
+///     })
+/// }
+/// ```
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct SystemdSockets {
+    sockets: Vec<SystemdSocket>,
+}
+
+/// A single socket passed by systemd
+#[cfg(unix)]
+#[derive(Debug)]
+enum SystemdSocket {
+    Tcp(std::net::TcpListener),
+    Unix(std::os::unix::net::UnixListener),
+}
+
+#[cfg(unix)]
+impl SystemdSockets {
+    /// Take the sockets passed to this process, none if it was not socket
+    /// activated.
+    ///
+    /// Call this before starting any threads, see [`SystemdSockets`].
+    ///
+    /// # Errors
+    /// If a passed file descriptor is neither a tcp nor a unix stream socket.
+    pub fn take() -> io::Result<Self> {
+        let mut fds = listenfd::ListenFd::from_env();
+
+        let sockets = (0..fds.len())
+            .map(|idx| {
+                if let Ok(Some(listener)) = fds.take_tcp_listener(idx) {
+                    listener.set_nonblocking(true)?;
+                    return Ok(SystemdSocket::Tcp(listener));
+                }
+                if let Ok(Some(listener)) = fds.take_unix_listener(idx) {
+                    listener.set_nonblocking(true)?;
+                    return Ok(SystemdSocket::Unix(listener));
+                }
+
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Socket activation fd {} is neither a tcp nor a unix stream socket",
+                        idx + 3
+                    ),
+                ))
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Self { sockets })
+    }
+
+    /// The number of sockets passed
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.sockets.len()
+    }
+
+    /// Whether no sockets were passed
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.sockets.is_empty()
+    }
+}
+
+/// Hands out the connection on stdin and stdout once
+#[derive(Debug)]
+pub struct StdioListener {
+    stream: Mutex<Option<Join<Stdin, Stdout>>>,
+}
+
+/// Setup of a unix domain socket to listen on.
+///
+/// MTAs like postfix often run as a different user than the milter, so
+/// the socket file usually needs to be accessible by them:
+///
+/// ```no_run
+/// # use miltr_server::serve::UnixSocket;
+/// # fn bind() -> std::io::Result<()> {
+/// let listener = UnixSocket::new("/var/spool/postfix/milter/miltr.sock")
+///     .with_mode(0o660)
+///     .with_owner(None, Some(120))
+///     .bind()?;
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct UnixSocket {
+    path: PathBuf,
+    mode: Option<u32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+}
+
+#[cfg(unix)]
+impl UnixSocket {
+    /// Setup a socket at `path`
+    #[must_use]
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            mode: None,
+            uid: None,
+            gid: None,
+        }
+    }
+
+    /// Set the permissions of the socket file, e.g. `0o660`
+    #[must_use]
+    pub fn with_mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Set the owning user and group of the socket file.
+    ///
+    /// `None` leaves the respective id unchanged.
+    #[must_use]
+    pub fn with_owner(mut self, uid: Option<u32>, gid: Option<u32>) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    /// Bind the socket.
+    ///
+    /// A stale socket file left over by a previous process is removed.
+    /// The socket file is removed again once the [`Listener`] is dropped.
+    ///
+    /// # Errors
+    /// - [`io::ErrorKind::AddrInUse`] if another process is listening on
+    ///   this socket
+    /// - [`io::ErrorKind::AlreadyExists`] if `path` exists, but is not a
+    ///   socket
+    /// - on any io error binding the socket or setting it's permissions
+    pub fn bind(self) -> io::Result<Listener> {
+        remove_stale_socket(&self.path)?;
+
+        let listener = UnixListener::bind(&self.path)?;
+        let guard = SocketFileGuard {
+            path: Some(self.path.clone()),
+        };
+
+        if let Some(mode) = self.mode {
+            fs::set_permissions(&self.path, fs::Permissions::from_mode(mode))?;
+        }
+        if self.uid.is_some() || self.gid.is_some() {
+            std::os::unix::fs::chown(&self.path, self.uid, self.gid)?;
+        }
+
+        Ok(Listener::Unix(listener, guard))
+    }
+}
+
+/// Remove the socket file at `path` if no one is listening on it anymore
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_stream) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is in use by another process", path.display()),
+        )),
+        Err(_err) => {
+            debug!("Removing stale socket file {}", path.display());
+            fs::remove_file(path)
+        }
+    }
+}
+
+/// Removes the socket file of a [`Listener`] on drop
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct SocketFileGuard {
+    /// `None` for sockets owned by someone else, e.g. systemd
+    path: Option<PathBuf>,
+}
+
+#[cfg(unix)]
+impl Drop for SocketFileGuard {
+    fn drop(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if fs::remove_file(path).is_err() {
+            debug!("Failed removing socket file {}", path.display());
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("miltr-{}-{name}.sock", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_cleanup() {
+        let path = socket_path("cleanup");
+
+        let listener = UnixSocket::new(&path)
+            .with_mode(0o600)
+            .bind()
+            .expect("Failed binding socket");
+        let mode = fs::metadata(&path)
+            .expect("Socket missing")
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        drop(listener);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_stale() {
+        let path = socket_path("stale");
+
+        // A std listener dropped without removing it's file leaves it stale
+        drop(std::os::unix::net::UnixListener::bind(&path).expect("Failed binding socket"));
+        assert!(path.exists());
+
+        let _listener = UnixSocket::new(&path)
+            .bind()
+            .expect("Stale socket not removed");
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_in_use() {
+        let path = socket_path("in-use");
+
+        let _listener = UnixSocket::new(&path)
+            .bind()
+            .expect("Failed binding socket");
+        let err = UnixSocket::new(&path)
+            .bind()
+            .expect_err("Bound socket in use");
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+    }
+
+    #[tokio::test]
+    async fn test_bind_spec() {
+        let spec = "inet:0@127.0.0.1".parse().expect("Invalid spec");
+        let listener = Listener::bind(&spec).await.expect("Failed binding tcp");
+        assert!(matches!(listener, Listener::Tcp(_)));
+
+        let path = socket_path("spec");
+        let spec = format!("local:{}", path.display())
+            .parse()
+            .expect("Invalid spec");
+        let listener = Listener::bind(&spec).await.expect("Failed binding unix");
+        assert!(matches!(listener, Listener::Unix(..)));
+        assert!(path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_bind_spec_wrong_family() {
+        let spec = "inet6:0@127.0.0.1".parse().expect("Invalid spec");
+        let err = Listener::bind(&spec)
+            .await
+            .expect_err("Bound an ipv4 address as inet6");
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_not_a_socket() {
+        let path = socket_path("file");
+        fs::write(&path, b"").expect("Failed creating file");
+
+        let err = UnixSocket::new(&path)
+            .bind()
+            .expect_err("Replaced a regular file");
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        fs::remove_file(&path).expect("Failed removing file");
+    }
+}
@@ -0,0 +1,281 @@
+//! A tokio based runtime accepting milter connections.
+//!
+//! Instead of writing an accept loop that creates a new milter and a new
+//! [`Server`] per connection, hand a [`MilterFactory`] to [`Serve`] and let
+//! it handle the [`Listener`]:
+//!
+//! ```no_run
+//! # use async_trait::async_trait;
+//! # use miltr_server::{Context, Milter};
+//! use miltr_server::serve::{Listener, Serve};
+//!
+//! # #[derive(Default)]
+//! # struct MyMilter;
+//! # #[async_trait]
+//! # impl Milter for MyMilter {
+//! #     type Error = &'static str;
+//! #     async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+//! #         Ok(())
+//! #     }
+//! # }
+//! # async fn run() -> std::io::Result<()> {
+//! let listener = Listener::bind_tcp("127.0.0.1:8080").await?;
+//!
+//! Serve::new(MyMilter::default)
+//!     .with_max_connections(64)
+//!     .run_until(listener, async {
+//!         tokio::signal::ctrl_c().await.ok();
+//!     })
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Instead of binding a socket itself, a milter can be handed it's sockets
+//! by systemd socket activation using [`SystemdSockets`], or be started per
+//! connection by inetd using [`Listener::stdio`]. [`Serve::run_all`] serves
+//! several listeners at once.
+
+mod listener;
+
+use std::{future::Future, io, sync::Arc};
+
+use futures::future::select_all;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::Semaphore,
+    task::JoinSet,
+};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+use miltr_utils::{debug, warn};
+
+use crate::{Configure, Milter, Server};
+
+pub use listener::{Listener, StdioListener, Stream};
+#[cfg(unix)]
+pub use listener::{SystemdSockets, UnixSocket};
+
+/// The default cap of concurrently handled connections
+pub const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+
+/// Creates a fresh milter for every accepted connection.
+///
+/// Implemented for every closure returning a milter, so e.g.
+/// `MyMilter::default` can be used as a factory directly.
+pub trait MilterFactory: Send + Sync + 'static {
+    /// The milter created by this factory
+    type Milter: Milter + 'static;
+
+    /// Create a new milter to handle a single connection
+    fn create(&self) -> Self::Milter;
+}
+
+impl<F, M> MilterFactory for F
+where
+    F: Fn() -> M + Send + Sync + 'static,
+    M: Milter + 'static,
+{
+    type Milter = M;
+
+    fn create(&self) -> Self::Milter {
+        self()
+    }
+}
+
+/// Serve milter connections accepted on a [`Listener`].
+///
+/// Every connection is handled in its own tokio task using a new milter
+/// created by the [`MilterFactory`].
+pub struct Serve<F: MilterFactory> {
+    factory: Arc<F>,
+    configure: Arc<Configure<F::Milter>>,
+    max_connections: usize,
+}
+
+impl<F: MilterFactory> Serve<F> {
+    /// Serve milters created by `factory`
+    #[must_use]
+    pub fn new(factory: F) -> Self {
+        Self {
+            factory: Arc::new(factory),
+            configure: Arc::new(|server| server),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+        }
+    }
+
+    /// Cap the number of concurrently handled connections.
+    ///
+    /// No new connections are accepted while `max_connections` are handled.
+    /// Defaults to [`DEFAULT_MAX_CONNECTIONS`].
+    ///
+    /// # Panics
+    /// If `max_connections` is zero.
+    #[must_use]
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        assert!(max_connections > 0, "At least one connection is required");
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Adapt the [`Server`] handling each connection.
+    ///
+    /// The server passed to `configure` is created using
+    /// [`Server::default_postfix`].
+    #[must_use]
+    pub fn with_server_config<C>(mut self, configure: C) -> Self
+    where
+        C: for<'m> Fn(Server<'m, F::Milter>) -> Server<'m, F::Milter> + Send + Sync + 'static,
+    {
+        self.configure = Arc::new(configure);
+        self
+    }
+
+    /// Accept connections on `listener` forever, or until it hands out no
+    /// more connections like [`Listener::stdio`].
+    ///
+    /// # Errors
+    /// Returns an error if accepting a connection fails.
+    pub async fn run(self, listener: Listener) -> io::Result<()> {
+        self.run_all_until(vec![listener], std::future::pending())
+            .await
+    }
+
+    /// Accept connections on `listener` until `shutdown` completes.
+    ///
+    /// See [`Serve::run_all_until`].
+    ///
+    /// # Errors
+    /// Returns an error if accepting a connection fails. Running sessions
+    /// are drained before returning it.
+    pub async fn run_until(
+        self,
+        listener: Listener,
+        shutdown: impl Future<Output = ()>,
+    ) -> io::Result<()> {
+        self.run_all_until(vec![listener], shutdown).await
+    }
+
+    /// Accept connections on all `listeners` forever, or until none of them
+    /// hands out connections anymore.
+    ///
+    /// # Errors
+    /// Returns an error if accepting a connection fails.
+    pub async fn run_all(self, listeners: Vec<Listener>) -> io::Result<()> {
+        self.run_all_until(listeners, std::future::pending()).await
+    }
+
+    /// Accept connections on all `listeners` until `shutdown` completes.
+    ///
+    /// The connection cap is shared by all listeners. After `shutdown`
+    /// completed, no new connections are accepted. Sessions already in
+    /// flight are drained: this returns after all of them finished.
+    ///
+    /// Errors of single connections do not stop the listeners.
+    ///
+    /// # Errors
+    /// Returns an error if accepting a connection fails. Running sessions
+    /// are drained before returning it.
+    pub async fn run_all_until(
+        self,
+        listeners: Vec<Listener>,
+        shutdown: impl Future<Output = ()>,
+    ) -> io::Result<()> {
+        let permits = Arc::new(Semaphore::new(self.max_connections));
+        let mut sessions = JoinSet::new();
+        let mut shutdown = std::pin::pin!(shutdown);
+        let mut open: Vec<&Listener> = listeners.iter().collect();
+
+        let result = loop {
+            if open.is_empty() {
+                debug!("Listeners closed, no longer accepting connections");
+                break Ok(());
+            }
+
+            let (index, accepted) = tokio::select! {
+                () = &mut shutdown => {
+                    debug!("Shutting down, no longer accepting connections");
+                    break Ok(());
+                }
+                // Reap finished sessions to not accumulate them
+                Some(_finished) = sessions.join_next(), if !sessions.is_empty() => continue,
+                accepted = Self::accept(&open, &permits) => accepted,
+            };
+
+            let (stream, permit) = match accepted {
+                Ok(Some(accepted)) => accepted,
+                Ok(None) => {
+                    open.remove(index);
+                    continue;
+                }
+                Err(err) => break Err(err),
+            };
+
+            debug!("Accepted connection");
+            let milter = self.factory.create();
+            let configure = Arc::clone(&self.configure);
+            sessions.spawn(async move {
+                Self::handle(milter, configure.as_ref(), stream).await;
+                drop(permit);
+            });
+        };
+
+        debug!("Draining {} sessions", sessions.len());
+        while sessions.join_next().await.is_some() {}
+
+        result
+    }
+
+    /// Wait for a free connection slot, then accept the next connection on
+    /// any of `listeners`, along with the index of the listener.
+    async fn accept(
+        listeners: &[&Listener],
+        permits: &Arc<Semaphore>,
+    ) -> (
+        usize,
+        io::Result<Option<(Stream, tokio::sync::OwnedSemaphorePermit)>>,
+    ) {
+        let permit = Arc::clone(permits)
+            .acquire_owned()
+            .await
+            .expect("Semaphore is never closed");
+        let accepts = listeners.iter().map(|listener| Box::pin(listener.accept()));
+        let (stream, index, _pending) = select_all(accepts).await;
+
+        (
+            index,
+            stream.map(|stream| stream.map(|stream| (stream, permit))),
+        )
+    }
+
+    /// Handle a single connection with `milter`
+    async fn handle(mut milter: F::Milter, configure: &Configure<F::Milter>, stream: Stream) {
+        let server = configure(Server::default_postfix(&mut milter));
+        let result = match stream {
+            Stream::Tcp(stream) => Self::handle_stream(server, stream).await,
+            #[cfg(unix)]
+            Stream::Unix(stream) => Self::handle_stream(server, stream).await,
+            Stream::Stdio(stream) => Self::handle_stream(server, stream).await,
+        };
+
+        let Err(err) = result else {
+            return;
+        };
+        match err.into_app_error() {
+            Ok(_impl_error) => {
+                warn!("Connection closed with an error of the milter");
+            }
+            #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+            Err(err) => {
+                warn!(%err, "Connection closed with an error");
+            }
+        }
+    }
+
+    async fn handle_stream<S: AsyncRead + AsyncWrite + Unpin + Send>(
+        mut server: Server<'_, F::Milter>,
+        stream: S,
+    ) -> Result<(), crate::Error<<F::Milter as Milter>::Error>> {
+        server.handle_connection(stream.compat()).await
+    }
+}
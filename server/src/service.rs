@@ -0,0 +1,343 @@
+//! Adapters between milters and [`tower`](https://docs.rs/tower) services.
+//!
+//! Turn a milter session into a [`Service`] using [`MilterService`], wrap it
+//! in tower middleware and turn it back into a [`Milter`] for the [`Server`]
+//! using [`ServiceMilter`]:
+//!
+//! ```
+//! # use async_trait::async_trait;
+//! # use miltr_server::{Context, Milter};
+//! use miltr_server::{
+//!     service::{MilterService, ServiceMilter},
+//!     Server,
+//! };
+//!
+//! # struct MyMilter;
+//! # #[async_trait]
+//! # impl Milter for MyMilter {
+//! #     type Error = &'static str;
+//! #     async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+//! #         Ok(())
+//! #     }
+//! # }
+//! // Wrap `service` in any layers, e.g. `tower::timeout::Timeout`
+//! let service = MilterService::new(MyMilter);
+//!
+//! let mut milter = ServiceMilter::new(service);
+//! let _server = Server::default_postfix(&mut milter);
+//! ```
+//!
+//! [`Server`]: crate::Server
+
+use std::{
+    future::poll_fn,
+    sync::Arc,
+    task::{Context as TaskContext, Poll},
+};
+
+use async_trait::async_trait;
+use futures::{future::BoxFuture, lock::Mutex};
+use miltr_common::{
+    actions::{Abort, Action, Continue, Quit, QuitNc},
+    commands::{
+        Body, Connect, Data, EndOfBody, EndOfHeader, Header, Helo, Macro, Mail, Recipient, Unknown,
+    },
+    decoding::ClientCommand,
+    encoding::ServerMessage,
+    modifications::ModificationResponse,
+    optneg::OptNeg,
+};
+use thiserror::Error;
+use tower_service::Service;
+
+use crate::{Configure, Context, Error, Milter, Server};
+
+/// A milter session as a [`Service`], answering every command with the
+/// messages to send back to the client.
+///
+/// Every command is handled by a [`Server`] just like
+/// commands received on a connection, keeping a [`Context`] and respecting
+/// the negotiated protocol, policies and timeouts. Commands are handled one
+/// at a time, clones of this service share the same session.
+pub struct MilterService<M: Milter> {
+    session: Arc<Mutex<Session<M>>>,
+    configure: Arc<Configure<M>>,
+}
+
+/// The milter and context of a session
+struct Session<M> {
+    milter: M,
+    context: Context,
+}
+
+impl<M: Milter> MilterService<M> {
+    /// Handle a new session with `milter`
+    pub fn new(milter: M) -> Self {
+        Self {
+            session: Arc::new(Mutex::new(Session {
+                milter,
+                context: Context::new(),
+            })),
+            configure: Arc::new(|server| server),
+        }
+    }
+
+    /// Adapt the [`Server`] handling each command.
+    ///
+    /// The server passed to `configure` is created using
+    /// [`Server::default_postfix`].
+    #[must_use]
+    pub fn with_server_config<C>(mut self, configure: C) -> Self
+    where
+        C: for<'m> Fn(Server<'m, M>) -> Server<'m, M> + Send + Sync + 'static,
+    {
+        self.configure = Arc::new(configure);
+        self
+    }
+}
+
+impl<M: Milter> Clone for MilterService<M> {
+    fn clone(&self) -> Self {
+        Self {
+            session: Arc::clone(&self.session),
+            configure: Arc::clone(&self.configure),
+        }
+    }
+}
+
+impl<M: Milter> std::fmt::Debug for MilterService<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MilterService").finish_non_exhaustive()
+    }
+}
+
+impl<M: Milter + 'static> Service<ClientCommand> for MilterService<M> {
+    type Response = Vec<ServerMessage>;
+    type Error = Error<M::Error>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, command: ClientCommand) -> Self::Future {
+        let session = Arc::clone(&self.session);
+        let configure = Arc::clone(&self.configure);
+
+        Box::pin(async move {
+            let mut session = session.lock().await;
+            let Session { milter, context } = &mut *session;
+
+            let mut server = configure(Server::default_postfix(milter));
+            let mut responses = Vec::new();
+            // Closing the connection after quit is up to the caller
+            let _flow = server
+                .handle_command(command, context, &mut responses)
+                .await?;
+            Ok(responses)
+        })
+    }
+}
+
+/// Errors of a [`ServiceMilter`]
+#[derive(Debug, Error)]
+pub enum ServiceError<E> {
+    /// The service failed
+    #[error("Service failed: {0}")]
+    Service(E),
+    /// The service responded with messages not fitting the command
+    #[error("Service did not respond with {expected}: {responses:?}")]
+    UnexpectedResponse {
+        /// What the command should have been answered with
+        expected: &'static str,
+        /// The messages the service responded with
+        responses: Vec<ServerMessage>,
+    },
+}
+
+/// Any [`Service`] handling milter commands as a [`Milter`].
+///
+/// Commands are handed to the service as they are received, the messages it
+/// responds with are sent back to the client. The counterpart of
+/// [`MilterService`].
+#[derive(Debug)]
+pub struct ServiceMilter<S> {
+    service: S,
+}
+
+impl<S> ServiceMilter<S> {
+    /// Handle commands using `service`
+    pub fn new(service: S) -> Self {
+        Self { service }
+    }
+
+    /// The wrapped service
+    pub fn into_inner(self) -> S {
+        self.service
+    }
+}
+
+impl<S> ServiceMilter<S>
+where
+    S: Service<ClientCommand, Response = Vec<ServerMessage>> + Send,
+    S::Future: Send,
+    S::Error: Send,
+{
+    /// Hand `command` to the service once it is ready
+    async fn call<C: Into<ClientCommand>>(
+        &mut self,
+        command: C,
+    ) -> Result<Vec<ServerMessage>, ServiceError<S::Error>> {
+        poll_fn(|cx| self.service.poll_ready(cx))
+            .await
+            .map_err(ServiceError::Service)?;
+
+        let mut responses = self
+            .service
+            .call(command.into())
+            .await
+            .map_err(ServiceError::Service)?;
+        responses.retain(|r| !matches!(r, ServerMessage::Progress(_)));
+        Ok(responses)
+    }
+
+    /// Hand `command` to the service, expecting an action or no answer
+    async fn action<C: Into<ClientCommand>>(
+        &mut self,
+        command: C,
+    ) -> Result<Action, ServiceError<S::Error>> {
+        let mut responses = self.call(command).await?;
+
+        match responses.pop() {
+            None => Ok(Continue.into()),
+            Some(ServerMessage::Action(action)) if responses.is_empty() => Ok(action),
+            Some(response) => {
+                responses.push(response);
+                Err(ServiceError::UnexpectedResponse {
+                    expected: "a single action",
+                    responses,
+                })
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<S> Milter for ServiceMilter<S>
+where
+    S: Service<ClientCommand, Response = Vec<ServerMessage>> + Send,
+    S::Future: Send,
+    S::Error: Send,
+{
+    type Error = ServiceError<S::Error>;
+
+    async fn option_negotiation(&mut self, theirs: OptNeg) -> Result<OptNeg, Error<Self::Error>> {
+        let mut responses = self.call(theirs).await.map_err(Error::from_app_error)?;
+
+        match responses.pop() {
+            Some(ServerMessage::Optneg(ours)) if responses.is_empty() => Ok(ours),
+            response => {
+                responses.extend(response);
+                Err(Error::from_app_error(ServiceError::UnexpectedResponse {
+                    expected: "options",
+                    responses,
+                }))
+            }
+        }
+    }
+
+    async fn macro_(&mut self, _context: &Context, macro_: Macro) -> Result<(), Self::Error> {
+        self.call(macro_).await.map(drop)
+    }
+
+    async fn connect(
+        &mut self,
+        _context: &Context,
+        connect_info: Connect,
+    ) -> Result<Action, Self::Error> {
+        self.action(connect_info).await
+    }
+
+    async fn helo(&mut self, _context: &Context, helo: Helo) -> Result<Action, Self::Error> {
+        self.action(helo).await
+    }
+
+    async fn mail(&mut self, _context: &Context, mail: Mail) -> Result<Action, Self::Error> {
+        self.action(mail).await
+    }
+
+    async fn rcpt(
+        &mut self,
+        _context: &Context,
+        recipient: Recipient,
+    ) -> Result<Action, Self::Error> {
+        self.action(recipient).await
+    }
+
+    async fn data(&mut self, _context: &Context) -> Result<Action, Self::Error> {
+        self.action(Data).await
+    }
+
+    async fn header(&mut self, _context: &Context, header: Header) -> Result<Action, Self::Error> {
+        self.action(header).await
+    }
+
+    async fn end_of_header(&mut self, _context: &Context) -> Result<Action, Self::Error> {
+        self.action(EndOfHeader).await
+    }
+
+    async fn body(&mut self, _context: &Context, body: Body) -> Result<Action, Self::Error> {
+        self.action(body).await
+    }
+
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
+        let mut responses = self.call(EndOfBody).await?;
+
+        let final_action = match responses.pop() {
+            Some(ServerMessage::Action(action)) => action,
+            response => {
+                responses.extend(response);
+                return Err(ServiceError::UnexpectedResponse {
+                    expected: "a final action",
+                    responses,
+                });
+            }
+        };
+        if !responses
+            .iter()
+            .all(|r| matches!(r, ServerMessage::ModificationAction(_)))
+        {
+            return Err(ServiceError::UnexpectedResponse {
+                expected: "modifications",
+                responses,
+            });
+        }
+
+        let mut builder = ModificationResponse::builder();
+        for response in responses {
+            if let ServerMessage::ModificationAction(modification) = response {
+                builder.push(modification);
+            }
+        }
+        Ok(builder.build(final_action))
+    }
+
+    async fn unknown(&mut self, _context: &Context, cmd: Unknown) -> Result<Action, Self::Error> {
+        self.action(cmd).await
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        self.call(Abort).await.map(drop)
+    }
+
+    async fn quit(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        self.call(Quit).await.map(drop)
+    }
+
+    async fn quit_nc(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        self.call(QuitNc).await.map(drop)
+    }
+}
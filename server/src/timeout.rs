@@ -0,0 +1,55 @@
+//! Limit how long to wait for the client and the milter
+
+use std::{pin::pin, time::Duration};
+
+use futures::{
+    future::{select, Either},
+    Future,
+};
+use futures_timer::Delay;
+use miltr_common::actions::{Action, Tempfail};
+
+/// The timeouts a server applies to a connection
+#[derive(Debug, Clone)]
+pub(crate) struct Timeouts {
+    /// How long to wait for the next command of the client
+    pub(crate) read: Option<Duration>,
+    /// How long a single milter callback may take
+    pub(crate) callback: Option<Duration>,
+    /// The answer to send if a callback timed out
+    pub(crate) callback_action: Action,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            read: None,
+            callback: None,
+            callback_action: Tempfail.into(),
+        }
+    }
+}
+
+impl Timeouts {
+    /// Await the next command of the client, limited by the read timeout
+    pub(crate) async fn read<F: Future>(&self, future: F) -> Result<F::Output, Duration> {
+        timeout(self.read, future).await
+    }
+
+    /// Await a milter callback, limited by the callback timeout
+    pub(crate) async fn callback<F: Future>(&self, future: F) -> Result<F::Output, Duration> {
+        timeout(self.callback, future).await
+    }
+}
+
+/// Await `future`, returning `limit` as error if it takes longer than that
+async fn timeout<F: Future>(limit: Option<Duration>, future: F) -> Result<F::Output, Duration> {
+    let Some(limit) = limit else {
+        return Ok(future.await);
+    };
+
+    match select(pin!(future), Delay::new(limit)).await {
+        Either::Left((output, _delay)) => Ok(output),
+        Either::Right(((), _future)) => Err(limit),
+    }
+}
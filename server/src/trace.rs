@@ -0,0 +1,201 @@
+//! Structured tracing of milter sessions.
+//!
+//! Every connection is handled in a `milter_connection` span. Each message
+//! gets a `milter_message` child span carrying the session id and the queue
+//! id from the `{i}` macro, once the client sent it. Received commands and
+//! sent actions are recorded as events with typed fields.
+
+use miltr_common::{
+    actions::Action, commands::Macro, decoding::ClientCommand, modifications::ModificationResponse,
+    optneg::MacroStage,
+};
+use tracing::{debug, field, info, info_span, Span};
+
+use crate::context::strip_braces;
+
+/// The spans of a connection and the message currently handled
+#[derive(Debug, Clone)]
+pub(crate) struct Trace {
+    connection: Span,
+    message: Option<MessageTrace>,
+    /// A queue id received before the message it belongs to started
+    queue_id: Option<String>,
+}
+
+/// What was received about the message currently handled
+#[derive(Debug, Clone)]
+struct MessageTrace {
+    span: Span,
+    sender: Option<String>,
+    recipients: usize,
+    headers: usize,
+    body_bytes: usize,
+}
+
+impl Trace {
+    /// Trace a connection handled in the current span
+    pub(crate) fn new() -> Self {
+        Self {
+            connection: Span::current(),
+            message: None,
+            queue_id: None,
+        }
+    }
+
+    /// Trace the next session on the same connection
+    pub(crate) fn restart(&self) -> Self {
+        Self {
+            connection: self.connection.clone(),
+            message: None,
+            queue_id: None,
+        }
+    }
+
+    /// The span to handle the current command in
+    pub(crate) fn span(&self) -> &Span {
+        self.message
+            .as_ref()
+            .map_or(&self.connection, |message| &message.span)
+    }
+
+    /// Record a `command` received in session `session_id`
+    pub(crate) fn command(&mut self, command: &ClientCommand, session_id: u64) {
+        match command {
+            ClientCommand::Macro(macro_) => self.macro_(macro_),
+            ClientCommand::Connect(connect) => debug!(
+                parent: self.span(),
+                hostname = %connect.hostname(),
+                address = %connect.address(),
+                "Received connect"
+            ),
+            ClientCommand::Helo(helo) => {
+                debug!(parent: self.span(), helo = %helo.helo(), "Received helo");
+            }
+            ClientCommand::Mail(mail) => {
+                self.message = None;
+                let message = self.message(session_id);
+                message.sender = Some(mail.sender().into_owned());
+                debug!(parent: &message.span, sender = %mail.sender(), "Received mail");
+            }
+            ClientCommand::Recipient(recipient) => {
+                let message = self.message(session_id);
+                message.recipients += 1;
+                debug!(
+                    parent: &message.span,
+                    recipient = %recipient.recipient(),
+                    recipients = message.recipients,
+                    "Received recipient"
+                );
+            }
+            ClientCommand::Header(header) => {
+                let message = self.message(session_id);
+                message.headers += 1;
+                debug!(
+                    parent: &message.span,
+                    name = %header.name(),
+                    headers = message.headers,
+                    "Received header"
+                );
+            }
+            ClientCommand::Body(body) => {
+                let message = self.message(session_id);
+                message.body_bytes += body.as_bytes().len();
+                debug!(
+                    parent: &message.span,
+                    len = body.as_bytes().len(),
+                    body_bytes = message.body_bytes,
+                    "Received body chunk"
+                );
+            }
+            ClientCommand::Data(_)
+            | ClientCommand::EndOfHeader(_)
+            | ClientCommand::EndOfBody(_) => {
+                let message = self.message(session_id);
+                debug!(parent: &message.span, %command, "Received command");
+            }
+            ClientCommand::OptNeg(_)
+            | ClientCommand::Unknown(_)
+            | ClientCommand::Abort(_)
+            | ClientCommand::Quit(_)
+            | ClientCommand::QuitNc(_) => {
+                debug!(parent: self.span(), %command, "Received command");
+            }
+        }
+    }
+
+    /// Record an `action` sent to the client
+    pub(crate) fn action(&self, action: &Action) {
+        debug!(parent: self.span(), verdict = %action, "Sending action");
+    }
+
+    /// Summarize the message once the milter decided on it
+    pub(crate) fn end_of_body(&self, response: &ModificationResponse) {
+        let Some(message) = &self.message else {
+            return;
+        };
+
+        info!(
+            parent: &message.span,
+            sender = message.sender.as_deref(),
+            recipients = message.recipients,
+            headers = message.headers,
+            body_bytes = message.body_bytes,
+            modifications = response.modifications().len(),
+            verdict = %response.final_action(),
+            "Message handled"
+        );
+    }
+
+    /// The current message is done, close it's span
+    pub(crate) fn finish_message(&mut self) {
+        self.message = None;
+    }
+
+    /// Record the queue id, if `macro_` carries it
+    fn macro_(&mut self, macro_: &Macro) {
+        debug!(parent: self.span(), stage = ?macro_.stage(), "Received macros");
+
+        let Some(queue_id) = macro_
+            .macros()
+            .find(|(name, _value)| strip_braces(name) == b"i")
+            .map(|(_name, value)| String::from_utf8_lossy(value).into_owned())
+        else {
+            return;
+        };
+
+        // Macros for the mail stage are sent before the mail command, which
+        // starts a new message
+        match &self.message {
+            Some(message) if macro_.stage() != Some(MacroStage::MailFrom) => {
+                message.span.record("queue_id", queue_id.as_str());
+            }
+            _ => self.queue_id = Some(queue_id),
+        }
+    }
+
+    /// The message currently handled, starting a new one if needed
+    fn message(&mut self, session_id: u64) -> &mut MessageTrace {
+        let connection = &self.connection;
+        let queue_id = &mut self.queue_id;
+
+        self.message.get_or_insert_with(|| {
+            let span = info_span!(
+                parent: connection,
+                "milter_message",
+                session_id,
+                queue_id = field::Empty
+            );
+            if let Some(queue_id) = queue_id.take() {
+                span.record("queue_id", queue_id.as_str());
+            }
+
+            MessageTrace {
+                span,
+                sender: None,
+                recipients: 0,
+                headers: 0,
+                body_bytes: 0,
+            }
+        })
+    }
+}
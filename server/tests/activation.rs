@@ -0,0 +1,182 @@
+//! Serving sockets inherited from a parent process.
+//!
+//! The milter runs in a child process, re-executing this test binary with
+//! [`CHILD`] set. It runs without the libtest harness, which would write to
+//! the stdout the stdio milter speaks on.
+
+use std::{
+    env,
+    os::unix::net::UnixListener,
+    process::{Command, Stdio},
+};
+
+use async_trait::async_trait;
+use command_fds::{CommandFdExt, FdMapping};
+use miette::{miette, ErrReport, IntoDiagnostic, Result, WrapErr};
+use tokio::{io::join, net::TcpListener, process::Command as AsyncCommand, runtime::Runtime};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+use miltr_client::Client;
+use miltr_common::{
+    actions::Action, modifications::ModificationResponse, optneg::OptNeg, socket::SocketSpec,
+};
+use miltr_server::{
+    serve::{Listener, Serve, SystemdSockets},
+    Context, Milter,
+};
+
+/// Which milter the child process should run
+const CHILD: &str = "MILTR_ACTIVATION_CHILD";
+
+#[derive(Debug, Default)]
+struct ActivationTestMilter;
+
+#[async_trait]
+impl Milter for ActivationTestMilter {
+    type Error = ErrReport;
+
+    async fn end_of_body(&mut self, _context: &Context) -> Result<ModificationResponse> {
+        Ok(ModificationResponse::empty_continue())
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Take the sockets passed by socket activation, before the runtime starts
+fn child_systemd() -> Result<()> {
+    let sockets = SystemdSockets::take().into_diagnostic()?;
+    if sockets.len() != 2 {
+        return Err(miette!("Expected 2 sockets, got {}", sockets.len()));
+    }
+    if env::var_os("LISTEN_FDS").is_some() {
+        return Err(miette!("LISTEN_FDS was not removed"));
+    }
+
+    Runtime::new()
+        .into_diagnostic()?
+        .block_on(serve_systemd(sockets))
+}
+
+/// Serve all sockets passed by socket activation
+async fn serve_systemd(sockets: SystemdSockets) -> Result<()> {
+    let listeners = Listener::from_systemd(sockets).into_diagnostic()?;
+    Serve::new(ActivationTestMilter::default)
+        .run_all(listeners)
+        .await
+        .into_diagnostic()
+}
+
+/// Serve the single connection on stdin and stdout
+async fn child_stdio() -> Result<()> {
+    Serve::new(ActivationTestMilter::default)
+        .run(Listener::stdio())
+        .await
+        .into_diagnostic()
+}
+
+/// Run a session on `spec` to check a milter is serving it
+async fn session(spec: &str) -> Result<()> {
+    let spec: SocketSpec = spec.parse().into_diagnostic()?;
+    let client = Client::new(OptNeg::default());
+    let mut connection = client.connect(&spec).await.into_diagnostic()?;
+
+    let response = connection.end_of_body().await.into_diagnostic()?;
+    assert!(matches!(response.final_action(), Action::Continue(_)));
+    connection.quit().await.into_diagnostic()?;
+
+    Ok(())
+}
+
+/// Pass a tcp and a unix socket to the child like systemd does
+async fn test_systemd() -> Result<()> {
+    let tcp = TcpListener::bind("127.0.0.1:0").await.into_diagnostic()?;
+    let port = tcp.local_addr().into_diagnostic()?.port();
+    let path = env::temp_dir().join(format!("miltr-{}-activation.sock", std::process::id()));
+    let unix = UnixListener::bind(&path).into_diagnostic()?;
+
+    // `LISTEN_PID` is only known after forking, let the shell set it
+    let mut child = Command::new("sh")
+        .args(["-c", "export LISTEN_PID=$$; exec \"$0\""])
+        .arg(env::current_exe().into_diagnostic()?)
+        .env(CHILD, "systemd")
+        .env("LISTEN_FDS", "2")
+        .fd_mappings(vec![
+            FdMapping {
+                parent_fd: tcp.into_std().into_diagnostic()?.into(),
+                child_fd: 3,
+            },
+            FdMapping {
+                parent_fd: unix.into(),
+                child_fd: 4,
+            },
+        ])
+        .into_diagnostic()?
+        .spawn()
+        .into_diagnostic()?;
+
+    let result = async {
+        session(&format!("inet:{port}@127.0.0.1"))
+            .await
+            .wrap_err("Tcp socket not served")?;
+        session(&format!("unix:{}", path.display()))
+            .await
+            .wrap_err("Unix socket not served")
+    }
+    .await;
+
+    child.kill().into_diagnostic()?;
+    child.wait().into_diagnostic()?;
+    // Owned by the parent, the child must not remove it
+    let exists = path.exists();
+    std::fs::remove_file(&path).into_diagnostic()?;
+
+    result?;
+    assert!(exists, "Socket file of an activated socket was removed");
+    Ok(())
+}
+
+/// Connect to the child via it's stdin and stdout like inetd does
+async fn test_stdio() -> Result<()> {
+    let mut child = AsyncCommand::new(env::current_exe().into_diagnostic()?)
+        .env(CHILD, "stdio")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .into_diagnostic()?;
+    let stdout = child.stdout.take().expect("Stdout not piped");
+    let stdin = child.stdin.take().expect("Stdin not piped");
+
+    let client = Client::new(OptNeg::default());
+    let mut connection = client
+        .connect_via(join(stdout, stdin).compat())
+        .await
+        .into_diagnostic()?;
+    let response = connection.end_of_body().await.into_diagnostic()?;
+    assert!(matches!(response.final_action(), Action::Continue(_)));
+    connection.quit().await.into_diagnostic()?;
+
+    // The child exits once the single connection was handled
+    let status = child.wait().await.into_diagnostic()?;
+    assert!(status.success(), "Stdio milter failed: {status}");
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let runtime = || Runtime::new().into_diagnostic();
+    match env::var(CHILD).as_deref() {
+        Ok("systemd") => return child_systemd(),
+        Ok("stdio") => return runtime()?.block_on(child_stdio()),
+        _ => {}
+    }
+
+    runtime()?.block_on(async {
+        test_systemd().await.wrap_err("test_systemd failed")?;
+        println!("test test_systemd ... ok");
+        test_stdio().await.wrap_err("test_stdio failed")?;
+        println!("test test_stdio ... ok");
+        Ok(())
+    })
+}
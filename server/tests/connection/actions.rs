@@ -0,0 +1,178 @@
+use async_trait::async_trait;
+use miette::{ErrReport, Result};
+
+use miltr_common::{
+    actions::{Action, Reject, Skip},
+    commands::{Body, Header},
+    optneg::{OptNeg, Protocol},
+    ProtocolError,
+};
+use miltr_server::{ActionPolicy, Context, Error, Milter};
+
+use crate::utils::{negotiate_raw, read_packet, spawn_in_memory, write_packet};
+
+/// Skips every body chunk
+#[derive(Debug, Clone)]
+struct SkippingTestMilter {
+    protocol: Protocol,
+}
+
+#[async_trait]
+impl Milter for SkippingTestMilter {
+    type Error = ErrReport;
+
+    async fn option_negotiation(&mut self, theirs: OptNeg) -> Result<OptNeg, Error<Self::Error>> {
+        let ours = OptNeg {
+            protocol: self.protocol,
+            ..Default::default()
+        };
+        Ok(ours
+            .merge_compatible(&theirs)
+            .map_err(ProtocolError::CompatibilityError)?)
+    }
+
+    async fn body(&mut self, _context: &Context, _body: Body) -> Result<Action, Self::Error> {
+        Ok(Skip.into())
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Rejects every header, without the client expecting a reply to headers
+#[derive(Debug, Clone)]
+struct NoReplyTestMilter;
+
+#[async_trait]
+impl Milter for NoReplyTestMilter {
+    type Error = ErrReport;
+
+    async fn option_negotiation(&mut self, theirs: OptNeg) -> Result<OptNeg, Error<Self::Error>> {
+        let ours = OptNeg {
+            protocol: Protocol::NR_HEADER,
+            ..Default::default()
+        };
+        Ok(ours
+            .merge_compatible(&theirs)
+            .map_err(ProtocolError::CompatibilityError)?)
+    }
+
+    async fn header(&mut self, _context: &Context, _header: Header) -> Result<Action, Self::Error> {
+        Ok(Reject.into())
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_skip_negotiated() -> Result<()> {
+    let milter = SkippingTestMilter {
+        protocol: Protocol::SMFIP_SKIP,
+    };
+    let (mut stream, _server) = spawn_in_memory(milter, |server| server);
+
+    negotiate_raw(&mut stream, Protocol::all().bits()).await?;
+
+    write_packet(&mut stream, b'B', b"Hello").await?;
+    let (code, _payload) = read_packet(&mut stream).await?;
+
+    assert_eq!(code, b's');
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_skip_downgraded() -> Result<()> {
+    let milter = SkippingTestMilter {
+        protocol: Protocol::empty(),
+    };
+    let (mut stream, _server) = spawn_in_memory(milter, |server| {
+        server.with_action_policy(ActionPolicy::Downgrade)
+    });
+
+    negotiate_raw(&mut stream, Protocol::all().bits()).await?;
+
+    write_packet(&mut stream, b'B', b"Hello").await?;
+    let (code, _payload) = read_packet(&mut stream).await?;
+
+    assert_eq!(code, b'c');
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_skip_pass_through() -> Result<()> {
+    let milter = SkippingTestMilter {
+        protocol: Protocol::empty(),
+    };
+    let (mut stream, _server) = spawn_in_memory(milter, |server| {
+        server.with_action_policy(ActionPolicy::PassThrough)
+    });
+
+    negotiate_raw(&mut stream, Protocol::all().bits()).await?;
+
+    write_packet(&mut stream, b'B', b"Hello").await?;
+    let (code, _payload) = read_packet(&mut stream).await?;
+
+    assert_eq!(code, b's');
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_skip_strict() -> Result<()> {
+    let milter = SkippingTestMilter {
+        protocol: Protocol::empty(),
+    };
+    let (mut stream, server) = spawn_in_memory(milter, |server| {
+        server.with_action_policy(ActionPolicy::Strict)
+    });
+
+    negotiate_raw(&mut stream, Protocol::all().bits()).await?;
+
+    write_packet(&mut stream, b'B', b"Hello").await?;
+
+    let result = server.await.expect("Server task panicked");
+    let err = result.expect_err("Server accepted a skip not negotiated");
+    assert!(err.to_string().contains("invalid action"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_no_reply_downgraded() -> Result<()> {
+    let (mut stream, _server) = spawn_in_memory(NoReplyTestMilter, |server| {
+        server.with_action_policy(ActionPolicy::Downgrade)
+    });
+
+    negotiate_raw(&mut stream, Protocol::all().bits()).await?;
+
+    // No reply to the header, the next one belongs to the end of headers
+    write_packet(&mut stream, b'L', b"Subject\0Hi\0").await?;
+    write_packet(&mut stream, b'N', b"").await?;
+    let (code, _payload) = read_packet(&mut stream).await?;
+
+    assert_eq!(code, b'c');
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_no_reply_strict() -> Result<()> {
+    let (mut stream, server) = spawn_in_memory(NoReplyTestMilter, |server| {
+        server.with_action_policy(ActionPolicy::Strict)
+    });
+
+    negotiate_raw(&mut stream, Protocol::all().bits()).await?;
+
+    write_packet(&mut stream, b'L', b"Subject\0Hi\0").await?;
+
+    let result = server.await.expect("Server task panicked");
+    let err = result.expect_err("Server accepted a reject without reply");
+    assert!(err.to_string().contains("invalid action"));
+
+    Ok(())
+}
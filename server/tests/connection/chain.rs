@@ -0,0 +1,297 @@
+use async_trait::async_trait;
+use miette::{IntoDiagnostic, Result};
+
+use miltr_client::{Client, Verdict};
+use miltr_common::{
+    actions::{Action, Continue, Reject, Skip},
+    commands::{Body, Helo, Macro, Recipient},
+    modifications::{headers::AddHeader, ModificationAction, ModificationResponse},
+    optneg::{Capability, OptNeg, Protocol},
+    ProtocolError,
+};
+use miltr_server::{Chain, Context, Error, Milter};
+use tokio::sync::mpsc;
+
+use crate::utils::spawn_in_memory;
+
+/// Adds a header named after itself and reports what it is asked
+struct ChainTestMilter {
+    name: &'static str,
+    protocol: Protocol,
+    reject: Option<&'static str>,
+    skip_body: bool,
+    reports: mpsc::UnboundedSender<String>,
+}
+
+impl ChainTestMilter {
+    fn report(&self, callback: &str) {
+        self.reports
+            .send(format!("{} {callback}", self.name))
+            .expect("Failed reporting");
+    }
+}
+
+#[async_trait]
+impl Milter for ChainTestMilter {
+    type Error = &'static str;
+
+    async fn option_negotiation(&mut self, theirs: OptNeg) -> Result<OptNeg, Error<Self::Error>> {
+        let ours = OptNeg {
+            capabilities: Capability::SMFIF_ADDHDRS,
+            protocol: self.protocol,
+            ..Default::default()
+        };
+        Ok(ours
+            .merge_compatible(&theirs)
+            .map_err(ProtocolError::CompatibilityError)?)
+    }
+
+    async fn helo(&mut self, _context: &Context, _helo: Helo) -> Result<Action, Self::Error> {
+        self.report("helo");
+        Ok(Continue.into())
+    }
+
+    async fn rcpt(
+        &mut self,
+        _context: &Context,
+        recipient: Recipient,
+    ) -> Result<Action, Self::Error> {
+        self.report("rcpt");
+        if Some(recipient.recipient().as_ref()) == self.reject {
+            return Ok(Reject.into());
+        }
+        Ok(Continue.into())
+    }
+
+    async fn body(&mut self, _context: &Context, _body: Body) -> Result<Action, Self::Error> {
+        self.report("body");
+        if self.skip_body {
+            return Ok(Skip.into());
+        }
+        Ok(Continue.into())
+    }
+
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
+        self.report("end_of_body");
+        let mut builder = ModificationResponse::builder();
+        builder.push(AddHeader::new(b"X-Checked-By", self.name.as_bytes()));
+        Ok(builder.contin())
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        self.report("abort");
+        Ok(())
+    }
+}
+
+fn chain(reports: &mpsc::UnboundedSender<String>) -> Chain<&'static str> {
+    Chain::new()
+        .with(ChainTestMilter {
+            name: "first",
+            protocol: Protocol::NO_HELO | Protocol::NO_CONNECT,
+            reject: Some("<first@example.com>"),
+            skip_body: false,
+            reports: reports.clone(),
+        })
+        .with(ChainTestMilter {
+            name: "second",
+            protocol: Protocol::NO_CONNECT,
+            reject: Some("<second@example.com>"),
+            skip_body: false,
+            reports: reports.clone(),
+        })
+}
+
+/// Two milters receiving the body, skipping it as told
+fn skipping_chain(
+    reports: &mpsc::UnboundedSender<String>,
+    skip_body: [bool; 2],
+) -> Chain<&'static str> {
+    let [first, second] = skip_body;
+    Chain::new()
+        .with(ChainTestMilter {
+            name: "first",
+            protocol: Protocol::SMFIP_SKIP,
+            reject: None,
+            skip_body: first,
+            reports: reports.clone(),
+        })
+        .with(ChainTestMilter {
+            name: "second",
+            protocol: Protocol::SMFIP_SKIP,
+            reject: None,
+            skip_body: second,
+            reports: reports.clone(),
+        })
+}
+
+/// Two milters of which only the first wants rejected recipients
+fn rcpt_rej_chain(reports: &mpsc::UnboundedSender<String>) -> Chain<&'static str> {
+    Chain::new()
+        .with(ChainTestMilter {
+            name: "first",
+            protocol: Protocol::SMFIP_RCPT_REJ,
+            reject: None,
+            skip_body: false,
+            reports: reports.clone(),
+        })
+        .with(ChainTestMilter {
+            name: "second",
+            protocol: Protocol::empty(),
+            reject: None,
+            skip_body: false,
+            reports: reports.clone(),
+        })
+}
+
+fn drain(received: &mut mpsc::UnboundedReceiver<String>) -> Vec<String> {
+    let mut reports = Vec::new();
+    while let Ok(report) = received.try_recv() {
+        reports.push(report);
+    }
+    reports
+}
+
+#[tokio::test]
+async fn test_chain() -> Result<()> {
+    let (reports, mut received) = mpsc::unbounded_channel();
+    let (stream, server) = spawn_in_memory(chain(&reports), |server| server);
+
+    let client = Client::new(OptNeg {
+        protocol: Protocol::all(),
+        ..Default::default()
+    });
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+    assert_eq!(connection.options().protocol, Protocol::NO_CONNECT);
+
+    // The first milter disabled helo
+    connection.helo(&b"localhost"[..]).await.into_diagnostic()?;
+    assert_eq!(drain(&mut received), ["second helo"]);
+
+    // The first rejecting milter decides
+    let verdict = connection
+        .recipient(&b"<first@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    assert!(matches!(verdict, Verdict::Reject));
+    assert_eq!(drain(&mut received), ["first rcpt"]);
+
+    let verdict = connection
+        .recipient(&b"<second@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    assert!(matches!(verdict, Verdict::Reject));
+    assert_eq!(drain(&mut received), ["first rcpt", "second rcpt"]);
+
+    // Modifications of both are merged
+    let response = connection.end_of_body().await.into_diagnostic()?;
+    let headers: Vec<String> = response
+        .modifications()
+        .iter()
+        .map(|m| match m {
+            ModificationAction::AddHeader(add) => add.value().to_string(),
+            m => panic!("Unexpected modification {m:?}"),
+        })
+        .collect();
+    assert_eq!(headers, ["first", "second"]);
+    assert!(matches!(response.final_action(), Action::Continue(_)));
+
+    connection.reset().await.into_diagnostic()?;
+    connection.quit().await.into_diagnostic()?;
+    server.await.into_diagnostic()??;
+    let reports = drain(&mut received);
+    assert!(reports.contains(&"first abort".to_string()));
+    assert!(reports.contains(&"second abort".to_string()));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_chain_skip_single() -> Result<()> {
+    let (reports, mut received) = mpsc::unbounded_channel();
+    let (stream, _server) =
+        spawn_in_memory(skipping_chain(&reports, [true, false]), |server| server);
+
+    let client = Client::new(OptNeg {
+        protocol: Protocol::SMFIP_SKIP,
+        ..Default::default()
+    });
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    // The second milter still wants the body
+    let verdict = connection.body(&b"Hello"[..]).await.into_diagnostic()?;
+    assert!(matches!(verdict, Verdict::Continue));
+    assert_eq!(drain(&mut received), ["first body", "second body"]);
+
+    let verdict = connection.body(&b"World"[..]).await.into_diagnostic()?;
+    assert!(matches!(verdict, Verdict::Continue));
+    assert_eq!(drain(&mut received), ["second body"]);
+
+    // The next message is sent to both again
+    connection.end_of_body().await.into_diagnostic()?;
+    drain(&mut received);
+    connection.body(&b"Hello"[..]).await.into_diagnostic()?;
+    assert_eq!(drain(&mut received), ["first body", "second body"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_chain_skip_all() -> Result<()> {
+    let (reports, mut received) = mpsc::unbounded_channel();
+    let (stream, _server) =
+        spawn_in_memory(skipping_chain(&reports, [true, true]), |server| server);
+
+    let client = Client::new(OptNeg {
+        protocol: Protocol::SMFIP_SKIP,
+        ..Default::default()
+    });
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    let verdict = connection.body(&b"Hello"[..]).await.into_diagnostic()?;
+    assert!(matches!(verdict, Verdict::Skip));
+    assert_eq!(drain(&mut received), ["first body", "second body"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_chain_rcpt_rej_single() -> Result<()> {
+    let (reports, mut received) = mpsc::unbounded_channel();
+    let (stream, _server) = spawn_in_memory(rcpt_rej_chain(&reports), |server| server);
+
+    let client = Client::new(OptNeg {
+        protocol: Protocol::SMFIP_RCPT_REJ,
+        ..Default::default()
+    });
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+    assert_eq!(connection.options().protocol, Protocol::SMFIP_RCPT_REJ);
+
+    // Only the first milter asked for recipients the MTA rejected
+    connection
+        .macro_(Macro::new(b'R', &[(b"{rcpt_mailer}", b"error")]))
+        .await
+        .into_diagnostic()?;
+    let verdict = connection
+        .recipient(&b"<unknown@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    assert!(matches!(verdict, Verdict::Continue));
+    assert_eq!(drain(&mut received), ["first rcpt"]);
+
+    connection
+        .macro_(Macro::new(b'R', &[(b"{rcpt_mailer}", b"local")]))
+        .await
+        .into_diagnostic()?;
+    let verdict = connection
+        .recipient(&b"<known@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    assert!(matches!(verdict, Verdict::Continue));
+    assert_eq!(drain(&mut received), ["first rcpt", "second rcpt"]);
+
+    Ok(())
+}
@@ -0,0 +1,191 @@
+use async_trait::async_trait;
+use miette::{IntoDiagnostic, Result};
+
+use miltr_client::{Client, Envelope, Verdict};
+use miltr_common::{
+    commands::Header,
+    modifications::{headers::AddHeader, ModificationAction, ModificationResponse},
+    optneg::OptNeg,
+};
+use miltr_server::{
+    collect::{Collect, Message, MessageMilter},
+    Context,
+};
+use tokio::sync::mpsc;
+
+use crate::utils::spawn_in_memory;
+
+/// Reports every message it receives
+struct CollectTestMilter {
+    reports: mpsc::UnboundedSender<(Option<String>, Message)>,
+}
+
+#[async_trait]
+impl MessageMilter for CollectTestMilter {
+    type Error = &'static str;
+
+    async fn message(
+        &mut self,
+        context: &Context,
+        message: Message,
+    ) -> Result<ModificationResponse, Self::Error> {
+        let size = message.body().len().to_string();
+        let helo = context.helo().map(|h| h.to_string());
+        self.reports
+            .send((helo, message))
+            .map_err(|_e| "Failed reporting")?;
+
+        let mut builder = ModificationResponse::builder();
+        builder.push(AddHeader::new(b"X-Body-Size", size.as_bytes()));
+        Ok(builder.contin())
+    }
+}
+
+#[tokio::test]
+async fn test_collect_message() -> Result<()> {
+    let (reports, mut received) = mpsc::unbounded_channel();
+    let milter = Collect::new(CollectTestMilter { reports }).with_memory_limit(8);
+    let (stream, _server) = spawn_in_memory(milter, |server| server);
+
+    let client = Client::new(OptNeg::default());
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    let envelope = Envelope::new(b"<alice@example.com>")
+        .with_helo(b"localhost")
+        .with_recipient(b"<bob@example.com>")
+        .with_recipient(b"<carol@example.com>");
+    let message =
+        b"Received: first\r\nSubject: Hi\r\nReceived: second\r\n\r\nHello Bob and Carol\r\n";
+    let outcome = connection
+        .process_message(&envelope, &message[..])
+        .await
+        .into_diagnostic()?;
+
+    let [ModificationAction::AddHeader(size)] = outcome.response().modifications() else {
+        panic!("Expected the body size header");
+    };
+    assert_eq!(size.value(), "21");
+
+    let (helo, message) = received.recv().await.expect("Milter did not report");
+    assert_eq!(helo.as_deref(), Some("localhost"));
+    assert_eq!(
+        message.mail().map(|m| m.sender().to_string()).as_deref(),
+        Some("<alice@example.com>")
+    );
+    let recipients: Vec<_> = message
+        .recipients()
+        .iter()
+        .map(|r| r.recipient().to_string())
+        .collect();
+    assert_eq!(recipients, ["<bob@example.com>", "<carol@example.com>"]);
+
+    let headers: Vec<_> = message
+        .headers()
+        .iter()
+        .map(|h| (h.name().to_string(), h.value().to_string(), h.index()))
+        .collect();
+    assert_eq!(
+        headers,
+        [
+            ("Received".to_string(), "first".to_string(), 1),
+            ("Subject".to_string(), "Hi".to_string(), 1),
+            ("Received".to_string(), "second".to_string(), 2),
+        ]
+    );
+
+    assert!(message.body().is_spilled());
+    assert_eq!(
+        message.body().to_vec().into_diagnostic()?,
+        b"Hello Bob and Carol\r\n"
+    );
+
+    connection.quit().await.into_diagnostic()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_collect_after_abort() -> Result<()> {
+    let (reports, mut received) = mpsc::unbounded_channel();
+    let milter = Collect::new(CollectTestMilter { reports });
+    let (stream, _server) = spawn_in_memory(milter, |server| server);
+
+    let client = Client::new(OptNeg::default());
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    connection
+        .mail(&b"<alice@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    connection
+        .header(Header::new(b"Subject", b"Aborted"))
+        .await
+        .into_diagnostic()?;
+    connection.reset().await.into_diagnostic()?;
+
+    let envelope = Envelope::new(b"<bob@example.com>").with_recipient(b"<carol@example.com>");
+    connection
+        .process_message(&envelope, &b"Subject: Hi\r\n\r\nHello\r\n"[..])
+        .await
+        .into_diagnostic()?;
+
+    let (_helo, message) = received.recv().await.expect("Milter did not report");
+    assert_eq!(message.headers().len(), 1);
+    assert_eq!(message.header("subject").as_deref(), Some("Hi"));
+    assert!(!message.body().is_spilled());
+    assert_eq!(message.body().to_vec().into_diagnostic()?, b"Hello\r\n");
+
+    connection.quit().await.into_diagnostic()?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_collect_spill_concurrent() -> Result<()> {
+    let (reports, mut received) = mpsc::unbounded_channel();
+    let spilling = Collect::new(CollectTestMilter {
+        reports: reports.clone(),
+    })
+    .with_memory_limit(0);
+    let (spilling_stream, _spilling_server) = spawn_in_memory(spilling, |server| server);
+    let (other_stream, _other_server) =
+        spawn_in_memory(Collect::new(CollectTestMilter { reports }), |server| server);
+
+    let client = Client::new(OptNeg::default());
+    let mut spilling = client
+        .connect_via(spilling_stream)
+        .await
+        .into_diagnostic()?;
+    let mut other = client.connect_via(other_stream).await.into_diagnostic()?;
+
+    spilling
+        .mail(&b"<alice@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    let chunk = vec![b'a'; 60 * 1024];
+    let envelope = Envelope::new(b"<bob@example.com>").with_recipient(b"<carol@example.com>");
+    for _ in 0..16 {
+        let verdict = spilling.body(&chunk[..]).await.into_diagnostic()?;
+        assert!(matches!(verdict, Verdict::Continue));
+
+        // The other session is handled while the body is spilled
+        other
+            .process_message(&envelope, &b"Subject: Hi\r\n\r\nHello\r\n"[..])
+            .await
+            .into_diagnostic()?;
+        let (_helo, message) = received.recv().await.expect("Milter did not report");
+        assert_eq!(message.body().len(), 7);
+    }
+    spilling.end_of_body().await.into_diagnostic()?;
+
+    let (_helo, message) = received.recv().await.expect("Milter did not report");
+    assert!(message.body().is_spilled());
+    assert_eq!(message.body().len(), 16 * 60 * 1024);
+    let body = tokio::task::spawn_blocking(move || message.body().to_vec())
+        .await
+        .into_diagnostic()?
+        .into_diagnostic()?;
+    assert!(body.iter().all(|&b| b == b'a'));
+
+    spilling.quit().await.into_diagnostic()?;
+    other.quit().await.into_diagnostic()?;
+    Ok(())
+}
@@ -0,0 +1,216 @@
+use async_trait::async_trait;
+use miette::{ErrReport, IntoDiagnostic, Result};
+
+use miltr_client::Client;
+use miltr_common::{
+    commands::{Helo, Macro, Mail},
+    modifications::ModificationResponse,
+    optneg::{MacroStage, MacroStages, OptNeg},
+    ProtocolError,
+};
+use miltr_server::{Context, Error, Milter};
+use tokio::sync::mpsc;
+
+use crate::utils::{negotiate_raw, read_packet, spawn_in_memory, write_packet};
+
+/// What the milter saw in the context at the end of a message
+#[derive(Debug, PartialEq)]
+struct Seen {
+    session_id: u64,
+    helo: Option<String>,
+    mta: Option<String>,
+    authen: Option<String>,
+    recipient: Option<String>,
+    queue_id: Option<String>,
+}
+
+/// Reports the context at the end of every message
+#[derive(Debug, Clone)]
+struct ContextTestMilter {
+    macro_stages: MacroStages,
+    sender: mpsc::UnboundedSender<Seen>,
+}
+
+#[async_trait]
+impl Milter for ContextTestMilter {
+    type Error = ErrReport;
+
+    async fn option_negotiation(&mut self, theirs: OptNeg) -> Result<OptNeg, Error<Self::Error>> {
+        let ours = OptNeg {
+            macro_stages: self.macro_stages.clone(),
+            ..Default::default()
+        };
+        Ok(ours
+            .merge_compatible(&theirs)
+            .map_err(ProtocolError::CompatibilityError)?)
+    }
+
+    async fn end_of_body(&mut self, context: &Context) -> Result<ModificationResponse> {
+        let seen = Seen {
+            session_id: context.session_id(),
+            helo: context.helo().map(String::from),
+            mta: context.macro_("j").map(String::from),
+            authen: context.macro_("{auth_authen}").map(String::from),
+            recipient: context.macro_("{rcpt_addr}").map(String::from),
+            queue_id: context.macro_("i").map(String::from),
+        };
+        self.sender.send(seen).into_diagnostic()?;
+
+        Ok(ModificationResponse::empty_continue())
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_context_collects_session() -> Result<()> {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let milter = ContextTestMilter {
+        macro_stages: MacroStages::default(),
+        sender,
+    };
+    let (mut stream, _server) = spawn_in_memory(milter, |server| server);
+
+    negotiate_raw(&mut stream, 0).await?;
+
+    write_packet(&mut stream, b'D', b"Cj\0mx.example.com\0").await?;
+    write_packet(&mut stream, b'H', b"client.example.com\0").await?;
+    read_packet(&mut stream).await?;
+    write_packet(&mut stream, b'D', b"M{auth_authen}\0alice\0").await?;
+    write_packet(&mut stream, b'M', b"<alice@example.com>\0").await?;
+    read_packet(&mut stream).await?;
+    write_packet(&mut stream, b'E', b"").await?;
+    read_packet(&mut stream).await?;
+
+    let first = receiver.recv().await.expect("Milter did not report");
+    assert_eq!(first.helo.as_deref(), Some("client.example.com"));
+    assert_eq!(first.mta.as_deref(), Some("mx.example.com"));
+    assert_eq!(first.authen.as_deref(), Some("alice"));
+
+    // Message macros are dropped on abort, connection macros are kept
+    write_packet(&mut stream, b'A', b"").await?;
+    write_packet(&mut stream, b'E', b"").await?;
+    read_packet(&mut stream).await?;
+
+    let second = receiver.recv().await.expect("Milter did not report");
+    assert_eq!(
+        second,
+        Seen {
+            authen: None,
+            ..first
+        }
+    );
+
+    // A re-used connection starts a new session
+    write_packet(&mut stream, b'K', b"").await?;
+    write_packet(&mut stream, b'E', b"").await?;
+    read_packet(&mut stream).await?;
+
+    let third = receiver.recv().await.expect("Milter did not report");
+    assert_ne!(third.session_id, second.session_id);
+    assert_eq!(third.helo, None);
+    assert_eq!(third.mta, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_message_macros_cleared() -> Result<()> {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let milter = ContextTestMilter {
+        macro_stages: MacroStages::default(),
+        sender,
+    };
+    let (mut stream, _server) = spawn_in_memory(milter, |server| server);
+
+    negotiate_raw(&mut stream, 0).await?;
+
+    write_packet(&mut stream, b'D', b"Cj\0mx.example.com\0").await?;
+    write_packet(&mut stream, b'D', b"M{auth_authen}\0alice\0").await?;
+    write_packet(&mut stream, b'M', b"<alice@example.com>\0").await?;
+    read_packet(&mut stream).await?;
+    write_packet(&mut stream, b'D', b"R{rcpt_addr}\0bob@example.com\0").await?;
+    write_packet(&mut stream, b'R', b"<bob@example.com>\0").await?;
+    read_packet(&mut stream).await?;
+    write_packet(&mut stream, b'D', b"Ei\0QX4711\0").await?;
+    write_packet(&mut stream, b'E', b"").await?;
+    read_packet(&mut stream).await?;
+
+    let first = receiver.recv().await.expect("Milter did not report");
+    assert_eq!(first.recipient.as_deref(), Some("bob@example.com"));
+    assert_eq!(first.queue_id.as_deref(), Some("QX4711"));
+
+    // The next message on the connection, without an abort in between
+    write_packet(&mut stream, b'D', b"M{auth_authen}\0carol\0").await?;
+    write_packet(&mut stream, b'M', b"<carol@example.com>\0").await?;
+    read_packet(&mut stream).await?;
+    write_packet(&mut stream, b'E', b"").await?;
+    read_packet(&mut stream).await?;
+
+    let second = receiver.recv().await.expect("Milter did not report");
+    assert_eq!(
+        second,
+        Seen {
+            authen: Some("carol".to_string()),
+            recipient: None,
+            queue_id: None,
+            ..first
+        }
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_sends_macros() -> Result<()> {
+    let mut macro_stages = MacroStages::default();
+    macro_stages.with_stage(MacroStage::Helo, &["j"]);
+    macro_stages.with_stage(MacroStage::MailFrom, &["{auth_authen}"]);
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let milter = ContextTestMilter {
+        macro_stages,
+        sender,
+    };
+    let (stream, _server) = spawn_in_memory(milter, |server| server);
+
+    let client = Client::new(OptNeg::default());
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    // Sent automatically, as requested by the milter
+    connection.set_macro("j", "mx.example.com");
+    connection.set_macro("{auth_authen}", "alice");
+    connection
+        .helo(Helo::from(&b"client.example.com"[..]))
+        .await
+        .into_diagnostic()?;
+    connection
+        .mail(Mail::from(&b"<alice@example.com>"[..]))
+        .await
+        .into_diagnostic()?;
+    connection.end_of_body().await.into_diagnostic()?;
+
+    let seen = receiver.recv().await.expect("Milter did not report");
+    assert_eq!(seen.mta.as_deref(), Some("mx.example.com"));
+    assert_eq!(seen.authen.as_deref(), Some("alice"));
+
+    // Sent explicitly
+    connection.reset().await.into_diagnostic()?;
+    connection.remove_macro("{auth_authen}");
+    connection
+        .macro_(Macro::new(b'M', &[(b"{auth_authen}", b"bob")]))
+        .await
+        .into_diagnostic()?;
+    connection
+        .mail(Mail::from(&b"<bob@example.com>"[..]))
+        .await
+        .into_diagnostic()?;
+    connection.end_of_body().await.into_diagnostic()?;
+
+    let seen = receiver.recv().await.expect("Milter did not report");
+    assert_eq!(seen.authen.as_deref(), Some("bob"));
+
+    Ok(())
+}
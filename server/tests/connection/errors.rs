@@ -0,0 +1,157 @@
+use async_trait::async_trait;
+use miette::{miette, ErrReport, IntoDiagnostic, Result};
+
+use miltr_client::{Client, Verdict};
+use miltr_common::{
+    actions::{Action, Replycode, Tempfail},
+    commands::{Helo, Recipient},
+    modifications::ModificationResponse,
+    optneg::OptNeg,
+};
+use miltr_server::{Context, ErrorPolicy, Milter};
+use tokio::sync::mpsc;
+
+use crate::utils::spawn_in_memory;
+
+/// Fails on recipients and the end of body, panics on helo
+#[derive(Debug, Clone)]
+struct FailingTestMilter {
+    reports: mpsc::UnboundedSender<String>,
+}
+
+#[async_trait]
+impl Milter for FailingTestMilter {
+    type Error = ErrReport;
+
+    async fn helo(&mut self, _context: &Context, _helo: Helo) -> Result<Action> {
+        panic!("Helo is not implemented");
+    }
+
+    async fn rcpt(&mut self, _context: &Context, recipient: Recipient) -> Result<Action> {
+        Err(miette!("Failed looking up {}", recipient.recipient()))
+    }
+
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
+        Err(miette!("Failed scanning the body"))
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        Err(miette!("Failed cleaning up"))
+    }
+
+    fn error(&mut self, _context: &Context, error: Self::Error) {
+        self.reports
+            .send(error.to_string())
+            .expect("Report receiver dropped");
+    }
+}
+
+#[tokio::test]
+async fn test_error_reply() -> Result<()> {
+    let (reports, mut received) = mpsc::unbounded_channel();
+    let (stream, server) = spawn_in_memory(FailingTestMilter { reports }, |server| {
+        server.with_error_policy(ErrorPolicy::Reply(Tempfail.into()))
+    });
+
+    let client = Client::new(OptNeg::default());
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    let verdict = connection
+        .recipient(&b"<bob@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    assert!(matches!(verdict, Verdict::Tempfail));
+    assert_eq!(
+        received.recv().await.as_deref(),
+        Some("Failed looking up <bob@example.com>")
+    );
+
+    let response = connection.end_of_body().await.into_diagnostic()?;
+    assert!(matches!(response.final_action(), Action::Tempfail(_)));
+    assert_eq!(
+        received.recv().await.as_deref(),
+        Some("Failed scanning the body")
+    );
+
+    // Callbacks without an answer are reported as well
+    connection.reset().await.into_diagnostic()?;
+    assert_eq!(received.recv().await.as_deref(), Some("Failed cleaning up"));
+
+    connection.quit().await.into_diagnostic()?;
+    server.await.into_diagnostic()??;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_error_replycode() -> Result<()> {
+    let (reports, _received) = mpsc::unbounded_channel();
+    let replycode = Replycode::new([4, 5, 1], [4, 3, 0], "Scanner unavailable");
+    let (stream, _server) = spawn_in_memory(FailingTestMilter { reports }, |server| {
+        server.with_error_policy(ErrorPolicy::Reply(replycode.into()))
+    });
+
+    let client = Client::new(OptNeg::default());
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    let verdict = connection
+        .recipient(&b"<bob@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    let Verdict::Replycode(replycode) = verdict else {
+        panic!("Expected a reply code, got {verdict:?}");
+    };
+    assert_eq!(replycode.message(), "Scanner unavailable");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_error_close() -> Result<()> {
+    let (reports, _received) = mpsc::unbounded_channel();
+    let (stream, server) = spawn_in_memory(FailingTestMilter { reports }, |server| server);
+
+    let client = Client::new(OptNeg::default());
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    connection
+        .recipient(&b"<bob@example.com>"[..])
+        .await
+        .expect_err("Connection was not closed");
+
+    let err = server
+        .await
+        .into_diagnostic()?
+        .expect_err("Server did not return the error");
+    assert!(err.to_string().contains("Failed looking up"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_panic_isolated() -> Result<()> {
+    let (reports, _received) = mpsc::unbounded_channel();
+    let (stream, server) = spawn_in_memory(FailingTestMilter { reports }, |server| {
+        server.with_error_policy(ErrorPolicy::Reply(Tempfail.into()))
+    });
+
+    let client = Client::new(OptNeg::default());
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    connection
+        .helo(&b"localhost"[..])
+        .await
+        .expect_err("Connection was not closed");
+
+    // The panic ended the session, not the server task
+    let err = server
+        .await
+        .into_diagnostic()?
+        .expect_err("Server did not return the panic");
+    assert!(err.to_string().contains("Helo is not implemented"));
+
+    Ok(())
+}
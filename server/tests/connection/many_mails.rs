@@ -6,7 +6,7 @@ use miette::{ErrReport, Result};
 
 use miltr_common::modifications::headers::AddHeader;
 use miltr_common::modifications::ModificationResponse;
-use miltr_server::Milter;
+use miltr_server::{Context, Milter};
 
 use crate::utils::TestCase;
 
@@ -35,7 +35,10 @@ impl AddHeaderTestMilter {
 impl Milter for AddHeaderTestMilter {
     type Error = ErrReport;
 
-    async fn end_of_body(&mut self) -> std::result::Result<ModificationResponse, Self::Error> {
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> std::result::Result<ModificationResponse, Self::Error> {
         self.end_of_body_called
             .fetch_add(1, atomic::Ordering::SeqCst);
         let mut builder = ModificationResponse::builder();
@@ -45,7 +48,7 @@ impl Milter for AddHeaderTestMilter {
         Ok(response)
     }
 
-    async fn abort(&mut self) -> std::result::Result<(), Self::Error> {
+    async fn abort(&mut self, _context: &Context) -> std::result::Result<(), Self::Error> {
         Ok(())
     }
 }
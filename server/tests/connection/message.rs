@@ -0,0 +1,156 @@
+use async_trait::async_trait;
+use miette::{ErrReport, IntoDiagnostic, Result};
+
+use miltr_client::{Client, Envelope, Stage, Verdict};
+use miltr_common::{
+    actions::{Action, Continue, Discard, Reject},
+    commands::{Body, Header, Recipient},
+    modifications::ModificationResponse,
+    optneg::OptNeg,
+};
+use miltr_server::{Context, Milter};
+use tokio::sync::mpsc;
+
+use crate::utils::spawn_in_memory;
+
+/// What the milter received of a single message
+#[derive(Debug, Default)]
+struct Received {
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+/// Records messages, rejecting some recipients and spam headers
+#[derive(Debug)]
+struct MessageTestMilter {
+    current: Received,
+    reports: mpsc::UnboundedSender<Received>,
+}
+
+#[async_trait]
+impl Milter for MessageTestMilter {
+    type Error = ErrReport;
+
+    async fn rcpt(&mut self, _context: &Context, recipient: Recipient) -> Result<Action> {
+        let action = match recipient.recipient().as_ref() {
+            "<rejected@example.com>" => Reject.into(),
+            _ => Continue.into(),
+        };
+        Ok(action)
+    }
+
+    async fn header(&mut self, _context: &Context, header: Header) -> Result<Action> {
+        let header = (header.name().to_string(), header.value().to_string());
+        let is_spam = header.0 == "X-Spam";
+        self.current.headers.push(header);
+
+        if is_spam {
+            return Ok(Discard.into());
+        }
+        Ok(Continue.into())
+    }
+
+    async fn body(&mut self, _context: &Context, body: Body) -> Result<Action> {
+        self.current.body.extend_from_slice(body.as_bytes());
+        Ok(Continue.into())
+    }
+
+    async fn end_of_body(&mut self, _context: &Context) -> Result<ModificationResponse> {
+        let received = std::mem::take(&mut self.current);
+        self.reports.send(received).into_diagnostic()?;
+        Ok(ModificationResponse::empty_continue())
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        self.current = Received::default();
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_process_message() -> Result<()> {
+    let (reports, mut received) = mpsc::unbounded_channel();
+    let milter = MessageTestMilter {
+        current: Received::default(),
+        reports,
+    };
+    let (stream, _server) = spawn_in_memory(milter, |server| server);
+
+    let client = Client::new(OptNeg::default());
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    let envelope = Envelope::new(b"<alice@example.com>")
+        .with_helo(b"localhost")
+        .with_recipient(b"<bob@example.com>")
+        .with_recipient(b"<rejected@example.com>");
+    let message = b"Subject: Hi\r\nX-Folded: first\r\n second\r\n\r\nHello\r\nBob\r\n";
+
+    let outcome = connection
+        .process_message(&envelope, &message[..])
+        .await
+        .into_diagnostic()?;
+
+    assert!(matches!(outcome.final_action(), Action::Continue(_)));
+    assert_eq!(outcome.decided_at(), None);
+    let [(recipient, verdict)] = outcome.rejected_recipients() else {
+        panic!("Expected one rejected recipient");
+    };
+    assert_eq!(recipient.recipient(), "<rejected@example.com>");
+    assert!(matches!(verdict, Verdict::Reject));
+
+    let received = received.recv().await.expect("Milter did not report");
+    assert_eq!(
+        received.headers,
+        vec![
+            ("Subject".to_string(), "Hi".to_string()),
+            ("X-Folded".to_string(), "first\n second".to_string()),
+        ]
+    );
+    assert_eq!(received.body, b"Hello\r\nBob\r\n");
+
+    connection.quit().await.into_diagnostic()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_process_message_stops_early() -> Result<()> {
+    let (reports, mut received) = mpsc::unbounded_channel();
+    let milter = MessageTestMilter {
+        current: Received::default(),
+        reports,
+    };
+    let (stream, _server) = spawn_in_memory(milter, |server| server);
+
+    let client = Client::new(OptNeg::default());
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    // All recipients rejected
+    let envelope = Envelope::new(b"<alice@example.com>").with_recipient(b"<rejected@example.com>");
+    let outcome = connection
+        .process_message(&envelope, &b"Subject: Hi\r\n\r\nHello\r\n"[..])
+        .await
+        .into_diagnostic()?;
+    assert_eq!(outcome.decided_at(), Some(Stage::Recipient));
+    assert!(matches!(outcome.final_action(), Action::Reject(_)));
+    assert_eq!(outcome.rejected_recipients().len(), 1);
+
+    // Discarded on a header, the connection is usable afterwards
+    let envelope = Envelope::new(b"<alice@example.com>").with_recipient(b"<bob@example.com>");
+    let outcome = connection
+        .process_message(&envelope, &b"X-Spam: yes\r\n\r\nBuy now\r\n"[..])
+        .await
+        .into_diagnostic()?;
+    assert_eq!(outcome.decided_at(), Some(Stage::Header));
+    assert!(matches!(outcome.final_action(), Action::Discard(_)));
+
+    let outcome = connection
+        .process_message(&envelope, &b"Subject: Hi\r\n\r\nHello\r\n"[..])
+        .await
+        .into_diagnostic()?;
+    assert_eq!(outcome.decided_at(), None);
+    let received = received.recv().await.expect("Milter did not report");
+    assert_eq!(received.body, b"Hello\r\n");
+
+    connection.quit().await.into_diagnostic()?;
+    Ok(())
+}
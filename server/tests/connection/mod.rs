@@ -1 +1,22 @@
+mod actions;
+mod chain;
+#[cfg(feature = "collect")]
+mod collect;
+mod context;
+mod errors;
 mod many_mails;
+mod message;
+mod optneg;
+mod ordering;
+mod progress;
+mod protocol;
+mod replace_body;
+mod reuse;
+#[cfg(feature = "serve")]
+mod serve;
+#[cfg(feature = "tower")]
+mod service;
+mod timeouts;
+#[cfg(feature = "tracing")]
+mod tracing;
+mod verdicts;
@@ -0,0 +1,105 @@
+use async_trait::async_trait;
+use miette::{ErrReport, IntoDiagnostic, Result};
+
+use miltr_client::Client;
+use miltr_common::optneg::{Capability, MacroStage, OptNeg, Protocol};
+use miltr_server::{Context, Error, Milter};
+
+use crate::utils::spawn_in_memory;
+
+#[derive(Debug, Clone)]
+struct MacroRequestTestMilter;
+
+#[async_trait]
+impl Milter for MacroRequestTestMilter {
+    type Error = ErrReport;
+
+    async fn option_negotiation(&mut self, _: OptNeg) -> Result<OptNeg, Error<Self::Error>> {
+        let mut optneg = OptNeg::default();
+        optneg
+            .macro_stages
+            .with_stage(MacroStage::Connect, &["j", "{daemon_addr}"]);
+        optneg
+            .macro_stages
+            .with_stage(MacroStage::EndOfBody, &["{i}"]);
+
+        Ok(optneg)
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_client_receives_macro_stages() -> Result<()> {
+    let (stream, _server) = spawn_in_memory(MacroRequestTestMilter, |server| server);
+
+    let client = Client::new(OptNeg::default());
+    let connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    let options = connection.options();
+    assert!(options.capabilities.contains(Capability::SMFIF_SETSYMLIST));
+    assert_eq!(
+        options.macro_stages[MacroStage::Connect],
+        vec!["j", "{daemon_addr}"]
+    );
+    assert_eq!(options.macro_stages[MacroStage::EndOfBody], vec!["{i}"]);
+    assert!(options.macro_stages[MacroStage::Helo].is_empty());
+
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+struct DefaultTestMilter;
+
+#[async_trait]
+impl Milter for DefaultTestMilter {
+    type Error = ErrReport;
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_downgrade_to_client_version() -> Result<()> {
+    let (stream, _server) = spawn_in_memory(DefaultTestMilter, |server| server);
+
+    let client = Client::new(OptNeg {
+        version: 2,
+        protocol: Protocol::all(),
+        ..Default::default()
+    });
+    let connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    let options = connection.options();
+    assert_eq!(options.version, 2);
+    assert_eq!(options.capabilities, Capability::for_version(2));
+    assert!(!options.capabilities.contains(Capability::SMFIF_CHGFROM));
+    assert!(!options.protocol.contains(Protocol::NR_HEADER));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reject_unsupported_client_version() -> Result<()> {
+    let (stream, server) = spawn_in_memory(DefaultTestMilter, |server| server);
+
+    let client = Client::new(OptNeg {
+        version: 1,
+        ..Default::default()
+    });
+    let _err = client
+        .connect_via(stream)
+        .await
+        .err()
+        .expect("Connected with unsupported version");
+
+    server
+        .await
+        .into_diagnostic()?
+        .expect_err("Server accepted unsupported version");
+
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use async_trait::async_trait;
+use miette::{IntoDiagnostic, Result};
+
+use miltr_common::optneg::Protocol;
+use miltr_server::{Context, Milter, OrderingPolicy};
+
+use crate::utils::{negotiate_raw, read_packet, spawn_in_memory, write_packet};
+
+/// Continues everything
+struct ContinueTestMilter;
+
+#[async_trait]
+impl Milter for ContinueTestMilter {
+    type Error = &'static str;
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_reject_out_of_order() -> Result<()> {
+    let (mut stream, server) = spawn_in_memory(ContinueTestMilter, |server| {
+        server.with_ordering_policy(OrderingPolicy::Reject)
+    });
+    negotiate_raw(&mut stream, 0).await?;
+
+    // A body without a sender
+    write_packet(&mut stream, b'B', b"Hello\r\n").await?;
+    read_packet(&mut stream)
+        .await
+        .expect_err("Connection was not closed");
+
+    let err = server
+        .await
+        .into_diagnostic()?
+        .expect_err("Server did not return the error");
+    assert!(err.to_string().contains("out of order"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_report_out_of_order() -> Result<()> {
+    let (mut stream, _server) = spawn_in_memory(ContinueTestMilter, |server| server);
+    negotiate_raw(&mut stream, 0).await?;
+
+    write_packet(&mut stream, b'B', b"Hello\r\n").await?;
+    let (code, _payload) = read_packet(&mut stream).await?;
+
+    assert_eq!(code, b'c');
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_abort_resets_state() -> Result<()> {
+    let (mut stream, _server) = spawn_in_memory(ContinueTestMilter, |server| {
+        server.with_ordering_policy(OrderingPolicy::Reject)
+    });
+    negotiate_raw(&mut stream, Protocol::empty().bits()).await?;
+
+    write_packet(&mut stream, b'M', b"<alice@example.com>\0").await?;
+    assert_eq!(read_packet(&mut stream).await?.0, b'c');
+    write_packet(&mut stream, b'L', b"Subject\0Hi\0").await?;
+    assert_eq!(read_packet(&mut stream).await?.0, b'c');
+
+    // The next mail may start after an abort
+    write_packet(&mut stream, b'A', &[]).await?;
+    write_packet(&mut stream, b'M', b"<alice@example.com>\0").await?;
+    assert_eq!(read_packet(&mut stream).await?.0, b'c');
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_quit_nc_resets_state() -> Result<()> {
+    let (mut stream, _server) = spawn_in_memory(ContinueTestMilter, |server| {
+        server.with_ordering_policy(OrderingPolicy::Reject)
+    });
+    negotiate_raw(&mut stream, Protocol::empty().bits()).await?;
+
+    write_packet(&mut stream, b'H', b"localhost\0").await?;
+    assert_eq!(read_packet(&mut stream).await?.0, b'c');
+    write_packet(&mut stream, b'M', b"<alice@example.com>\0").await?;
+    assert_eq!(read_packet(&mut stream).await?.0, b'c');
+
+    // A new session starts without option negotiation
+    write_packet(&mut stream, b'K', &[]).await?;
+    write_packet(&mut stream, b'H', b"localhost\0").await?;
+    assert_eq!(read_packet(&mut stream).await?.0, b'c');
+
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use miette::{ErrReport, IntoDiagnostic, Result};
+
+use miltr_client::Client;
+use miltr_common::{actions::Action, modifications::ModificationResponse, optneg::OptNeg};
+use miltr_server::{Context, Milter};
+use tokio::time::sleep;
+
+use crate::utils::{negotiate_raw, read_packet, spawn_in_memory, write_packet};
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(20);
+const END_OF_BODY_DURATION: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone)]
+struct SlowTestMilter;
+
+#[async_trait]
+impl Milter for SlowTestMilter {
+    type Error = ErrReport;
+
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
+        sleep(END_OF_BODY_DURATION).await;
+        Ok(ModificationResponse::empty_continue())
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_client_accepts_progress() -> Result<()> {
+    let (stream, _server) = spawn_in_memory(SlowTestMilter, |server| {
+        server.with_progress_interval(PROGRESS_INTERVAL)
+    });
+
+    let client = Client::new(OptNeg::default());
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    let response = connection.end_of_body().await.into_diagnostic()?;
+
+    assert!(matches!(response.final_action(), Action::Continue(_)));
+    assert!(response.modifications().is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_sends_progress() -> Result<()> {
+    let (mut stream, _server) = spawn_in_memory(SlowTestMilter, |server| {
+        server.with_progress_interval(PROGRESS_INTERVAL)
+    });
+
+    negotiate_raw(&mut stream, 0).await?;
+    write_packet(&mut stream, b'E', &[]).await?;
+
+    let mut progress_count = 0;
+    loop {
+        match read_packet(&mut stream).await?.0 {
+            b'p' => progress_count += 1,
+            code => {
+                assert_eq!(code, b'c');
+                break;
+            }
+        }
+    }
+
+    assert!(progress_count > 0);
+
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use async_trait::async_trait;
+use miette::{ErrReport, IntoDiagnostic, Result};
+
+use miltr_client::Client;
+use miltr_common::{
+    actions::{Action, Reject},
+    commands::{Header, Helo},
+    optneg::{OptNeg, Protocol},
+    ProtocolError,
+};
+use miltr_server::{Context, Error, Milter};
+
+use crate::utils::{negotiate_raw, read_packet, spawn_in_memory, write_packet};
+
+/// Rejects everything it is notified about
+#[derive(Debug, Clone)]
+struct RejectingTestMilter {
+    protocol: Protocol,
+}
+
+#[async_trait]
+impl Milter for RejectingTestMilter {
+    type Error = ErrReport;
+
+    async fn option_negotiation(&mut self, theirs: OptNeg) -> Result<OptNeg, Error<Self::Error>> {
+        let ours = OptNeg {
+            protocol: self.protocol,
+            ..Default::default()
+        };
+        Ok(ours
+            .merge_compatible(&theirs)
+            .map_err(ProtocolError::CompatibilityError)?)
+    }
+
+    async fn helo(&mut self, _context: &Context, _helo: Helo) -> Result<Action, Self::Error> {
+        Ok(Reject.into())
+    }
+
+    async fn header(&mut self, _context: &Context, _header: Header) -> Result<Action, Self::Error> {
+        Ok(Reject.into())
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_no_reply_for_headers() -> Result<()> {
+    let milter = RejectingTestMilter {
+        protocol: Protocol::NR_HEADER,
+    };
+    let (stream, _server) = spawn_in_memory(milter, |server| server);
+
+    let client = Client::new(OptNeg {
+        protocol: Protocol::all(),
+        ..Default::default()
+    });
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+    assert_eq!(connection.options().protocol, Protocol::NR_HEADER);
+
+    connection
+        .header(Header::new(b"Subject", b"Hi"))
+        .await
+        .into_diagnostic()?;
+
+    // If the rejected header had been answered, end of body would read it
+    let response = connection.end_of_body().await.into_diagnostic()?;
+    assert!(matches!(response.final_action(), Action::Continue(_)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_disabled_stage_not_notified() -> Result<()> {
+    let milter = RejectingTestMilter {
+        protocol: Protocol::NO_HELO,
+    };
+    let (mut stream, _server) = spawn_in_memory(milter, |server| server);
+
+    negotiate_raw(&mut stream, Protocol::all().bits()).await?;
+
+    // Send a helo anyway, the milter must not be asked
+    write_packet(&mut stream, b'H', b"localhost\0").await?;
+    let (code, _payload) = read_packet(&mut stream).await?;
+
+    assert_eq!(code, b'c');
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_disabled_stage_no_reply() -> Result<()> {
+    let milter = RejectingTestMilter {
+        protocol: Protocol::NO_HELO | Protocol::NR_HELO,
+    };
+    let (mut stream, _server) = spawn_in_memory(milter, |server| server);
+
+    negotiate_raw(&mut stream, Protocol::all().bits()).await?;
+
+    // Neither the helo nor the data are answered
+    write_packet(&mut stream, b'H', b"localhost\0").await?;
+    write_packet(&mut stream, b'E', &[]).await?;
+    let (code, _payload) = read_packet(&mut stream).await?;
+
+    assert_eq!(code, b'c');
+
+    Ok(())
+}
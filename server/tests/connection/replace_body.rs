@@ -0,0 +1,107 @@
+use async_trait::async_trait;
+use futures::io::Cursor;
+use miette::{IntoDiagnostic, Result};
+
+use miltr_client::{Client, Message};
+use miltr_common::{
+    modifications::{
+        body::{ReplaceBody, ReplaceBodyStream},
+        ModificationAction, ModificationResponse,
+    },
+    optneg::OptNeg,
+};
+use miltr_server::{Context, Milter};
+
+use crate::utils::spawn_in_memory;
+
+/// How the test milter replaces the body
+#[derive(Debug, Clone, Copy)]
+enum Replace {
+    Whole,
+    Reader,
+    Chunks,
+}
+
+/// Replaces every body with `body()`
+struct ReplaceBodyTestMilter(Replace);
+
+fn body() -> Vec<u8> {
+    (0..200_000_u32).map(|i| b'a' + (i % 26) as u8).collect()
+}
+
+#[async_trait]
+impl Milter for ReplaceBodyTestMilter {
+    type Error = &'static str;
+
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
+        let mut builder = ModificationResponse::builder();
+        let response = match self.0 {
+            Replace::Whole => {
+                builder.push(ReplaceBody::new(&body()));
+                builder.contin()
+            }
+            Replace::Reader => builder
+                .contin()
+                .with_body_stream(ReplaceBodyStream::from_reader(Cursor::new(body()))),
+            Replace::Chunks => {
+                let body = body();
+                let chunks: Vec<Vec<u8>> = body.chunks(100_000).map(<[u8]>::to_vec).collect();
+                builder
+                    .contin()
+                    .with_body_stream(ReplaceBodyStream::from_chunks(chunks))
+            }
+        };
+        Ok(response)
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+async fn assert_replaced_in_chunks(replace: Replace) -> Result<()> {
+    let (stream, _server) = spawn_in_memory(ReplaceBodyTestMilter(replace), |server| server);
+
+    let client = Client::new(OptNeg::default());
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    connection
+        .mail(&b"<alice@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    let response = connection.end_of_body().await.into_diagnostic()?;
+
+    let chunks = response.modifications();
+    assert!(chunks.len() > 1, "Body was not split");
+    for chunk in chunks {
+        let ModificationAction::ReplaceBody(chunk) = chunk else {
+            panic!("Expected only body replacements, got {chunk:?}");
+        };
+        assert!(chunk.as_bytes().len() <= ReplaceBody::MAX_CHUNK_SIZE);
+    }
+
+    let mut message = Message::new("<alice@example.com>").with_body(b"Hello\r\n");
+    message.apply(&response);
+    assert_eq!(message.body(), body());
+
+    connection.quit().await.into_diagnostic()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replace_body_split() -> Result<()> {
+    assert_replaced_in_chunks(Replace::Whole).await
+}
+
+#[tokio::test]
+async fn test_replace_body_from_reader() -> Result<()> {
+    assert_replaced_in_chunks(Replace::Reader).await
+}
+
+#[tokio::test]
+async fn test_replace_body_from_chunks() -> Result<()> {
+    assert_replaced_in_chunks(Replace::Chunks).await
+}
@@ -0,0 +1,109 @@
+use async_trait::async_trait;
+use miette::{ErrReport, IntoDiagnostic, Result};
+
+use miltr_client::Client;
+use miltr_common::{
+    actions::{Action, Continue},
+    commands::Mail,
+    modifications::ModificationResponse,
+    optneg::OptNeg,
+};
+use miltr_server::{Context, Milter};
+use tokio::sync::mpsc;
+
+use crate::utils::spawn_in_memory;
+
+/// A mail seen by the milter
+#[derive(Debug, PartialEq)]
+struct Seen {
+    session_id: u64,
+    helo: Option<String>,
+    sender: String,
+}
+
+/// Reports every mail at it's end of body
+#[derive(Debug, Clone)]
+struct ReuseTestMilter {
+    sender: Option<String>,
+    reports: mpsc::UnboundedSender<Seen>,
+}
+
+#[async_trait]
+impl Milter for ReuseTestMilter {
+    type Error = ErrReport;
+
+    async fn mail(&mut self, _context: &Context, mail: Mail) -> Result<Action> {
+        self.sender = Some(mail.sender().into_owned());
+        Ok(Continue.into())
+    }
+
+    async fn end_of_body(&mut self, context: &Context) -> Result<ModificationResponse> {
+        let seen = Seen {
+            session_id: context.session_id(),
+            helo: context.helo().map(String::from),
+            sender: self.sender.take().unwrap_or_default(),
+        };
+        self.reports.send(seen).into_diagnostic()?;
+
+        Ok(ModificationResponse::empty_continue())
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        self.sender = None;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_many_mails_one_connection() -> Result<()> {
+    let (reports, mut received) = mpsc::unbounded_channel();
+    let milter = ReuseTestMilter {
+        sender: None,
+        reports,
+    };
+    let (stream, server) = spawn_in_memory(milter, |server| server);
+
+    let client = Client::new(OptNeg::default());
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    // Two mails within the same smtp session
+    connection
+        .helo(&b"client.example.com"[..])
+        .await
+        .into_diagnostic()?;
+    for sender in ["<alice@example.com>", "<bob@example.com>"] {
+        connection.mail(sender.as_bytes()).await.into_diagnostic()?;
+        connection.end_of_body().await.into_diagnostic()?;
+        connection.reset().await.into_diagnostic()?;
+    }
+
+    // A new smtp session on the same connection
+    connection.quit_nc().await.into_diagnostic()?;
+    connection
+        .mail(&b"<carol@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    connection.end_of_body().await.into_diagnostic()?;
+    connection.quit().await.into_diagnostic()?;
+
+    server.await.into_diagnostic()??;
+
+    let alice = received.recv().await.expect("Missing first mail");
+    let bob = received.recv().await.expect("Missing second mail");
+    let carol = received.recv().await.expect("Missing third mail");
+
+    assert_eq!(alice.sender, "<alice@example.com>");
+    assert_eq!(alice.helo.as_deref(), Some("client.example.com"));
+    assert_eq!(
+        bob,
+        Seen {
+            sender: "<bob@example.com>".to_string(),
+            ..alice
+        }
+    );
+    assert_eq!(carol.sender, "<carol@example.com>");
+    assert_eq!(carol.helo, None);
+    assert_ne!(carol.session_id, bob.session_id);
+
+    Ok(())
+}
@@ -0,0 +1,229 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use miette::{ErrReport, IntoDiagnostic, Result};
+
+use miltr_client::Client;
+use miltr_common::{
+    actions::Action, modifications::ModificationResponse, optneg::OptNeg, socket::SocketSpec,
+};
+use miltr_server::{
+    serve::{Listener, Serve},
+    Context, Milter,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::oneshot,
+    time::{sleep, timeout},
+};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+const END_OF_BODY_DURATION: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+struct CountingTestMilter;
+
+#[async_trait]
+impl Milter for CountingTestMilter {
+    type Error = ErrReport;
+
+    async fn end_of_body(&mut self, _context: &Context) -> Result<ModificationResponse> {
+        sleep(END_OF_BODY_DURATION).await;
+        Ok(ModificationResponse::empty_continue())
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Bind a listener on a random port, returning it's address
+async fn listen() -> Result<(Listener, String)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.into_diagnostic()?;
+    let addr = listener.local_addr().into_diagnostic()?.to_string();
+    Ok((listener.into(), addr))
+}
+
+#[tokio::test]
+async fn test_serve_milter_per_connection() -> Result<()> {
+    let (listener, addr) = listen().await?;
+    let created = Arc::new(AtomicUsize::new(0));
+    let (shutdown, shutdown_signal) = oneshot::channel::<()>();
+
+    let factory_created = Arc::clone(&created);
+    let serving = tokio::spawn(
+        Serve::new(move || {
+            factory_created.fetch_add(1, Ordering::SeqCst);
+            CountingTestMilter
+        })
+        .run_until(listener, async {
+            shutdown_signal.await.ok();
+        }),
+    );
+
+    for _ in 0..3 {
+        let stream = TcpStream::connect(&addr).await.into_diagnostic()?;
+        let client = Client::new(OptNeg::default());
+        let mut connection = client
+            .connect_via(stream.compat())
+            .await
+            .into_diagnostic()?;
+        let response = connection.end_of_body().await.into_diagnostic()?;
+        assert!(matches!(response.final_action(), Action::Continue(_)));
+        connection.quit().await.into_diagnostic()?;
+    }
+
+    shutdown.send(()).expect("Server stopped early");
+    serving.await.into_diagnostic()?.into_diagnostic()?;
+
+    assert_eq!(created.load(Ordering::SeqCst), 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_serve_drains_sessions() -> Result<()> {
+    let (listener, addr) = listen().await?;
+    let (shutdown, shutdown_signal) = oneshot::channel::<()>();
+
+    let serving = tokio::spawn(
+        Serve::new(|| CountingTestMilter).run_until(listener, async {
+            shutdown_signal.await.ok();
+        }),
+    );
+
+    let stream = TcpStream::connect(&addr).await.into_diagnostic()?;
+    let client = Client::new(OptNeg::default());
+    let mut connection = client
+        .connect_via(stream.compat())
+        .await
+        .into_diagnostic()?;
+
+    // Shut down while the end of body is still being handled
+    let session = tokio::spawn(async move {
+        let response = connection.end_of_body().await;
+        connection.quit().await.ok();
+        response
+    });
+    sleep(END_OF_BODY_DURATION / 4).await;
+    shutdown.send(()).expect("Server stopped early");
+
+    let response = session.await.into_diagnostic()?.into_diagnostic()?;
+    assert!(matches!(response.final_action(), Action::Continue(_)));
+    serving.await.into_diagnostic()?.into_diagnostic()?;
+
+    // No new connections are accepted anymore
+    assert!(TcpStream::connect(&addr).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_serve_max_connections() -> Result<()> {
+    let (listener, addr) = listen().await?;
+
+    let _serving = tokio::spawn(
+        Serve::new(|| CountingTestMilter)
+            .with_max_connections(1)
+            .run(listener),
+    );
+
+    let client = Client::new(OptNeg::default());
+    let stream = TcpStream::connect(&addr).await.into_diagnostic()?;
+    let first = client
+        .connect_via(stream.compat())
+        .await
+        .into_diagnostic()?;
+
+    // The second connection is not handled while the first one is open
+    let stream = TcpStream::connect(&addr).await.into_diagnostic()?;
+    let mut second = Box::pin(client.connect_via(stream.compat()));
+    assert!(timeout(END_OF_BODY_DURATION, &mut second).await.is_err());
+
+    first.quit().await.into_diagnostic()?;
+    let second = second.await.into_diagnostic()?;
+    second.quit().await.into_diagnostic()?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_serve_max_connections_shared() -> Result<()> {
+    let (first_listener, first_addr) = listen().await?;
+    let (second_listener, second_addr) = listen().await?;
+
+    let _serving = tokio::spawn(
+        Serve::new(|| CountingTestMilter)
+            .with_max_connections(1)
+            .run_all(vec![first_listener, second_listener]),
+    );
+
+    let client = Client::new(OptNeg::default());
+    let stream = TcpStream::connect(&first_addr).await.into_diagnostic()?;
+    let first = client
+        .connect_via(stream.compat())
+        .await
+        .into_diagnostic()?;
+
+    // The cap counts the connections of both listeners
+    let stream = TcpStream::connect(&second_addr).await.into_diagnostic()?;
+    let mut second = Box::pin(client.connect_via(stream.compat()));
+    assert!(timeout(END_OF_BODY_DURATION, &mut second).await.is_err());
+
+    first.quit().await.into_diagnostic()?;
+    let second = second.await.into_diagnostic()?;
+    second.quit().await.into_diagnostic()?;
+
+    Ok(())
+}
+
+/// Serve on `listener` and run a session connecting via `spec`
+async fn session_via_spec(listener: Listener, spec: &SocketSpec) -> Result<()> {
+    let (shutdown, shutdown_signal) = oneshot::channel::<()>();
+    let serving = tokio::spawn(
+        Serve::new(|| CountingTestMilter).run_until(listener, async {
+            shutdown_signal.await.ok();
+        }),
+    );
+
+    let client = Client::new(OptNeg::default());
+    let mut connection = client.connect(spec).await.into_diagnostic()?;
+    let response = connection.end_of_body().await.into_diagnostic()?;
+    assert!(matches!(response.final_action(), Action::Continue(_)));
+    connection.quit().await.into_diagnostic()?;
+
+    shutdown.send(()).expect("Server stopped early");
+    serving.await.into_diagnostic()?.into_diagnostic()?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_serve_inet_spec() -> Result<()> {
+    let (listener, addr) = listen().await?;
+    let port = addr.rsplit_once(':').expect("Addr without port").1;
+    let spec: SocketSpec = format!("inet:{port}@127.0.0.1").parse().into_diagnostic()?;
+
+    session_via_spec(listener, &spec).await
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_serve_unix_spec() -> Result<()> {
+    let path = std::env::temp_dir().join(format!("miltr-{}-serve.sock", std::process::id()));
+    let spec: SocketSpec = format!("unix:{}", path.display())
+        .parse()
+        .into_diagnostic()?;
+    let listener = Listener::bind(&spec).await.into_diagnostic()?;
+
+    session_via_spec(listener, &spec).await?;
+    assert!(!path.exists());
+
+    Ok(())
+}
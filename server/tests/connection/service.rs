@@ -0,0 +1,218 @@
+use std::{convert::Infallible, io, time::Duration};
+
+use async_trait::async_trait;
+use miette::{IntoDiagnostic, Result};
+
+use miltr_client::{Client, Verdict};
+use miltr_common::{
+    actions::{Action, Continue, Reject, Tempfail},
+    commands::{Header, Helo, Recipient},
+    decoding::ClientCommand,
+    encoding::ServerMessage,
+    modifications::{headers::AddHeader, ModificationAction, ModificationResponse},
+    optneg::{Capability, OptNeg, Protocol},
+    ProtocolError,
+};
+use miltr_server::{
+    service::{MilterService, ServiceError, ServiceMilter},
+    Context, Error, ErrorPolicy, Milter,
+};
+use tokio::sync::mpsc;
+use tower::{service_fn, ServiceBuilder};
+
+use crate::utils::spawn_in_memory;
+
+/// Rejects a recipient, stalls on a slow helo and reports aborts
+struct ServiceTestMilter {
+    recipients: usize,
+    reports: mpsc::UnboundedSender<&'static str>,
+}
+
+#[async_trait]
+impl Milter for ServiceTestMilter {
+    type Error = io::Error;
+
+    async fn option_negotiation(&mut self, theirs: OptNeg) -> Result<OptNeg, Error<Self::Error>> {
+        let ours = OptNeg {
+            capabilities: Capability::SMFIF_ADDHDRS,
+            protocol: Protocol::NO_CONNECT | Protocol::NR_HEADER,
+            ..Default::default()
+        };
+        Ok(ours
+            .merge_compatible(&theirs)
+            .map_err(ProtocolError::CompatibilityError)?)
+    }
+
+    async fn helo(&mut self, _context: &Context, helo: Helo) -> Result<Action, Self::Error> {
+        if helo.helo() == "slow.example.com" {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+        Ok(Continue.into())
+    }
+
+    async fn rcpt(
+        &mut self,
+        _context: &Context,
+        recipient: Recipient,
+    ) -> Result<Action, Self::Error> {
+        if recipient.recipient() == "<spam@example.com>" {
+            return Ok(Reject.into());
+        }
+        self.recipients += 1;
+        Ok(Continue.into())
+    }
+
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
+        let mut builder = ModificationResponse::builder();
+        let recipients = self.recipients.to_string();
+        builder.push(AddHeader::new(b"X-Recipients", recipients.as_bytes()));
+        Ok(builder.contin())
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        self.reports.send("abort").expect("Failed reporting");
+        Ok(())
+    }
+}
+
+fn client() -> Client {
+    Client::new(OptNeg {
+        protocol: Protocol::all(),
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn test_service_roundtrip() -> Result<()> {
+    let (reports, mut received) = mpsc::unbounded_channel();
+    let service = ServiceBuilder::new()
+        .concurrency_limit(1)
+        .timeout(Duration::from_secs(1))
+        .service(MilterService::new(ServiceTestMilter {
+            recipients: 0,
+            reports,
+        }));
+    let (stream, server) = spawn_in_memory(ServiceMilter::new(service), |server| server);
+
+    let mut connection = client().connect_via(stream).await.into_diagnostic()?;
+    assert_eq!(
+        connection.options().protocol,
+        Protocol::NO_CONNECT | Protocol::NR_HEADER
+    );
+
+    connection
+        .helo(&b"mail.example.com"[..])
+        .await
+        .into_diagnostic()?;
+    connection
+        .mail(&b"<alice@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    let verdict = connection
+        .recipient(&b"<spam@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    assert!(matches!(verdict, Verdict::Reject));
+    connection
+        .recipient(&b"<bob@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    // Not replied to, the service responds with nothing
+    connection
+        .header(Header::new(b"Subject", b"Hi"))
+        .await
+        .into_diagnostic()?;
+
+    let response = connection.end_of_body().await.into_diagnostic()?;
+    assert_matches::assert_matches!(
+        response.modifications(),
+        [ModificationAction::AddHeader(add)] if add.value() == "1"
+    );
+    assert!(matches!(response.final_action(), Action::Continue(_)));
+
+    connection.reset().await.into_diagnostic()?;
+    connection.quit().await.into_diagnostic()?;
+    server.await.into_diagnostic()??;
+    assert_eq!(received.recv().await, Some("abort"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_service_timeout() -> Result<()> {
+    let (reports, _received) = mpsc::unbounded_channel();
+    let service = ServiceBuilder::new()
+        .timeout(Duration::from_millis(50))
+        .service(MilterService::new(ServiceTestMilter {
+            recipients: 0,
+            reports,
+        }));
+    let (stream, _server) = spawn_in_memory(ServiceMilter::new(service), |server| {
+        server.with_error_policy(ErrorPolicy::Reply(Tempfail.into()))
+    });
+
+    let mut connection = client().connect_via(stream).await.into_diagnostic()?;
+
+    let verdict = connection
+        .helo(&b"slow.example.com"[..])
+        .await
+        .into_diagnostic()?;
+    assert!(matches!(verdict, Verdict::Tempfail));
+
+    // The session is still usable
+    let verdict = connection
+        .helo(&b"mail.example.com"[..])
+        .await
+        .into_diagnostic()?;
+    assert!(matches!(verdict, Verdict::Continue));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_service_server_config() -> Result<()> {
+    let (reports, _received) = mpsc::unbounded_channel();
+    let service = MilterService::new(ServiceTestMilter {
+        recipients: 0,
+        reports,
+    })
+    .with_server_config(|server| server.with_callback_timeout(Duration::from_millis(50), Tempfail));
+    let (stream, _server) = spawn_in_memory(ServiceMilter::new(service), |server| server);
+
+    let mut connection = client().connect_via(stream).await.into_diagnostic()?;
+
+    // Timed out by the server handling the service call
+    let verdict = connection
+        .helo(&b"slow.example.com"[..])
+        .await
+        .into_diagnostic()?;
+    assert!(matches!(verdict, Verdict::Tempfail));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_service_missing_final_action() {
+    // Answers the end of body with a modification only
+    let service = service_fn(|_command: ClientCommand| async {
+        let add = AddHeader::new(b"X-Checked", b"yes");
+        Ok::<_, Infallible>(vec![ServerMessage::ModificationAction(add.into())])
+    });
+    let mut milter = ServiceMilter::new(service);
+
+    let error = milter
+        .end_of_body(&Context::new())
+        .await
+        .expect_err("Accepted modifications without a final action");
+    assert_matches::assert_matches!(
+        error,
+        ServiceError::UnexpectedResponse { expected: "a final action", responses }
+            if matches!(
+                responses.as_slice(),
+                [ServerMessage::ModificationAction(ModificationAction::AddHeader(_))]
+            )
+    );
+}
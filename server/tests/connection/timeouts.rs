@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use miette::{ErrReport, IntoDiagnostic, Result};
+
+use miltr_client::{Client, ResponseError, Verdict};
+use miltr_common::{
+    actions::{Action, Continue, Tempfail},
+    commands::Recipient,
+    modifications::ModificationResponse,
+    optneg::OptNeg,
+};
+use miltr_server::{Context, Error, Milter, Server};
+use tokio::{io::duplex, time::sleep};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+use crate::utils::spawn_in_memory;
+
+const TIMEOUT: Duration = Duration::from_millis(50);
+const CALLBACK_DURATION: Duration = Duration::from_millis(300);
+
+/// Takes longer than the timeouts to answer recipients and the end of body
+#[derive(Debug, Clone)]
+struct SlowTestMilter;
+
+#[async_trait]
+impl Milter for SlowTestMilter {
+    type Error = ErrReport;
+
+    async fn rcpt(&mut self, _context: &Context, _recipient: Recipient) -> Result<Action> {
+        sleep(CALLBACK_DURATION).await;
+        Ok(Continue.into())
+    }
+
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
+        sleep(CALLBACK_DURATION).await;
+        Ok(ModificationResponse::empty_continue())
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_client_command_timeout() -> Result<()> {
+    let (stream, _server) = spawn_in_memory(SlowTestMilter, |server| server);
+
+    let client = Client::new(OptNeg::default()).with_command_timeout(TIMEOUT);
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    let err = connection
+        .recipient(&b"<bob@example.com>"[..])
+        .await
+        .expect_err("Slow recipient did not time out");
+    assert!(matches!(err, ResponseError::Timeout(TIMEOUT)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_content_timeout() -> Result<()> {
+    let (stream, _server) = spawn_in_memory(SlowTestMilter, |server| server);
+
+    let client = Client::new(OptNeg::default()).with_content_timeout(TIMEOUT);
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    let err = connection
+        .end_of_body()
+        .await
+        .expect_err("Slow end of body did not time out");
+    assert!(matches!(err, ResponseError::Timeout(TIMEOUT)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_progress_restarts_content_timeout() -> Result<()> {
+    let (stream, _server) = spawn_in_memory(SlowTestMilter, |server| {
+        server.with_progress_interval(TIMEOUT / 5)
+    });
+
+    let client = Client::new(OptNeg::default()).with_content_timeout(TIMEOUT);
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    let response = connection.end_of_body().await.into_diagnostic()?;
+    assert!(matches!(response.final_action(), Action::Continue(_)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_callback_timeout() -> Result<()> {
+    let (stream, _server) = spawn_in_memory(SlowTestMilter, |server| {
+        server.with_callback_timeout(TIMEOUT, Tempfail)
+    });
+
+    let client = Client::new(OptNeg::default());
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    let verdict = connection
+        .recipient(&b"<bob@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    assert!(matches!(verdict, Verdict::Tempfail));
+
+    let response = connection.end_of_body().await.into_diagnostic()?;
+    assert!(matches!(response.final_action(), Action::Tempfail(_)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_read_timeout() -> Result<()> {
+    let mut milter = SlowTestMilter;
+
+    let (client_end, server_end) = duplex(1024);
+    let mut server = Server::default_postfix(&mut milter).with_read_timeout(TIMEOUT);
+
+    // The client negotiates options, but stays silent afterwards
+    let client = Client::new(OptNeg::default());
+    let (connection, result) = tokio::join!(
+        client.connect_via(client_end.compat()),
+        server.handle_connection(server_end.compat()),
+    );
+
+    let _connection = connection.into_diagnostic()?;
+    assert!(matches!(result, Err(Error::ReadTimeout(TIMEOUT))));
+
+    Ok(())
+}
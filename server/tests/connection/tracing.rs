@@ -0,0 +1,144 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use miette::{IntoDiagnostic, Result};
+
+use miltr_client::Client;
+use miltr_common::{
+    actions::{Action, Continue, Reject},
+    commands::{Header, Macro, Recipient},
+    optneg::OptNeg,
+};
+use miltr_server::{Context, Milter};
+use tracing_subscriber::{filter::LevelFilter, fmt::MakeWriter, util::SubscriberInitExt};
+
+use crate::utils::spawn_in_memory;
+
+/// Rejects every message with more than one recipient
+struct TracedTestMilter {
+    recipients: usize,
+}
+
+#[async_trait]
+impl Milter for TracedTestMilter {
+    type Error = &'static str;
+
+    async fn rcpt(
+        &mut self,
+        _context: &Context,
+        _recipient: Recipient,
+    ) -> Result<Action, Self::Error> {
+        self.recipients += 1;
+        if self.recipients > 1 {
+            return Ok(Reject.into());
+        }
+        Ok(Continue.into())
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        self.recipients = 0;
+        Ok(())
+    }
+}
+
+/// Collects everything logged
+#[derive(Debug, Clone, Default)]
+struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Logs {
+    fn lines(&self) -> Vec<String> {
+        let logs = self.0.lock().expect("Logs poisoned");
+        String::from_utf8_lossy(&logs)
+            .lines()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    fn line(&self, message: &str) -> String {
+        self.lines()
+            .into_iter()
+            .find(|line| line.contains(message))
+            .unwrap_or_else(|| panic!("Nothing logged with {message:?}"))
+    }
+}
+
+impl io::Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().expect("Logs poisoned").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for Logs {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+#[tokio::test]
+async fn test_message_span() -> Result<()> {
+    let logs = Logs::default();
+    let _guard = tracing_subscriber::fmt()
+        .with_writer(logs.clone())
+        .with_ansi(false)
+        .with_max_level(LevelFilter::DEBUG)
+        .finish()
+        .set_default();
+
+    let (stream, server) = spawn_in_memory(TracedTestMilter { recipients: 0 }, |server| server);
+    let client = Client::new(OptNeg::default());
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    // Postfix sends the queue id with the macros of the recipient stage
+    connection
+        .mail(&b"<alice@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    connection
+        .macro_(Macro::new(b'R', &[(b"i", b"4Q2Xyz0Abc")]))
+        .await
+        .into_diagnostic()?;
+    connection
+        .recipient(&b"<bob@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    connection
+        .header(Header::new(b"Subject", b"Hi"))
+        .await
+        .into_diagnostic()?;
+    connection.body(&b"Hello"[..]).await.into_diagnostic()?;
+    connection.end_of_body().await.into_diagnostic()?;
+
+    // The next message is traced in a new span
+    connection.reset().await.into_diagnostic()?;
+    connection
+        .mail(&b"<carol@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    connection.quit().await.into_diagnostic()?;
+    server.await.into_diagnostic()??;
+
+    let recipient = logs.line("Received recipient");
+    assert!(recipient.contains("milter_connection:milter_message{"));
+    assert!(recipient.contains(r#"queue_id="4Q2Xyz0Abc""#));
+    assert!(recipient.contains("recipient=<bob@example.com> recipients=1"));
+
+    let handled = logs.line("Message handled");
+    assert!(handled.contains(r#"queue_id="4Q2Xyz0Abc""#));
+    assert!(handled.contains(
+        r#"sender="<alice@example.com>" recipients=1 headers=1 body_bytes=5 modifications=0 verdict=Continue"#
+    ));
+
+    let next = logs.line("sender=<carol@example.com>");
+    assert!(!next.contains("queue_id"));
+
+    Ok(())
+}
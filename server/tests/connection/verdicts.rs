@@ -0,0 +1,133 @@
+use async_trait::async_trait;
+use miette::{ErrReport, IntoDiagnostic, Result};
+
+use miltr_client::{Client, Verdict};
+use miltr_common::{
+    actions::{Action, Continue, Reject, Replycode, Skip},
+    commands::{Body, Recipient},
+    modifications::ModificationResponse,
+    optneg::{OptNeg, Protocol},
+    ProtocolError,
+};
+use miltr_server::{Context, Error, Milter};
+use tokio::sync::mpsc;
+
+use crate::utils::spawn_in_memory;
+
+/// Rejects some recipients and skips the body after the first chunk
+#[derive(Debug, Clone)]
+struct VerdictTestMilter {
+    body_chunks: usize,
+    reports: mpsc::UnboundedSender<usize>,
+}
+
+#[async_trait]
+impl Milter for VerdictTestMilter {
+    type Error = ErrReport;
+
+    async fn option_negotiation(&mut self, theirs: OptNeg) -> Result<OptNeg, Error<Self::Error>> {
+        let ours = OptNeg {
+            protocol: Protocol::SMFIP_SKIP,
+            ..Default::default()
+        };
+        Ok(ours
+            .merge_compatible(&theirs)
+            .map_err(ProtocolError::CompatibilityError)?)
+    }
+
+    async fn rcpt(&mut self, _context: &Context, recipient: Recipient) -> Result<Action> {
+        let action = match recipient.recipient().as_ref() {
+            "<rejected@example.com>" => Reject.into(),
+            "<unknown@example.com>" => Replycode::new([5, 5, 0], [5, 1, 1], "No such user").into(),
+            _ => Continue.into(),
+        };
+        Ok(action)
+    }
+
+    async fn body(&mut self, _context: &Context, _body: Body) -> Result<Action> {
+        self.body_chunks += 1;
+        Ok(Skip.into())
+    }
+
+    async fn end_of_body(&mut self, _context: &Context) -> Result<ModificationResponse> {
+        self.reports.send(self.body_chunks).into_diagnostic()?;
+        Ok(ModificationResponse::empty_continue())
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        self.body_chunks = 0;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_verdicts() -> Result<()> {
+    let (reports, _received) = mpsc::unbounded_channel();
+    let milter = VerdictTestMilter {
+        body_chunks: 0,
+        reports,
+    };
+    let (stream, _server) = spawn_in_memory(milter, |server| server);
+
+    let client = Client::new(OptNeg {
+        protocol: Protocol::SMFIP_SKIP,
+        ..Default::default()
+    });
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    let verdict = connection
+        .recipient(&b"<alice@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    assert!(verdict.is_continue());
+
+    let verdict = connection
+        .recipient(&b"<rejected@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    assert!(matches!(verdict, Verdict::Reject));
+
+    let verdict = connection
+        .recipient(&b"<unknown@example.com>"[..])
+        .await
+        .into_diagnostic()?;
+    let Verdict::Replycode(replycode) = verdict else {
+        panic!("Expected a reply code, got {verdict:?}");
+    };
+    assert_eq!(replycode.message(), "No such user");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_body_skipped() -> Result<()> {
+    let (reports, mut received) = mpsc::unbounded_channel();
+    let milter = VerdictTestMilter {
+        body_chunks: 0,
+        reports,
+    };
+    let (stream, _server) = spawn_in_memory(milter, |server| server);
+
+    let client = Client::new(OptNeg {
+        protocol: Protocol::SMFIP_SKIP,
+        ..Default::default()
+    });
+    let mut connection = client.connect_via(stream).await.into_diagnostic()?;
+
+    for chunk in ["first", "second", "third"] {
+        let verdict = connection.body(chunk.as_bytes()).await.into_diagnostic()?;
+        assert!(matches!(verdict, Verdict::Skip));
+    }
+    connection.end_of_body().await.into_diagnostic()?;
+
+    // Only the first chunk was sent
+    assert_eq!(received.recv().await, Some(1));
+
+    // A new mail sends it's body again
+    connection.reset().await.into_diagnostic()?;
+    connection.body(&b"fourth"[..]).await.into_diagnostic()?;
+    connection.end_of_body().await.into_diagnostic()?;
+    assert_eq!(received.recv().await, Some(1));
+
+    Ok(())
+}
@@ -2,7 +2,7 @@ use crate::utils::TestCase;
 use async_trait::async_trait;
 use miette::Error as ErrReport;
 use miltr_common::modifications::{body::ReplaceBody, ModificationResponse};
-use miltr_server::Milter;
+use miltr_server::{Context, Milter};
 
 #[derive(Debug, Clone)]
 struct ReplaceBodyTestMilter;
@@ -10,14 +10,17 @@ struct ReplaceBodyTestMilter;
 #[async_trait]
 impl Milter for ReplaceBodyTestMilter {
     type Error = ErrReport;
-    async fn end_of_body(&mut self) -> Result<ModificationResponse, Self::Error> {
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
         let mut builder = ModificationResponse::builder();
         builder.push(ReplaceBody::new("Replace Body\r\n".as_bytes()));
         let response = builder.contin();
         Ok(response)
     }
 
-    async fn abort(&mut self) -> Result<(), Self::Error> {
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
         Ok(())
     }
 }
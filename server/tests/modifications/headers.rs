@@ -5,7 +5,7 @@ use miltr_common::modifications::{
     headers::{AddHeader, ChangeHeader, InsertHeader},
     ModificationResponse,
 };
-use miltr_server::Milter;
+use miltr_server::{Context, Milter};
 
 #[derive(Debug, Default, Clone)]
 struct AddHeaderMilter;
@@ -13,7 +13,10 @@ struct AddHeaderMilter;
 #[async_trait]
 impl Milter for AddHeaderMilter {
     type Error = ErrReport;
-    async fn end_of_body(&mut self) -> Result<ModificationResponse, Self::Error> {
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
         let mut builder = ModificationResponse::builder();
         builder.push(AddHeader::new(
             "Test Add Header".as_bytes(),
@@ -23,7 +26,7 @@ impl Milter for AddHeaderMilter {
         Ok(response)
     }
 
-    async fn abort(&mut self) -> Result<(), Self::Error> {
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
         Ok(())
     }
 }
@@ -49,7 +52,10 @@ struct ChangeHeaderMilter;
 #[async_trait]
 impl Milter for ChangeHeaderMilter {
     type Error = ErrReport;
-    async fn end_of_body(&mut self) -> Result<ModificationResponse, Self::Error> {
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
         let mut builder = ModificationResponse::builder();
         builder.push(ChangeHeader::new(
             1,
@@ -60,7 +66,7 @@ impl Milter for ChangeHeaderMilter {
         Ok(response)
     }
 
-    async fn abort(&mut self) -> Result<(), Self::Error> {
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
         Ok(())
     }
 }
@@ -85,7 +91,10 @@ struct InsertHeaderMilter;
 #[async_trait]
 impl Milter for InsertHeaderMilter {
     type Error = ErrReport;
-    async fn end_of_body(&mut self) -> Result<ModificationResponse, Self::Error> {
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
         let mut builder = ModificationResponse::builder();
         builder.push(InsertHeader::new(
             1,
@@ -96,7 +105,7 @@ impl Milter for InsertHeaderMilter {
         Ok(response)
     }
 
-    async fn abort(&mut self) -> Result<(), Self::Error> {
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
         Ok(())
     }
 }
@@ -5,7 +5,7 @@ use miltr_common::{
     commands::Macro,
     optneg::{MacroStage, OptNeg},
 };
-use miltr_server::{Error, Milter};
+use miltr_server::{Context, Error, Milter};
 use tokio::sync::mpsc;
 
 #[derive(Debug, Clone)]
@@ -43,12 +43,12 @@ impl Milter for MacroRequestTestMilter {
         Ok(optneg)
     }
 
-    async fn macro_(&mut self, macr: Macro) -> Result<()> {
+    async fn macro_(&mut self, _context: &Context, macr: Macro) -> Result<()> {
         self.sender.send(macr).await.expect("Failed sending macro");
         Ok(())
     }
 
-    async fn abort(&mut self) -> Result<(), Self::Error> {
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
         Ok(())
     }
 }
@@ -3,3 +3,4 @@ mod headers;
 mod r#macro;
 mod quarantine;
 mod recipient;
+mod sender;
@@ -2,7 +2,7 @@ use crate::utils::TestCase;
 use async_trait::async_trait;
 use miette::Error as ErrReport;
 use miltr_common::modifications::{quarantine::Quarantine, ModificationResponse};
-use miltr_server::Milter;
+use miltr_server::{Context, Milter};
 
 /// This quarantines the message into a holding pool (/var/spool/postfix/hold) defined by the MTA.
 /// (First implemented in Sendmail in version 8.13; offered to the milter by
@@ -13,14 +13,17 @@ struct QuarantineTestMilter;
 #[async_trait]
 impl Milter for QuarantineTestMilter {
     type Error = ErrReport;
-    async fn end_of_body(&mut self) -> Result<ModificationResponse, Self::Error> {
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
         let mut builder = ModificationResponse::builder();
         builder.push(Quarantine::new("Invalid Email".as_bytes()));
         let response = builder.contin();
         Ok(response)
     }
 
-    async fn abort(&mut self) -> Result<(), Self::Error> {
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
         Ok(())
     }
 }
@@ -2,10 +2,10 @@ use crate::utils::TestCase;
 use async_trait::async_trait;
 use miette::Error as ErrReport;
 use miltr_common::modifications::{
-    recipients::{AddRecipient, DeleteRecipient},
+    recipients::{AddRecipient, AddRecipientWithArgs, DeleteRecipient},
     ModificationResponse,
 };
-use miltr_server::Milter;
+use miltr_server::{Context, Milter};
 
 ///This does not change To in Header
 #[derive(Debug, Clone)]
@@ -14,7 +14,10 @@ struct AddRcptTestMilter;
 #[async_trait]
 impl Milter for AddRcptTestMilter {
     type Error = ErrReport;
-    async fn end_of_body(&mut self) -> Result<ModificationResponse, Self::Error> {
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
         let mut builder = ModificationResponse::builder();
         builder.push(AddRecipient::new(
             "<add_rcpt-added@blackhole.com>".as_bytes(),
@@ -23,7 +26,7 @@ impl Milter for AddRcptTestMilter {
         Ok(response)
     }
 
-    async fn abort(&mut self) -> Result<(), Self::Error> {
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
         Ok(())
     }
 }
@@ -49,14 +52,17 @@ struct DeleteRcptTestMilter;
 #[async_trait]
 impl Milter for DeleteRcptTestMilter {
     type Error = ErrReport;
-    async fn end_of_body(&mut self) -> Result<ModificationResponse, Self::Error> {
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
         let mut builder = ModificationResponse::builder();
         builder.push(DeleteRecipient::new("Hei <hei@domain.tld>".as_bytes()));
         let response = builder.contin();
         Ok(response)
     }
 
-    async fn abort(&mut self) -> Result<(), Self::Error> {
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
         Ok(())
     }
 }
@@ -75,3 +81,45 @@ async fn test_delete_rcpt() {
         .await
         .expect_err("Deleting the recipient did not delete the mails");
 }
+
+///This does not change To in Header
+#[derive(Debug, Clone)]
+struct AddRcptWithArgsTestMilter;
+
+#[async_trait]
+impl Milter for AddRcptWithArgsTestMilter {
+    type Error = ErrReport;
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
+        let mut builder = ModificationResponse::builder();
+        builder.push(AddRecipientWithArgs::new(
+            "<add_rcpt_par-added@blackhole.com>".as_bytes(),
+            "NOTIFY=NEVER".as_bytes(),
+        ));
+        let response = builder.contin();
+        Ok(response)
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+#[tokio::test]
+async fn test_add_rcpt_with_args() {
+    let testcase = TestCase::setup(
+        "modifications-recipient-add-rcpt-with-args",
+        AddRcptWithArgsTestMilter,
+    )
+    .await
+    .expect("Failed setting up test case");
+
+    let response = testcase.send_mail().await.expect("Failed sending mail");
+    let testcase = testcase.stop().await.expect("Failed to shut down postfix");
+
+    testcase
+        .validate_mail("recipient: add_rcpt_par-added@blackhole.com", &response)
+        .await
+        .expect("Received mail did not contain added recipient");
+}
@@ -0,0 +1,42 @@
+use crate::utils::TestCase;
+use async_trait::async_trait;
+use miette::Error as ErrReport;
+use miltr_common::modifications::{sender::ChangeFrom, ModificationResponse};
+use miltr_server::{Context, Milter};
+
+/// This does not change From in Header
+#[derive(Debug, Clone)]
+struct ChangeFromTestMilter;
+
+#[async_trait]
+impl Milter for ChangeFromTestMilter {
+    type Error = ErrReport;
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
+        let mut builder = ModificationResponse::builder();
+        builder.push(ChangeFrom::new("<change_from@blackhole.com>".as_bytes()));
+        let response = builder.contin();
+        Ok(response)
+    }
+
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_change_from() {
+    let testcase = TestCase::setup("modifications-sender-change-from", ChangeFromTestMilter)
+        .await
+        .expect("Failed setting up test case");
+
+    let response = testcase.send_mail().await.expect("Failed sending mail");
+    let testcase = testcase.stop().await.expect("Failed to shut down postfix");
+
+    testcase
+        .validate_mail("sender: change_from@blackhole.com", &response)
+        .await
+        .expect("Received mail did not contain changed sender");
+}
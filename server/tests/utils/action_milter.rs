@@ -12,7 +12,7 @@ use tokio::net::{TcpListener, TcpStream};
 use tokio_util::compat::TokioAsyncReadCompatExt;
 
 use miltr_common::{actions::Action, modifications::ModificationResponse};
-use miltr_server::{Milter, Server};
+use miltr_server::{Context, Milter, Server};
 
 /// A milter performing a single action on `end_of_body`.
 #[derive(Clone)]
@@ -40,13 +40,16 @@ impl ActionMilter {
 impl Milter for ActionMilter {
     type Error = &'static str;
 
-    async fn end_of_body(&mut self) -> Result<ModificationResponse, Self::Error> {
+    async fn end_of_body(
+        &mut self,
+        _context: &Context,
+    ) -> Result<ModificationResponse, Self::Error> {
         println!("Body called");
         self.0.action_called.fetch_add(1, Ordering::SeqCst);
         Ok(ModificationResponse::builder().build(self.0.action.clone()))
     }
 
-    async fn abort(&mut self) -> Result<(), Self::Error> {
+    async fn abort(&mut self, _context: &Context) -> Result<(), Self::Error> {
         Ok(())
     }
 }
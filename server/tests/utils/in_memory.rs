@@ -0,0 +1,93 @@
+//! Test utils to run a milter server on an in-memory connection.
+use std::fmt::{Debug, Display};
+
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use miette::{miette, IntoDiagnostic, Result};
+use tokio::{
+    io::{duplex, DuplexStream},
+    task::JoinHandle,
+};
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+
+use miltr_server::{Milter, Server};
+
+const DUPLEX_BUFFER_SIZE: usize = 2_usize.pow(17);
+
+/// Spawn `milter` on one end of an in-memory connection.
+///
+/// The `configure` closure may adapt the default postfix server before it
+/// handles the connection. The other end of the connection is returned to
+/// be used by a client.
+pub fn spawn_in_memory<M, E, F>(
+    mut milter: M,
+    configure: F,
+) -> (Compat<DuplexStream>, JoinHandle<Result<()>>)
+where
+    E: Debug + Display + 'static,
+    M: Milter<Error = E> + 'static,
+    F: for<'m> FnOnce(Server<'m, M>) -> Server<'m, M> + Send + 'static,
+{
+    let (client_end, server_end) = duplex(DUPLEX_BUFFER_SIZE);
+
+    let handle = tokio::spawn(async move {
+        let mut server = configure(Server::default_postfix(&mut milter));
+        server
+            .handle_connection(server_end.compat())
+            .await
+            .map_err(|e| miette!("{e}"))
+    });
+
+    (client_end.compat(), handle)
+}
+
+/// Write a single raw packet with `code` and `payload`
+pub async fn write_packet<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    code: u8,
+    payload: &[u8],
+) -> Result<()> {
+    let length = u32::try_from(payload.len() + 1).into_diagnostic()?;
+
+    stream
+        .write_all(&length.to_be_bytes())
+        .await
+        .into_diagnostic()?;
+    stream.write_all(&[code]).await.into_diagnostic()?;
+    stream.write_all(payload).await.into_diagnostic()?;
+
+    Ok(())
+}
+
+/// Read a single raw packet, returning it's code and payload
+pub async fn read_packet<R: AsyncRead + Unpin>(stream: &mut R) -> Result<(u8, Vec<u8>)> {
+    let mut length = [0_u8; 4];
+    stream.read_exact(&mut length).await.into_diagnostic()?;
+
+    let mut packet = vec![0_u8; u32::from_be_bytes(length) as usize];
+    stream.read_exact(&mut packet).await.into_diagnostic()?;
+
+    let payload = packet.split_off(1);
+    Ok((packet[0], payload))
+}
+
+/// Option negotiate version 6 with the given `protocol` flags
+pub async fn negotiate_raw<RW: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut RW,
+    protocol: u32,
+) -> Result<()> {
+    let mut payload = Vec::with_capacity(12);
+    payload.extend_from_slice(&6_u32.to_be_bytes());
+    payload.extend_from_slice(&u32::MAX.to_be_bytes());
+    payload.extend_from_slice(&protocol.to_be_bytes());
+    write_packet(stream, b'O', &payload).await?;
+
+    let (code, _payload) = read_packet(stream).await?;
+    if code != b'O' {
+        return Err(miette!(
+            "Expected an option negotiation, got '{}'",
+            code as char
+        ));
+    }
+
+    Ok(())
+}
@@ -1,9 +1,11 @@
 mod action_milter;
+mod in_memory;
 mod portguard;
 mod postfix;
 mod testcase;
 
 pub use action_milter::ActionMilter;
+pub use in_memory::{negotiate_raw, read_packet, spawn_in_memory, write_packet};
 pub use testcase::TestCase;
 
 use action_milter::run_milter;
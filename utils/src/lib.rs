@@ -73,6 +73,16 @@ macro_rules! debug {
     }
 }
 
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        {
+            tracing::warn!($($arg)+);
+        }
+    }
+}
+
 #[macro_export]
 macro_rules! trace {
     ($($arg:tt)+) => {
@@ -82,3 +92,13 @@ macro_rules! trace {
         }
     }
 }
+
+#[macro_export]
+macro_rules! metric {
+    ($($arg:tt)+) => {
+        #[cfg(feature = "metrics")]
+        {
+            $($arg)+;
+        }
+    }
+}